@@ -0,0 +1,241 @@
+use syn::spanned::Spanned;
+use syn::{Attribute, Error, Expr, Ident, Lit, LitStr, Meta, Path};
+
+/// How the strings inside a field are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StringMode {
+    Path,
+    Literal,
+    Indented,
+}
+
+impl StringMode {
+    pub fn token(self) -> &'static str {
+        match self {
+            StringMode::Path => "PATH",
+            StringMode::Literal => "LITERAL",
+            StringMode::Indented => "INDENTED",
+        }
+    }
+}
+
+/// The `#[nix(...)]` attributes of a struct
+#[derive(Default)]
+pub struct Container {
+    pub rename_all: Option<RenameRule>,
+    /// Take the defaults of options from the `Default` implementation
+    pub default: bool,
+}
+
+/// The `#[nix(...)]` attributes of a field
+#[derive(Default)]
+pub struct Field {
+    pub rename: Option<String>,
+    pub skip: bool,
+    pub skip_serializing_if: Option<Path>,
+    pub strings: Option<StringMode>,
+    pub attr_path: bool,
+    pub comment: Option<String>,
+    /// Custom option type expression
+    pub ty: Option<String>,
+    /// Default value of the option
+    pub default: Option<Expr>,
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RenameRule {
+    Lower,
+    Upper,
+    Pascal,
+    Camel,
+    Snake,
+    ScreamingSnake,
+    Kebab,
+}
+
+impl RenameRule {
+    fn from_str(rule: &LitStr) -> syn::Result<Self> {
+        Ok(match rule.value().as_str() {
+            "lowercase" => RenameRule::Lower,
+            "UPPERCASE" => RenameRule::Upper,
+            "PascalCase" => RenameRule::Pascal,
+            "camelCase" => RenameRule::Camel,
+            "snake_case" => RenameRule::Snake,
+            "SCREAMING_SNAKE_CASE" => RenameRule::ScreamingSnake,
+            "kebab-case" => RenameRule::Kebab,
+            other => {
+                return Err(Error::new(
+                    rule.span(),
+                    format!("unknown rename rule `{other}`"),
+                ));
+            }
+        })
+    }
+
+    /// Renames a PascalCase variant name
+    pub fn apply_to_variant(self, variant: &str) -> String {
+        match self {
+            RenameRule::Pascal => variant.to_string(),
+            RenameRule::Lower => variant.to_ascii_lowercase(),
+            RenameRule::Upper => variant.to_ascii_uppercase(),
+            RenameRule::Camel => {
+                let mut chars = variant.chars();
+                match chars.next() {
+                    Some(first) => first.to_lowercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            }
+            RenameRule::Snake | RenameRule::ScreamingSnake | RenameRule::Kebab => {
+                let mut snake = String::with_capacity(variant.len());
+                for (i, c) in variant.char_indices() {
+                    if c.is_uppercase() && i > 0 {
+                        snake.push('_');
+                    }
+                    snake.extend(c.to_lowercase());
+                }
+                self.apply(&snake)
+            }
+        }
+    }
+
+    /// Renames a snake_case field name
+    pub fn apply(self, field: &str) -> String {
+        match self {
+            RenameRule::Lower | RenameRule::Snake => field.to_string(),
+            RenameRule::Upper | RenameRule::ScreamingSnake => field.to_ascii_uppercase(),
+            RenameRule::Kebab => field.replace('_', "-"),
+            RenameRule::Pascal | RenameRule::Camel => {
+                let mut renamed = String::with_capacity(field.len());
+                let mut capitalize = self == RenameRule::Pascal;
+                for c in field.chars() {
+                    if c == '_' {
+                        capitalize = true;
+                    } else if capitalize {
+                        renamed.push(c.to_ascii_uppercase());
+                        capitalize = false;
+                    } else {
+                        renamed.push(c);
+                    }
+                }
+                renamed
+            }
+        }
+    }
+}
+
+impl Container {
+    pub fn from_attrs(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut container = Container::default();
+
+        for attr in attrs.iter().filter(|a| a.path().is_ident("nix")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("rename_all") {
+                    container.rename_all = Some(RenameRule::from_str(&meta.value()?.parse()?)?);
+                    Ok(())
+                } else if meta.path.is_ident("default") {
+                    container.default = true;
+                    Ok(())
+                } else {
+                    Err(meta.error("unknown nix container attribute"))
+                }
+            })?;
+        }
+
+        Ok(container)
+    }
+}
+
+impl Field {
+    pub fn from_attrs(attrs: &[Attribute]) -> syn::Result<Self> {
+        let mut field = Field::default();
+
+        for attr in attrs.iter().filter(|a| a.path().is_ident("nix")) {
+            attr.parse_nested_meta(|meta| {
+                let mode = if meta.path.is_ident("path") {
+                    StringMode::Path
+                } else if meta.path.is_ident("literal") {
+                    StringMode::Literal
+                } else if meta.path.is_ident("indented") {
+                    StringMode::Indented
+                } else if meta.path.is_ident("attr_path") {
+                    field.attr_path = true;
+                    return Ok(());
+                } else if meta.path.is_ident("skip") {
+                    field.skip = true;
+                    return Ok(());
+                } else if meta.path.is_ident("rename") {
+                    field.rename = Some(meta.value()?.parse::<LitStr>()?.value());
+                    return Ok(());
+                } else if meta.path.is_ident("comment") {
+                    field.comment = Some(meta.value()?.parse::<LitStr>()?.value());
+                    return Ok(());
+                } else if meta.path.is_ident("type") {
+                    field.ty = Some(meta.value()?.parse::<LitStr>()?.value());
+                    return Ok(());
+                } else if meta.path.is_ident("default") {
+                    field.default = Some(meta.value()?.parse::<LitStr>()?.parse()?);
+                    return Ok(());
+                } else if meta.path.is_ident("description") {
+                    field.description = Some(meta.value()?.parse::<LitStr>()?.value());
+                    return Ok(());
+                } else if meta.path.is_ident("skip_serializing_if") {
+                    let path: LitStr = meta.value()?.parse()?;
+                    field.skip_serializing_if = Some(path.parse()?);
+                    return Ok(());
+                } else {
+                    return Err(meta.error("unknown nix field attribute"));
+                };
+
+                if field.strings.is_some_and(|m| m != mode) {
+                    return Err(Error::new(
+                        meta.path.span(),
+                        "only one of `path`, `literal` and `indented` can be used",
+                    ));
+                }
+                field.strings = Some(mode);
+                Ok(())
+            })?;
+        }
+
+        Ok(field)
+    }
+
+    /// Renders the attribute name of the field `ident`
+    pub fn key(&self, ident: &Ident, container: &Container) -> String {
+        let name = ident.to_string();
+        let name = name.strip_prefix("r#").unwrap_or(&name);
+        match (&self.rename, container.rename_all) {
+            (Some(rename), _) => rename.clone(),
+            (None, Some(rule)) => rule.apply(name),
+            (None, None) => name.to_string(),
+        }
+    }
+}
+
+/// Joins the lines of the doc comments of an item, as written by `///`
+pub fn doc_comment(attrs: &[Attribute]) -> Option<String> {
+    let lines: Vec<String> = attrs
+        .iter()
+        .filter(|a| a.path().is_ident("doc"))
+        .filter_map(|a| match &a.meta {
+            Meta::NameValue(nv) => match &nv.value {
+                Expr::Lit(expr) => match &expr.lit {
+                    Lit::Str(s) => Some(s.value()),
+                    _ => None,
+                },
+                _ => None,
+            },
+            _ => None,
+        })
+        .map(|line| {
+            line.strip_prefix(' ')
+                .unwrap_or(&line)
+                .trim_end()
+                .to_string()
+        })
+        .collect();
+
+    let doc = lines.join("\n").trim().to_string();
+    (!doc.is_empty()).then_some(doc)
+}
@@ -0,0 +1,169 @@
+//! # ser_nix_derive
+//!
+//! Provides `#[derive(NixSerialize)]` for [ser_nix](https://docs.rs/ser_nix).
+//! Enable the `derive` feature of ser_nix and use it as `ser_nix::NixSerialize`
+//! rather than depending on this crate directly.
+mod attr;
+mod options;
+mod serialize;
+
+use proc_macro::TokenStream;
+use syn::{DeriveInput, Error, parse_macro_input};
+
+/// Implements `serde::Serialize` for a struct with named fields, with
+/// `#[nix(...)]` attributes controlling how each field is written by ser_nix.
+///
+/// This replaces `#[derive(Serialize)]`, so the two cannot be used together.
+///
+/// # Field attributes
+///
+/// - `#[nix(path)]` writes the strings of the field as Nix paths, like
+///   [`NixPathBuf`](https://docs.rs/ser_nix/latest/ser_nix/struct.NixPathBuf.html).
+/// - `#[nix(literal)]` writes the strings of the field as raw Nix expressions, like
+///   [`NixLiteral`](https://docs.rs/ser_nix/latest/ser_nix/struct.NixLiteral.html).
+/// - `#[nix(indented)]` writes the strings of the field as indented `''` strings.
+/// - `#[nix(comment = "...")]` writes a comment above the attribute.
+/// - `#[nix(attr_path)]` writes the attributes of a struct or map field as
+///   `field.attribute = ...;` instead of a nested attribute set.
+/// - `#[nix(rename = "...")]`, `#[nix(skip)]` and
+///   `#[nix(skip_serializing_if = "...")]` behave like their serde counterparts.
+///
+/// `path`, `literal` and `indented` apply to every string inside the field,
+/// including those in an `Option`, a `Vec` or the values of a map, so no
+/// adapter is needed for each shape. Map keys are left as they are.
+///
+/// # Container attributes
+///
+/// - `#[nix(rename_all = "...")]` renames every field, with the same rules as
+///   serde (`camelCase`, `kebab-case`, ...).
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string, NixSerialize};
+/// use std::path::PathBuf;
+///
+/// #[derive(NixSerialize)]
+/// #[nix(rename_all = "camelCase")]
+/// struct Host {
+///     #[nix(path)]
+///     imports: Vec<PathBuf>,
+///     #[nix(attr_path, comment = "Set by the installer")]
+///     networking: Networking,
+///     #[nix(literal)]
+///     system_packages: Vec<String>,
+/// }
+///
+/// #[derive(NixSerialize)]
+/// #[nix(rename_all = "camelCase")]
+/// struct Networking {
+///     host_name: String,
+/// }
+///
+/// let host = Host {
+///     imports: vec![PathBuf::from("./hardware-configuration.nix")],
+///     networking: Networking { host_name: "nixos".into() },
+///     system_packages: vec!["pkgs.git".into()],
+/// };
+///
+/// let expected = concat!(
+///     "{\n",
+///     "  imports = [\n",
+///     "    ./hardware-configuration.nix\n",
+///     "  ];\n",
+///     "  # Set by the installer\n",
+///     "  networking.hostName = \"nixos\";\n",
+///     "  systemPackages = [\n",
+///     "    pkgs.git\n",
+///     "  ];\n",
+///     "}",
+/// );
+///
+/// assert_eq!(to_string(&host).unwrap(), expected);
+/// ```
+#[proc_macro_derive(NixSerialize, attributes(nix))]
+pub fn derive_nix_serialize(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    serialize::expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Implements `ser_nix::NixOptions` for a struct with named fields, declaring
+/// a NixOS option for every field, and `ser_nix::NixType` as a
+/// `lib.types.submodule` of those options so it can be nested in other structs.
+///
+/// On an enum without fields, implements `ser_nix::NixType` as a
+/// `lib.types.enum` of the variant names instead.
+///
+/// The type of each option comes from the `NixType` implementation of the
+/// field's type, and its description from the field's doc comment.
+///
+/// # Field attributes
+///
+/// - `#[nix(default = "...")]` sets the default to a Rust expression, written
+///   with the configuration the options are serialized with.
+/// - `#[nix(description = "...")]` replaces the doc comment as description.
+/// - `#[nix(type = "...")]` replaces the type with a Nix expression, such as
+///   `lib.types.port`.
+/// - `#[nix(rename = "...")]` and `#[nix(skip)]` apply as for
+///   [`NixSerialize`](derive@NixSerialize), also on enum variants.
+///
+/// # Container attributes
+///
+/// - `#[nix(default)]` takes the default of every field from the `Default`
+///   implementation of the struct.
+/// - `#[nix(rename_all = "...")]` applies as for
+///   [`NixSerialize`](derive@NixSerialize), also on enums.
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string, NixOptions};
+/// use serde::Serialize;
+///
+/// #[derive(Default, Serialize, NixOptions)]
+/// #[nix(default, rename_all = "camelCase")]
+/// struct Service {
+///     /// Whether to start the service.
+///     enable: bool,
+///     log_level: LogLevel,
+/// }
+///
+/// #[derive(Default, Serialize, NixOptions)]
+/// #[serde(rename_all = "lowercase")]
+/// #[nix(rename_all = "lowercase")]
+/// enum LogLevel {
+///     #[default]
+///     Info,
+///     Debug,
+/// }
+///
+/// let expected = concat!(
+///     "{\n",
+///     "  enable = lib.mkOption {\n",
+///     "    type = lib.types.bool;\n",
+///     "    default = false;\n",
+///     "    description = \"Whether to start the service.\";\n",
+///     "  };\n",
+///     "  logLevel = lib.mkOption {\n",
+///     "    type = lib.types.enum [\n",
+///     "      \"info\"\n",
+///     "      \"debug\"\n",
+///     "    ];\n",
+///     "    default = \"info\";\n",
+///     "  };\n",
+///     "}",
+/// );
+///
+/// assert_eq!(to_string(&Service::nix_options()).unwrap(), expected);
+/// ```
+#[proc_macro_derive(NixOptions, attributes(nix))]
+pub fn derive_nix_options(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    options::expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
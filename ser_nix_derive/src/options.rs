@@ -0,0 +1,124 @@
+use crate::attr::{Container, Field, doc_comment};
+use proc_macro2::TokenStream;
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Fields, Ident, parse_quote};
+
+pub fn expand(mut input: DeriveInput) -> syn::Result<TokenStream> {
+    let container = Container::from_attrs(&input.attrs)?;
+
+    let type_params: Vec<Ident> = input
+        .generics
+        .type_params()
+        .map(|p| p.ident.clone())
+        .collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in type_params {
+        where_clause
+            .predicates
+            .push(parse_quote!(#param: ::ser_nix::NixType));
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    match &input.data {
+        Data::Struct(data) => {
+            let Fields::Named(fields) = &data.fields else {
+                return Err(unsupported_options(ident));
+            };
+
+            let default = container
+                .default
+                .then(|| quote!(let __default = <Self as ::core::default::Default>::default();));
+
+            let mut statements = Vec::new();
+            for field in &fields.named {
+                let attrs = Field::from_attrs(&field.attrs)?;
+                if attrs.skip {
+                    continue;
+                }
+
+                let field_ident = field.ident.as_ref().expect("named fields have names");
+                let key = attrs.key(field_ident, &container);
+
+                let field_ty = &field.ty;
+                let mut option = match &attrs.ty {
+                    Some(expr) => quote! {
+                        ::ser_nix::NixOption::new(::ser_nix::NixOptionType::Custom(#expr.into()))
+                    },
+                    None => quote! {
+                        ::ser_nix::NixOption::new(<#field_ty as ::ser_nix::NixType>::nix_type())
+                    },
+                };
+                match (&attrs.default, container.default) {
+                    (Some(expr), _) => option = quote!(#option.default(&(#expr))),
+                    (None, true) => option = quote!(#option.default(&__default.#field_ident)),
+                    (None, false) => {}
+                }
+                if let Some(description) = attrs.description.or_else(|| doc_comment(&field.attrs)) {
+                    option = quote!(#option.description(#description));
+                }
+
+                statements.push(quote!(__options.push(#key, #option);));
+            }
+
+            Ok(quote! {
+                impl #impl_generics ::ser_nix::NixOptions for #ident #ty_generics #where_clause {
+                    fn nix_options() -> ::ser_nix::NixOptionSet {
+                        #default
+                        let mut __options = ::ser_nix::NixOptionSet::new();
+                        #(#statements)*
+                        __options
+                    }
+                }
+
+                impl #impl_generics ::ser_nix::NixType for #ident #ty_generics #where_clause {
+                    fn nix_type() -> ::ser_nix::NixOptionType {
+                        ::ser_nix::NixOptionType::Submodule(
+                            <Self as ::ser_nix::NixOptions>::nix_options(),
+                        )
+                    }
+                }
+            })
+        }
+        Data::Enum(data) => {
+            let mut values = Vec::new();
+            for variant in &data.variants {
+                if !matches!(variant.fields, Fields::Unit) {
+                    return Err(Error::new_spanned(
+                        variant,
+                        "`NixOptions` only supports enums without fields",
+                    ));
+                }
+                let attrs = Field::from_attrs(&variant.attrs)?;
+                if attrs.skip {
+                    continue;
+                }
+                let name = variant.ident.to_string();
+                values.push(match (attrs.rename, container.rename_all) {
+                    (Some(rename), _) => rename,
+                    (None, Some(rule)) => rule.apply_to_variant(&name),
+                    (None, None) => name,
+                });
+            }
+
+            Ok(quote! {
+                impl #impl_generics ::ser_nix::NixType for #ident #ty_generics #where_clause {
+                    fn nix_type() -> ::ser_nix::NixOptionType {
+                        ::ser_nix::NixOptionType::Enum(
+                            ::std::vec![#(::ser_nix::Value::String(::std::string::String::from(#values))),*],
+                        )
+                    }
+                }
+            })
+        }
+        Data::Union(_) => Err(unsupported_options(ident)),
+    }
+}
+
+fn unsupported_options(ident: &Ident) -> Error {
+    Error::new(
+        ident.span(),
+        "`NixOptions` only supports structs with named fields and enums without fields",
+    )
+}
@@ -0,0 +1,93 @@
+use crate::attr::{Container, Field};
+use proc_macro2::{Span, TokenStream};
+use quote::quote;
+use syn::{Data, DeriveInput, Error, Fields, Ident, parse_quote};
+
+pub fn expand(mut input: DeriveInput) -> syn::Result<TokenStream> {
+    let container = Container::from_attrs(&input.attrs)?;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(unsupported(&input.ident)),
+        },
+        _ => return Err(unsupported(&input.ident)),
+    };
+
+    let mut statements = Vec::new();
+    let mut len = 0usize;
+
+    for field in fields {
+        let attrs = Field::from_attrs(&field.attrs)?;
+        if attrs.skip {
+            continue;
+        }
+        len += 1;
+
+        let ident = field.ident.as_ref().expect("named fields have names");
+        let key = attrs.key(ident, &container);
+
+        let mut value = quote!(&self.#ident);
+        if let Some(mode) = attrs.strings {
+            let token = Ident::new(mode.token(), Span::call_site());
+            value = quote!(&::ser_nix::__private::Mode::new(::ser_nix::__private::#token, #value));
+        }
+        if attrs.attr_path {
+            value =
+                quote!(&::ser_nix::__private::Mode::new(::ser_nix::__private::ATTR_PATH, #value));
+        }
+        if let Some(comment) = &attrs.comment {
+            value = quote!(&::ser_nix::NixCommented::new(#comment, #value));
+        }
+
+        let serialize = quote!(__state.serialize_field(#key, #value)?;);
+        statements.push(match &attrs.skip_serializing_if {
+            Some(predicate) => quote! {
+                if #predicate(&self.#ident) {
+                    __state.skip_field(#key)?;
+                } else {
+                    #serialize
+                }
+            },
+            None => serialize,
+        });
+    }
+
+    let type_params: Vec<Ident> = input
+        .generics
+        .type_params()
+        .map(|p| p.ident.clone())
+        .collect();
+    let where_clause = input.generics.make_where_clause();
+    for param in type_params {
+        where_clause
+            .predicates
+            .push(parse_quote!(#param: ::ser_nix::__private::serde::Serialize));
+    }
+
+    let ident = &input.ident;
+    let name = ident.to_string();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+
+    Ok(quote! {
+        impl #impl_generics ::ser_nix::__private::serde::Serialize for #ident #ty_generics #where_clause {
+            fn serialize<__S>(&self, __serializer: __S) -> ::core::result::Result<__S::Ok, __S::Error>
+            where
+                __S: ::ser_nix::__private::serde::Serializer,
+            {
+                use ::ser_nix::__private::serde::ser::SerializeStruct as _;
+
+                let mut __state = __serializer.serialize_struct(#name, #len)?;
+                #(#statements)*
+                __state.end()
+            }
+        }
+    })
+}
+
+fn unsupported(ident: &Ident) -> Error {
+    Error::new(
+        ident.span(),
+        "`NixSerialize` only supports structs with named fields",
+    )
+}
@@ -0,0 +1,115 @@
+use crate::token::Token;
+use crate::unpack::Payload;
+use serde::{Serialize, Serializer};
+
+/// Applies the Nix function `function` to `arg`, written as `function arg`.
+///
+/// The argument is parenthesized when it is not a single term. The function
+/// is usually a [`Lib`] function, or another application to pass several
+/// arguments.
+pub(crate) struct Apply<'a, F: ?Sized, T: ?Sized> {
+    pub function: &'a F,
+    pub arg: &'a T,
+}
+
+impl<F, T> Serialize for Apply<'_, F, T>
+where
+    F: ?Sized + Serialize,
+    T: ?Sized + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(
+            Token::Unpack(Payload::Apply).name(),
+            &(self.function, self.arg),
+        )
+    }
+}
+
+/// A member of nixpkgs' `lib`, such as `mkForce` or `types.str`, written
+/// through the identifier set with [`Config::lib`](crate::Config::lib).
+pub(crate) struct Lib<'a>(pub &'a str);
+
+impl Serialize for Lib<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(Token::Lib.name(), self.0)
+    }
+}
+
+/// A value written as a single term, parenthesized if necessary, so it can be
+/// an element of a list.
+pub(crate) struct Term<'a, T: ?Sized>(pub &'a T);
+
+impl<T> Serialize for Term<'_, T>
+where
+    T: ?Sized + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(Token::Term.name(), self.0)
+    }
+}
+
+/// Whether a rendered expression must be parenthesized to be used as the
+/// argument of a function application.
+///
+/// Anything that is not a single term, such as an application, an operator, a
+/// negation or a negative number, is parenthesized. Whitespace inside brackets and strings
+/// is ignored.
+pub(crate) fn needs_parens(arg: &str) -> bool {
+    let arg = arg.trim();
+    if arg.starts_with(['-', '!']) {
+        return true;
+    }
+
+    let mut depth = 0usize;
+    let mut chars = arg.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => {
+                            chars.next();
+                        }
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            '\'' if chars.peek() == Some(&'\'') => {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    if c == '\'' && chars.peek() == Some(&'\'') {
+                        chars.next();
+                        // `'''`, `''$` and `''\` are escapes inside the string
+                        match chars.peek() {
+                            Some('\'') | Some('$') => {
+                                chars.next();
+                            }
+                            Some('\\') => {
+                                chars.next();
+                                chars.next();
+                            }
+                            _ => break,
+                        }
+                    }
+                }
+            }
+            c if c.is_whitespace() && depth == 0 => return true,
+            _ => {}
+        }
+    }
+
+    false
+}
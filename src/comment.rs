@@ -0,0 +1,108 @@
+use crate::token::Token;
+use crate::unpack::Payload;
+use serde::{Serialize, Serializer};
+
+/// How the text of a comment is written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CommentStyle {
+    /// Every line of the comment starts with `#`.
+    #[default]
+    Line,
+    /// The comment is wrapped in `/*` and `*/`.
+    Block,
+}
+
+/// A comment waiting to be written above the attribute it belongs to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Comment {
+    pub text: String,
+    pub style: CommentStyle,
+}
+
+/// A value with a comment written above the attribute it is the value of.
+///
+/// Comments spanning several lines are written as several `#` lines, or as a
+/// single `/* */` comment with [`NixCommented::block`]. Serialization fails
+/// for comments on values that are not written as the value of an attribute
+/// on its own line, such as list elements or the top-level value.
+///
+/// # Example
+///
+/// ```
+/// use serde::Serialize;
+/// use ser_nix::{to_string, NixCommented};
+///
+/// #[derive(Serialize)]
+/// struct Config {
+///     port: NixCommented<u16>,
+/// }
+///
+/// let config = Config {
+///     port: NixCommented::new("Keep in sync with the firewall", 8080),
+/// };
+///
+/// let result = to_string(&config).unwrap();
+/// assert_eq!(
+///     result,
+///     "{\n  # Keep in sync with the firewall\n  port = 8080;\n}"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NixCommented<T> {
+    comment: String,
+    style: CommentStyle,
+    value: T,
+}
+
+impl<T> NixCommented<T> {
+    /// Creates a value with a `#` comment.
+    pub fn new(comment: impl Into<String>, value: T) -> Self {
+        NixCommented {
+            comment: comment.into(),
+            style: CommentStyle::Line,
+            value,
+        }
+    }
+
+    /// Creates a value with a `/* */` comment.
+    ///
+    /// Serialization fails if the comment contains `*/`.
+    pub fn block(comment: impl Into<String>, value: T) -> Self {
+        NixCommented {
+            comment: comment.into(),
+            style: CommentStyle::Block,
+            value,
+        }
+    }
+
+    /// Returns the text of the comment.
+    pub fn comment(&self) -> &str {
+        &self.comment
+    }
+
+    /// Returns how the comment is written.
+    pub fn style(&self) -> CommentStyle {
+        self.style
+    }
+
+    /// Returns a reference to the commented value.
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+
+    /// Converts into the commented value.
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+}
+
+impl<T: Serialize> Serialize for NixCommented<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let token = Token::Unpack(Payload::Comment(self.style));
+        serializer.serialize_newtype_struct(token.name(), &(self.comment.as_str(), &self.value))
+    }
+}
@@ -0,0 +1,126 @@
+use crate::apply::{Apply, Lib, Term};
+use crate::literal::NixLiteral;
+use crate::options::{NixOptionType, NixType};
+use serde::{Serialize, Serializer};
+
+/// A definition that only applies when `condition` is true, written as
+/// `lib.mkIf condition value`.
+///
+/// The condition is parenthesized when it is not a single term.
+///
+/// # Example
+///
+/// ```
+/// use serde::Serialize;
+/// use ser_nix::{to_string, MkIf, NixLiteral};
+///
+/// #[derive(Serialize)]
+/// struct Nginx {
+///     enable: bool,
+/// }
+///
+/// let nginx = MkIf {
+///     condition: NixLiteral::from("cfg.enable && cfg.web"),
+///     value: Nginx { enable: true },
+/// };
+///
+/// let result = to_string(&nginx).unwrap();
+/// assert_eq!(
+///     result,
+///     "lib.mkIf (cfg.enable && cfg.web) {\n  enable = true;\n}"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MkIf<T> {
+    pub condition: NixLiteral<'static>,
+    pub value: T,
+}
+
+impl<T> MkIf<T> {
+    /// Creates a definition guarded by `condition`.
+    pub fn new(condition: impl Into<NixLiteral<'static>>, value: T) -> Self {
+        MkIf {
+            condition: condition.into(),
+            value,
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for MkIf<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let function = Apply {
+            function: &Lib("mkIf"),
+            arg: &self.condition,
+        };
+        Apply {
+            function: &function,
+            arg: &self.value,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<T: NixType> NixType for MkIf<T> {
+    fn nix_type() -> NixOptionType {
+        T::nix_type()
+    }
+}
+
+/// Several definitions merged into one, written as `lib.mkMerge [ ... ]`.
+///
+/// Elements that are not a single term, such as [`MkIf`]s, are parenthesized.
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string, MkIf, MkMerge};
+/// use std::collections::BTreeMap;
+///
+/// let base = BTreeMap::from([("enable", true)]);
+/// let debug = BTreeMap::from([("verbose", true)]);
+///
+/// let merged = MkMerge(vec![
+///     MkIf::new("true", base),
+///     MkIf::new("cfg.debug", debug),
+/// ]);
+///
+/// let result = to_string(&merged).unwrap();
+/// assert_eq!(
+///     result,
+///     concat!(
+///         "lib.mkMerge [\n",
+///         "  (lib.mkIf true {\n",
+///         "    enable = true;\n",
+///         "  })\n",
+///         "  (lib.mkIf cfg.debug {\n",
+///         "    verbose = true;\n",
+///         "  })\n",
+///         "]",
+///     )
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct MkMerge<T>(pub Vec<T>);
+
+impl<T: Serialize> Serialize for MkMerge<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let definitions: Vec<_> = self.0.iter().map(Term).collect();
+        Apply {
+            function: &Lib("mkMerge"),
+            arg: &definitions,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<T: NixType> NixType for MkMerge<T> {
+    fn nix_type() -> NixOptionType {
+        T::nix_type()
+    }
+}
@@ -0,0 +1,301 @@
+use super::format::{DefaultFormatter, Formatter, NixfmtFormatter, ToPrettyFormatter};
+
+use std::sync::Arc;
+
+/// Options controlling the layout of serialized Nix.
+///
+/// The default configuration produces the same output as [`to_string`](crate::to_string).
+/// Presets such as [`Config::nixfmt`] adjust the layout to match other tools,
+/// and [`Config::formatter`] accepts any custom [`Formatter`].
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string_with_config, Config};
+///
+/// let empty: Vec<u8> = Vec::new();
+///
+/// let result = to_string_with_config(&empty, &Config::nixfmt()).unwrap();
+/// assert_eq!(result, "[ ]\n");
+/// ```
+#[derive(Clone)]
+pub struct Config {
+    pub(crate) formatter: Arc<dyn Formatter + Send + Sync>,
+    pub(crate) none: NonePolicy,
+    pub(crate) unit_structs_as_attrsets: bool,
+    pub(crate) omit_empty: bool,
+    pub(crate) header: Option<String>,
+    pub(crate) lib: String,
+    pub(crate) share_repeated: Option<usize>,
+    pub(crate) with_common_prefix: bool,
+    pub(crate) strict_literals: bool,
+}
+
+/// How `None` values are written when they are the value of an attribute.
+///
+/// `None` values inside lists are always written as `null`, since leaving them
+/// out would change the length of the list.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum NonePolicy {
+    /// Write the attribute with a `null` value.
+    #[default]
+    Null,
+    /// Leave the attribute out entirely, as if the field was skipped with
+    /// `#[serde(skip_serializing_if = "Option::is_none")]`.
+    Omit,
+    /// Write the attribute as a commented-out line such as `# hostName = null;`,
+    /// in the style of `nixos-generate-config`.
+    ///
+    /// Fields serialized with [`as_hinted_option`](crate::as_hinted_option)
+    /// are followed by a hint of their type.
+    Placeholder,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            formatter: Arc::new(DefaultFormatter),
+            none: NonePolicy::default(),
+            unit_structs_as_attrsets: false,
+            omit_empty: false,
+            header: None,
+            lib: String::from("lib"),
+            share_repeated: None,
+            with_common_prefix: false,
+            strict_literals: false,
+        }
+    }
+}
+
+impl std::fmt::Debug for Config {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Config")
+            .field("none", &self.none)
+            .field("unit_structs_as_attrsets", &self.unit_structs_as_attrsets)
+            .field("omit_empty", &self.omit_empty)
+            .field("header", &self.header)
+            .field("lib", &self.lib)
+            .field("share_repeated", &self.share_repeated)
+            .field("with_common_prefix", &self.with_common_prefix)
+            .field("strict_literals", &self.strict_literals)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Config {
+    /// Creates the default configuration.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Output that is left unchanged by [nixfmt](https://github.com/NixOS/nixfmt).
+    ///
+    /// See [`NixfmtFormatter`] for details.
+    pub fn nixfmt() -> Self {
+        Self::new().formatter(NixfmtFormatter)
+    }
+
+    /// Output matching nixpkgs' `lib.generators.toPretty { }`.
+    ///
+    /// See [`ToPrettyFormatter`] for details.
+    pub fn to_pretty() -> Self {
+        Self::new().formatter(ToPrettyFormatter)
+    }
+
+    /// Lays out the output with a custom [`Formatter`].
+    pub fn formatter<F>(mut self, formatter: F) -> Self
+    where
+        F: Formatter + Send + Sync + 'static,
+    {
+        self.formatter = Arc::new(formatter);
+        self
+    }
+
+    /// Sets how `None` attribute values are written.
+    ///
+    /// This applies to every struct field and map entry, including those of
+    /// third-party types that cannot be annotated with `skip_serializing_if`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use serde::Serialize;
+    /// use ser_nix::{to_string_with_config, Config, NonePolicy};
+    ///
+    /// #[derive(Serialize)]
+    /// struct Service {
+    ///     enable: bool,
+    ///     user: Option<String>,
+    /// }
+    ///
+    /// let service = Service { enable: true, user: None };
+    ///
+    /// let config = Config::new().none_policy(NonePolicy::Omit);
+    /// let result = to_string_with_config(&service, &config).unwrap();
+    /// assert_eq!(result, "{\n  enable = true;\n}");
+    /// ```
+    pub fn none_policy(mut self, policy: NonePolicy) -> Self {
+        self.none = policy;
+        self
+    }
+
+    /// Writes unit structs as an empty attribute set instead of `null`.
+    pub fn unit_structs_as_attrsets(mut self, enabled: bool) -> Self {
+        self.unit_structs_as_attrsets = enabled;
+        self
+    }
+
+    /// Leaves out attributes whose value is an empty list or attribute set.
+    ///
+    /// An attribute set that becomes empty because all of its attributes were
+    /// left out is itself left out as well.
+    pub fn omit_empty_collections(mut self, enabled: bool) -> Self {
+        self.omit_empty = enabled;
+        self
+    }
+
+    /// Writes `text` as a `#` comment at the top of the output, such as a
+    /// notice that the file is generated.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ser_nix::{to_string_with_config, Config};
+    ///
+    /// let config = Config::new().header("Generated by nixgen, do not edit.");
+    /// let result = to_string_with_config(&true, &config).unwrap();
+    /// assert_eq!(result, "# Generated by nixgen, do not edit.\ntrue");
+    /// ```
+    pub fn header(mut self, text: impl Into<String>) -> Self {
+        self.header = Some(text.into());
+        self
+    }
+
+    /// Sets the identifier nixpkgs' `lib` is available as, which is `lib` by
+    /// default.
+    ///
+    /// This applies to everything written through `lib`, such as
+    /// [`MkForce`](crate::MkForce) and [`NixOption`](crate::NixOption), but
+    /// not to [`NixLiteral`](crate::NixLiteral)s.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ser_nix::{to_string_with_config, Config, MkForce};
+    ///
+    /// let config = Config::new().lib("pkgs.lib");
+    /// let result = to_string_with_config(&MkForce(true), &config).unwrap();
+    /// assert_eq!(result, "pkgs.lib.mkForce true");
+    /// ```
+    pub fn lib(mut self, identifier: impl Into<String>) -> Self {
+        self.lib = identifier.into();
+        self
+    }
+
+    /// Binds values spanning at least `min_lines` lines that are serialized
+    /// several times in a `let` enclosing the output, and refers to them by
+    /// name, such as `shared1`. Values are compared by what they serialize,
+    /// so a [`NixLiteral`](crate::NixLiteral) is never shared with a value
+    /// that happens to be written as the same text.
+    ///
+    /// The bindings are placed after the arguments of a top-level
+    /// [`NixModule`](crate::NixModule). Values inside functions, `let`, `rec`
+    /// and `with` expressions are not shared, since they may refer to the
+    /// arguments or bindings in scope.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ser_nix::{to_string_with_config, Config};
+    /// use std::collections::BTreeMap;
+    ///
+    /// let tls = BTreeMap::from([("enable", true), ("acme", true)]);
+    /// let hosts = BTreeMap::from([("a", &tls), ("b", &tls)]);
+    ///
+    /// let config = Config::new().share_repeated(3);
+    /// let result = to_string_with_config(&hosts, &config).unwrap();
+    ///
+    /// let expected = concat!(
+    ///     "let\n",
+    ///     "  shared1 = {\n",
+    ///     "    acme = true;\n",
+    ///     "    enable = true;\n",
+    ///     "  };\n",
+    ///     "in\n",
+    ///     "{\n",
+    ///     "  a = shared1;\n",
+    ///     "  b = shared1;\n",
+    ///     "}",
+    /// );
+    ///
+    /// assert_eq!(result, expected);
+    /// ```
+    pub fn share_repeated(mut self, min_lines: usize) -> Self {
+        self.share_repeated = Some(min_lines);
+        self
+    }
+
+    /// Writes lists of [`NixLiteral`](crate::NixLiteral)s that all select
+    /// from the same attribute path as a `with` expression, such as
+    /// `with pkgs; [ git curl ]`.
+    ///
+    /// Only lists of at least two literals that are all attribute paths, such
+    /// as `pkgs.git`, are rewritten. Variables bound around the list take
+    /// precedence over the attributes brought into scope by `with`, so lists
+    /// inside a [`NixLet`](crate::NixLet), [`NixRec`](crate::NixRec),
+    /// [`NixLambda`](crate::NixLambda) or [`NixModule`](crate::NixModule) are
+    /// left alone, as are lists that would name a global such as `map`.
+    /// Variables bound by expressions written as literals are not known, so
+    /// the elements should not have their names.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ser_nix::{to_string_with_config, Config, NixLiteral};
+    ///
+    /// let packages = vec![NixLiteral::new("pkgs.git"), NixLiteral::new("pkgs.jq")];
+    ///
+    /// let config = Config::new().with_common_prefix(true);
+    /// let result = to_string_with_config(&packages, &config).unwrap();
+    /// assert_eq!(result, "with pkgs; [\n  git\n  jq\n]");
+    /// ```
+    pub fn with_common_prefix(mut self, enabled: bool) -> Self {
+        self.with_common_prefix = enabled;
+        self
+    }
+
+    /// Checks that every [`NixLiteral`](crate::NixLiteral) is a single
+    /// complete Nix expression, failing serialization otherwise.
+    ///
+    /// The error names the attribute holding the literal and the column of
+    /// the mistake. Literals can also be checked when they are created, with
+    /// [`NixLiteral::parse`](crate::NixLiteral::parse).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use serde::Serialize;
+    /// use ser_nix::{to_string_with_config, Config, NixLiteral};
+    ///
+    /// #[derive(Serialize)]
+    /// struct Service {
+    ///     package: NixLiteral<'static>,
+    /// }
+    ///
+    /// let service = Service {
+    ///     package: NixLiteral::new("pkgs.hello;"),
+    /// };
+    ///
+    /// let config = Config::new().strict_literals(true);
+    /// let error = to_string_with_config(&service, &config).unwrap_err();
+    /// assert_eq!(
+    ///     error.to_string(),
+    ///     "invalid Nix literal `pkgs.hello;` in `package`: unexpected `;` at column 11"
+    /// );
+    /// ```
+    pub fn strict_literals(mut self, enabled: bool) -> Self {
+        self.strict_literals = enabled;
+        self
+    }
+}
@@ -0,0 +1,524 @@
+use crate::error::Error;
+use serde::ser::{
+    self, Serialize, SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant,
+    SerializeTuple, SerializeTupleStruct, SerializeTupleVariant, Serializer,
+};
+
+/// A value recorded from its `Serialize` implementation, so it can be written
+/// later by the serializer of the value it is part of, with the layout of
+/// its configuration.
+///
+/// Floats are kept as their bits, so that recorded values can be compared,
+/// ordered and hashed.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Content {
+    Bool(bool),
+    I64(i64),
+    I128(i128),
+    U64(u64),
+    U128(u128),
+    F32(u32),
+    F64(u64),
+    Char(char),
+    Str(String),
+    Bytes(Vec<u8>),
+    None,
+    Some(Box<Content>),
+    Unit,
+    UnitStruct(&'static str),
+    UnitVariant(&'static str, u32, &'static str),
+    NewtypeStruct(&'static str, Box<Content>),
+    NewtypeVariant(&'static str, u32, &'static str, Box<Content>),
+    Seq(Vec<Content>),
+    Tuple(Vec<Content>),
+    TupleStruct(&'static str, Vec<Content>),
+    TupleVariant(&'static str, u32, &'static str, Vec<Content>),
+    Map(Vec<(Content, Content)>),
+    Struct(&'static str, Vec<(&'static str, Content)>),
+    StructVariant(
+        &'static str,
+        u32,
+        &'static str,
+        Vec<(&'static str, Content)>,
+    ),
+}
+
+impl Content {
+    /// Records `value`, or the message of the error serializing it failed
+    /// with
+    pub fn record<T>(value: &T) -> Result<Content, String>
+    where
+        T: ?Sized + Serialize,
+    {
+        value.serialize(Recorder).map_err(|e| e.to_string())
+    }
+}
+
+impl Serialize for Content {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Content::Bool(v) => serializer.serialize_bool(*v),
+            Content::I64(v) => serializer.serialize_i64(*v),
+            Content::I128(v) => serializer.serialize_i128(*v),
+            Content::U64(v) => serializer.serialize_u64(*v),
+            Content::U128(v) => serializer.serialize_u128(*v),
+            Content::F32(bits) => serializer.serialize_f32(f32::from_bits(*bits)),
+            Content::F64(bits) => serializer.serialize_f64(f64::from_bits(*bits)),
+            Content::Char(v) => serializer.serialize_char(*v),
+            Content::Str(v) => serializer.serialize_str(v),
+            Content::Bytes(v) => serializer.serialize_bytes(v),
+            Content::None => serializer.serialize_none(),
+            Content::Some(value) => serializer.serialize_some(&**value),
+            Content::Unit => serializer.serialize_unit(),
+            Content::UnitStruct(name) => serializer.serialize_unit_struct(name),
+            Content::UnitVariant(name, index, variant) => {
+                serializer.serialize_unit_variant(name, *index, variant)
+            }
+            Content::NewtypeStruct(name, value) => {
+                serializer.serialize_newtype_struct(name, &**value)
+            }
+            Content::NewtypeVariant(name, index, variant, value) => {
+                serializer.serialize_newtype_variant(name, *index, variant, &**value)
+            }
+            Content::Seq(elements) => {
+                let mut seq = serializer.serialize_seq(Some(elements.len()))?;
+                for element in elements {
+                    seq.serialize_element(element)?;
+                }
+                seq.end()
+            }
+            Content::Tuple(elements) => {
+                let mut tuple = serializer.serialize_tuple(elements.len())?;
+                for element in elements {
+                    tuple.serialize_element(element)?;
+                }
+                tuple.end()
+            }
+            Content::TupleStruct(name, fields) => {
+                let mut tuple = serializer.serialize_tuple_struct(name, fields.len())?;
+                for field in fields {
+                    tuple.serialize_field(field)?;
+                }
+                tuple.end()
+            }
+            Content::TupleVariant(name, index, variant, fields) => {
+                let mut tuple =
+                    serializer.serialize_tuple_variant(name, *index, variant, fields.len())?;
+                for field in fields {
+                    tuple.serialize_field(field)?;
+                }
+                tuple.end()
+            }
+            Content::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            Content::Struct(name, fields) => {
+                let mut s = serializer.serialize_struct(name, fields.len())?;
+                for (key, value) in fields {
+                    s.serialize_field(key, value)?;
+                }
+                s.end()
+            }
+            Content::StructVariant(name, index, variant, fields) => {
+                let mut s =
+                    serializer.serialize_struct_variant(name, *index, variant, fields.len())?;
+                for (key, value) in fields {
+                    s.serialize_field(key, value)?;
+                }
+                s.end()
+            }
+        }
+    }
+}
+
+/// Records the value serialized into it as [`Content`]
+struct Recorder;
+
+/// The elements of a sequence, tuple or tuple struct being recorded
+struct Elements {
+    kind: ElementsKind,
+    elements: Vec<Content>,
+}
+
+enum ElementsKind {
+    Seq,
+    Tuple,
+    TupleStruct(&'static str),
+    TupleVariant(&'static str, u32, &'static str),
+}
+
+/// The fields of a struct or struct variant being recorded
+struct Fields {
+    name: &'static str,
+    variant: Option<(u32, &'static str)>,
+    fields: Vec<(&'static str, Content)>,
+}
+
+/// The entries of a map being recorded
+struct Entries {
+    entries: Vec<(Content, Content)>,
+    key: Option<Content>,
+}
+
+impl Serializer for Recorder {
+    type Ok = Content;
+    type Error = Error;
+    type SerializeSeq = Elements;
+    type SerializeTuple = Elements;
+    type SerializeTupleStruct = Elements;
+    type SerializeTupleVariant = Elements;
+    type SerializeMap = Entries;
+    type SerializeStruct = Fields;
+    type SerializeStructVariant = Fields;
+
+    fn serialize_bool(self, v: bool) -> Result<Content, Error> {
+        Ok(Content::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Content, Error> {
+        Ok(Content::I64(v.into()))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Content, Error> {
+        Ok(Content::I64(v.into()))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Content, Error> {
+        Ok(Content::I64(v.into()))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Content, Error> {
+        Ok(Content::I64(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Content, Error> {
+        Ok(Content::I128(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Content, Error> {
+        Ok(Content::U64(v.into()))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Content, Error> {
+        Ok(Content::U64(v.into()))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Content, Error> {
+        Ok(Content::U64(v.into()))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Content, Error> {
+        Ok(Content::U64(v))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Content, Error> {
+        Ok(Content::U128(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Content, Error> {
+        Ok(Content::F32(v.to_bits()))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Content, Error> {
+        Ok(Content::F64(v.to_bits()))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Content, Error> {
+        Ok(Content::Char(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Content, Error> {
+        Ok(Content::Str(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Content, Error> {
+        Ok(Content::Bytes(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Content, Error> {
+        Ok(Content::None)
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<Content, Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        Ok(Content::Some(Box::new(value.serialize(Recorder)?)))
+    }
+
+    fn serialize_unit(self) -> Result<Content, Error> {
+        Ok(Content::Unit)
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Content, Error> {
+        Ok(Content::UnitStruct(name))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> Result<Content, Error> {
+        Ok(Content::UnitVariant(name, index, variant))
+    }
+
+    fn serialize_newtype_struct<T>(self, name: &'static str, value: &T) -> Result<Content, Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let value = value.serialize(Recorder)?;
+        Ok(Content::NewtypeStruct(name, Box::new(value)))
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Content, Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let value = value.serialize(Recorder)?;
+        Ok(Content::NewtypeVariant(
+            name,
+            index,
+            variant,
+            Box::new(value),
+        ))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Elements, Error> {
+        Ok(Elements::new(ElementsKind::Seq, len.unwrap_or(0)))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Elements, Error> {
+        Ok(Elements::new(ElementsKind::Tuple, len))
+    }
+
+    fn serialize_tuple_struct(self, name: &'static str, len: usize) -> Result<Elements, Error> {
+        Ok(Elements::new(ElementsKind::TupleStruct(name), len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Elements, Error> {
+        let kind = ElementsKind::TupleVariant(name, index, variant);
+        Ok(Elements::new(kind, len))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Entries, Error> {
+        Ok(Entries {
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, name: &'static str, len: usize) -> Result<Fields, Error> {
+        Ok(Fields::new(name, None, len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Fields, Error> {
+        Ok(Fields::new(name, Some((index, variant)), len))
+    }
+}
+
+impl Elements {
+    fn new(kind: ElementsKind, len: usize) -> Self {
+        Elements {
+            kind,
+            elements: Vec::with_capacity(len),
+        }
+    }
+
+    fn push<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.elements.push(value.serialize(Recorder)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Content, Error> {
+        let elements = self.elements;
+        Ok(match self.kind {
+            ElementsKind::Seq => Content::Seq(elements),
+            ElementsKind::Tuple => Content::Tuple(elements),
+            ElementsKind::TupleStruct(name) => Content::TupleStruct(name, elements),
+            ElementsKind::TupleVariant(name, index, variant) => {
+                Content::TupleVariant(name, index, variant, elements)
+            }
+        })
+    }
+}
+
+impl SerializeSeq for Elements {
+    type Ok = Content;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Content, Error> {
+        self.finish()
+    }
+}
+
+impl SerializeTuple for Elements {
+    type Ok = Content;
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Content, Error> {
+        self.finish()
+    }
+}
+
+impl SerializeTupleStruct for Elements {
+    type Ok = Content;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Content, Error> {
+        self.finish()
+    }
+}
+
+impl SerializeTupleVariant for Elements {
+    type Ok = Content;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Content, Error> {
+        self.finish()
+    }
+}
+
+impl SerializeMap for Entries {
+    type Ok = Content;
+    type Error = Error;
+
+    fn serialize_key<T>(&mut self, key: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.key = Some(key.serialize(Recorder)?);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| ser::Error::custom("map value without a key"))?;
+        self.entries.push((key, value.serialize(Recorder)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Content, Error> {
+        Ok(Content::Map(self.entries))
+    }
+}
+
+impl Fields {
+    fn new(name: &'static str, variant: Option<(u32, &'static str)>, len: usize) -> Self {
+        Fields {
+            name,
+            variant,
+            fields: Vec::with_capacity(len),
+        }
+    }
+
+    fn push<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.fields.push((key, value.serialize(Recorder)?));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Content, Error> {
+        Ok(match self.variant {
+            None => Content::Struct(self.name, self.fields),
+            Some((index, variant)) => {
+                Content::StructVariant(self.name, index, variant, self.fields)
+            }
+        })
+    }
+}
+
+impl SerializeStruct for Fields {
+    type Ok = Content;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(key, value)
+    }
+
+    fn end(self) -> Result<Content, Error> {
+        self.finish()
+    }
+}
+
+impl SerializeStructVariant for Fields {
+    type Ok = Content;
+    type Error = Error;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.push(key, value)
+    }
+
+    fn end(self) -> Result<Content, Error> {
+        self.finish()
+    }
+}
@@ -0,0 +1,415 @@
+use crate::syntax::{AttrName, Binding, Kind, Node, StrPart, SyntaxError, unescape_indented};
+use std::ops::Range;
+
+/// A value read from Nix source, with the range of the source it was read
+/// from
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Data {
+    pub kind: DataKind,
+    pub span: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum DataKind {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// A path, written as in the source
+    Path(String),
+    List(Vec<Data>),
+    Attrs(Vec<Attr>),
+    /// An expression that is not data, written as in the source
+    Literal(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Attr {
+    pub name: String,
+    pub name_span: Range<usize>,
+    pub value: Data,
+}
+
+/// Reads the data of `node` parsed from `src`, replacing the variables bound
+/// by `let` and `rec` with their values.
+pub(crate) fn read(src: &str, node: &Node) -> Result<Data, SyntaxError> {
+    let mut reader = Reader {
+        src,
+        frames: Vec::new(),
+        active: Vec::new(),
+    };
+    reader.read(node, None)
+}
+
+/// A binding, with its path after the first attribute name
+#[derive(Clone, Copy)]
+enum Entry<'n> {
+    Value {
+        rest: &'n [AttrName],
+        value: &'n Node,
+    },
+    Inherit {
+        from: Option<&'n Node>,
+    },
+}
+
+/// The bindings of the same attribute name, with the names they are bound by
+type Group<'n> = (&'n str, Vec<(&'n AttrName, Entry<'n>)>);
+
+fn entries(bindings: &[Binding]) -> impl Iterator<Item = (&AttrName, Entry<'_>)> {
+    bindings.iter().flat_map(|binding| match binding {
+        Binding::Attr { path, value } => vec![(
+            &path[0],
+            Entry::Value {
+                rest: &path[1..],
+                value,
+            },
+        )],
+        Binding::Inherit { from, names } => names
+            .iter()
+            .map(|name| {
+                (
+                    name,
+                    Entry::Inherit {
+                        from: from.as_ref(),
+                    },
+                )
+            })
+            .collect(),
+    })
+}
+
+/// Groups bindings by their first attribute name, or returns `None` if one of
+/// the names is dynamic
+fn group<'n>(
+    entries: impl IntoIterator<Item = (&'n AttrName, Entry<'n>)>,
+) -> Option<Vec<Group<'n>>> {
+    let mut groups: Vec<Group> = Vec::new();
+    for (attr_name, entry) in entries {
+        let AttrName::Static { name, .. } = attr_name else {
+            return None;
+        };
+        match groups.iter_mut().find(|(other, _)| other == name) {
+            Some((_, group)) => group.push((attr_name, entry)),
+            None => groups.push((name, vec![(attr_name, entry)])),
+        }
+    }
+    Some(groups)
+}
+
+/// The variables bound by a `let` or a recursive attrset
+struct Frame<'n> {
+    parent: Option<usize>,
+    groups: Vec<Group<'n>>,
+}
+
+struct Reader<'s, 'n> {
+    src: &'s str,
+    frames: Vec<Frame<'n>>,
+    /// Variables whose values are being read, to detect infinite recursion
+    active: Vec<(usize, &'n str)>,
+}
+
+impl<'n> Reader<'_, 'n> {
+    /// Reads `node` with the variables of `scope`, the index of the innermost
+    /// frame around it
+    fn read(&mut self, node: &'n Node, scope: Option<usize>) -> Result<Data, SyntaxError> {
+        let text = &self.src[node.span.clone()];
+        let kind = match &node.kind {
+            Kind::Int(i) => DataKind::Int(*i),
+            Kind::Float(f) => DataKind::Float(*f),
+            Kind::Ident(name) => return self.variable(name, node.span.clone(), scope),
+            Kind::String { parts, indented } => {
+                let mut literal = String::new();
+                for part in parts {
+                    match part {
+                        StrPart::Literal(text) => literal += text,
+                        StrPart::Interpolation(_) => return Ok(self.literal(node)),
+                    }
+                }
+                match indented {
+                    // The indentation is found in the source, where an
+                    // escaped newline or space is not whitespace
+                    true => {
+                        let body = &text[2..text.len() - 2];
+                        DataKind::String(unescape_indented(&strip_indentation(body)))
+                    }
+                    false => DataKind::String(literal),
+                }
+            }
+            // Neither search paths nor paths in the home directory are
+            // paths of the machine reading them
+            Kind::Path if text.starts_with(['<', '~']) => DataKind::Literal(text.to_string()),
+            Kind::Path => DataKind::Path(text.to_string()),
+            Kind::Uri => DataKind::String(text.to_string()),
+            Kind::List(elements) => DataKind::List(
+                elements
+                    .iter()
+                    .map(|element| self.read(element, scope))
+                    .collect::<Result<_, _>>()?,
+            ),
+            Kind::Attrs { rec, bindings } => {
+                let Some(groups) = group(entries(bindings)) else {
+                    return Ok(self.literal(node));
+                };
+                let values = match rec {
+                    true => Some(self.push_frame(scope, groups.clone())),
+                    false => scope,
+                };
+                match self.attrs(&groups, values, scope)? {
+                    Some(attrs) => DataKind::Attrs(attrs),
+                    None => return Ok(self.literal(node)),
+                }
+            }
+            Kind::Let { bindings, body } => {
+                let Some(groups) = group(entries(bindings)) else {
+                    return Ok(self.literal(node));
+                };
+                let frame = self.push_frame(scope, groups);
+                return self.read(body, Some(frame));
+            }
+            Kind::Paren(inner) => return self.read(inner, scope),
+            Kind::Select {
+                expr,
+                path,
+                default,
+            } => {
+                let mut data = self.read(expr, scope)?;
+                for attr_name in path {
+                    let (AttrName::Static { name, span }, DataKind::Attrs(attrs)) =
+                        (attr_name, data.kind)
+                    else {
+                        return Ok(self.literal(node));
+                    };
+                    data = match attrs.into_iter().find(|attr| attr.name == *name) {
+                        Some(attr) => attr.value,
+                        None => match default {
+                            Some(default) => return self.read(default, scope),
+                            None => {
+                                let msg = format!("attribute `{name}` is missing");
+                                return Err(SyntaxError::new(self.src, span.start, msg));
+                            }
+                        },
+                    };
+                }
+                return Ok(data);
+            }
+            // A path with characters that cannot be written in a path
+            // literal is written as `./. + "/a b"`
+            Kind::Add(lhs, rhs) if matches!(lhs.kind, Kind::Path) => {
+                let path = self.read(lhs, scope)?;
+                let suffix = match &rhs.kind {
+                    Kind::String { .. } => self.read(rhs, scope)?,
+                    _ => return Ok(self.literal(node)),
+                };
+                match (path.kind, suffix.kind) {
+                    (DataKind::Path(path), DataKind::String(suffix)) if suffix.starts_with('/') => {
+                        DataKind::Path(join_path(&path, &suffix))
+                    }
+                    _ => return Ok(self.literal(node)),
+                }
+            }
+            Kind::Add(..) | Kind::Other => return Ok(self.literal(node)),
+        };
+        Ok(Data {
+            kind,
+            span: node.span.clone(),
+        })
+    }
+
+    /// Keeps `node` as an expression, with the indentation of its lines
+    /// after the first removed
+    fn literal(&self, node: &Node) -> Data {
+        let mut expr = String::new();
+        crate::literal::reindent_into(&self.src[node.span.clone()], "", &mut expr);
+        Data {
+            kind: DataKind::Literal(expr),
+            span: node.span.clone(),
+        }
+    }
+
+    fn push_frame(&mut self, parent: Option<usize>, groups: Vec<Group<'n>>) -> usize {
+        self.frames.push(Frame { parent, groups });
+        self.frames.len() - 1
+    }
+
+    /// Reads the variable `name`, which is kept as a literal unless it is
+    /// bound in `scope` or is a constant
+    fn variable(
+        &mut self,
+        name: &str,
+        span: Range<usize>,
+        scope: Option<usize>,
+    ) -> Result<Data, SyntaxError> {
+        let mut frame = scope;
+        while let Some(index) = frame {
+            let groups = &self.frames[index].groups;
+            if let Some((name, entries)) = groups.iter().find(|(other, _)| *other == name) {
+                let (name, entries) = (*name, entries.clone());
+                if self.active.contains(&(index, name)) {
+                    let msg = format!("infinite recursion in the value of `{name}`");
+                    return Err(SyntaxError::new(self.src, span.start, msg));
+                }
+
+                self.active.push((index, name));
+                let parent = self.frames[index].parent;
+                let value = self.group(name, &entries, Some(index), parent);
+                self.active.pop();
+                if let Some(value) = value? {
+                    return Ok(value);
+                }
+                break;
+            }
+            frame = self.frames[index].parent;
+        }
+
+        let kind = match name {
+            "true" => DataKind::Bool(true),
+            "false" => DataKind::Bool(false),
+            "null" => DataKind::Null,
+            name => DataKind::Literal(name.to_string()),
+        };
+        Ok(Data { kind, span })
+    }
+
+    /// Reads the attributes of `groups`, whose values are read in the scope
+    /// `values` and whose inherited attributes are taken from `inherits`, or
+    /// returns `None` if one of the names is dynamic
+    fn attrs(
+        &mut self,
+        groups: &[Group<'n>],
+        values: Option<usize>,
+        inherits: Option<usize>,
+    ) -> Result<Option<Vec<Attr>>, SyntaxError> {
+        let mut attrs = Vec::with_capacity(groups.len());
+        for (name, entries) in groups {
+            let Some(value) = self.group(name, entries, values, inherits)? else {
+                return Ok(None);
+            };
+            attrs.push(Attr {
+                name: name.to_string(),
+                name_span: entries[0].0.span(),
+                value,
+            });
+        }
+        Ok(Some(attrs))
+    }
+
+    /// Reads the value of the attribute `name` from its bindings, merging
+    /// those of nested attributes such as `a.b = 1; a.c = 2;`
+    fn group(
+        &mut self,
+        name: &'n str,
+        entries: &[(&'n AttrName, Entry<'n>)],
+        values: Option<usize>,
+        inherits: Option<usize>,
+    ) -> Result<Option<Data>, SyntaxError> {
+        match entries {
+            [(_, Entry::Value { rest: [], value })] => {
+                return self.read(value, values).map(Some);
+            }
+            [(attr_name, Entry::Inherit { from })] => {
+                let span = attr_name.span();
+                let Some(from) = from else {
+                    return self.variable(name, span, inherits).map(Some);
+                };
+                let set = self.read(from, values)?;
+                return match set.kind {
+                    DataKind::Attrs(attrs) => match attrs.into_iter().find(|a| a.name == name) {
+                        Some(attr) => Ok(Some(attr.value)),
+                        None => {
+                            let msg = format!("attribute `{name}` is missing");
+                            Err(SyntaxError::new(self.src, span.start, msg))
+                        }
+                    },
+                    _ => {
+                        let from = &self.src[from.span.clone()];
+                        let literal = match from.contains(char::is_whitespace) {
+                            true => format!("({from}).{name}"),
+                            false => format!("{from}.{name}"),
+                        };
+                        Ok(Some(Data {
+                            kind: DataKind::Literal(literal),
+                            span,
+                        }))
+                    }
+                };
+            }
+            _ => {}
+        }
+
+        let mut nested = Vec::new();
+        for (attr_name, entry) in entries {
+            match entry {
+                Entry::Value {
+                    rest: [first, rest @ ..],
+                    value,
+                } => nested.push((first, Entry::Value { rest, value })),
+                Entry::Value {
+                    rest: [],
+                    value:
+                        Node {
+                            kind:
+                                Kind::Attrs {
+                                    rec: false,
+                                    bindings,
+                                },
+                            ..
+                        },
+                } => nested.extend(crate::data::entries(bindings)),
+                _ => {
+                    let msg = format!("attribute `{name}` is already defined");
+                    let span = match entries[0].0 == *attr_name {
+                        true => entries[1].0.span(),
+                        false => attr_name.span(),
+                    };
+                    return Err(SyntaxError::new(self.src, span.start, msg));
+                }
+            }
+        }
+        let Some(groups) = group(nested) else {
+            return Ok(None);
+        };
+        let span = entries[0].0.span();
+        Ok(self.attrs(&groups, values, inherits)?.map(|attrs| Data {
+            kind: DataKind::Attrs(attrs),
+            span,
+        }))
+    }
+}
+
+/// Removes the indentation common to the lines of an indented string, its
+/// first line if it is blank and the spaces before its closing quotes, like
+/// Nix does
+fn strip_indentation(text: &str) -> String {
+    let spaces = |line: &str| line.len() - line.trim_start_matches(' ').len();
+    let blank = |line: &str| spaces(line) == line.len();
+    let indent = text
+        .split('\n')
+        .filter(|line| !blank(line))
+        .map(spaces)
+        .min()
+        .unwrap_or(0);
+
+    let mut lines: Vec<&str> = text
+        .split('\n')
+        .map(|line| &line[spaces(line).min(indent)..])
+        .collect();
+    if lines.len() > 1 && blank(lines[0]) {
+        lines.remove(0);
+    }
+    if let Some(last) = lines.last_mut()
+        && blank(last)
+    {
+        *last = "";
+    }
+    lines.join("\n")
+}
+
+/// Appends `suffix`, which starts with a `/`, to `path`, turning
+/// `./. + "/a b"` into `./a b`
+fn join_path(path: &str, suffix: &str) -> String {
+    let base = path.strip_suffix("/.").unwrap_or(path);
+    format!("{base}{suffix}")
+}
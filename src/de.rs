@@ -0,0 +1,936 @@
+use crate::data::{Data, DataKind};
+use crate::error::Error;
+use crate::literal::NixLiteral;
+use crate::ser::variant_key;
+use crate::syntax::SyntaxError;
+use crate::token::Token;
+use crate::value::Value;
+use serde::de::value::{MapDeserializer, SeqDeserializer};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, Unexpected,
+    VariantAccess, Visitor,
+};
+use std::borrow::Cow;
+use std::fmt;
+
+impl de::Error for Error {
+    fn custom<T>(msg: T) -> Self
+    where
+        T: fmt::Display,
+    {
+        Error::Message(msg.to_string())
+    }
+}
+
+/// Deserializes an instance of `T` from Nix text.
+///
+/// Attrsets, lists, strings, numbers, booleans, `null` and paths are read as
+/// data, and the variables bound by `let` and recursive attrsets are replaced
+/// with their values, as with [`parse`](crate::parse). Other expressions can
+/// only be read into a [`NixLiteral`](crate::NixLiteral), which also accepts
+/// any value as the text it was written with.
+///
+/// Structs and maps are read from attrsets, sequences and tuples from lists,
+/// paths into [`PathBuf`](std::path::PathBuf) or
+/// [`NixPathBuf`](crate::NixPathBuf), and enums from the names of their unit
+/// variants or from attrsets with a single attribute named after the variant,
+/// as they are serialized.
+///
+/// # Errors
+///
+/// Fails with the line and column of the value at fault if `s` is not valid
+/// Nix or does not match the layout of `T`.
+///
+/// # Example
+///
+/// ```
+/// use serde::Deserialize;
+/// use ser_nix::{from_str, NixLiteral};
+///
+/// #[derive(Debug, Deserialize)]
+/// struct Service {
+///     port: u16,
+///     package: NixLiteral<'static>,
+/// }
+///
+/// let service: Service = from_str("{ port = 80; package = pkgs.nginx; }").unwrap();
+/// assert_eq!(service.port, 80);
+/// assert_eq!(service.package.as_str(), "pkgs.nginx");
+///
+/// let error = from_str::<Service>("{\n  port = \"80\";\n}").unwrap_err();
+/// assert_eq!(
+///     error.to_string(),
+///     "invalid type: string \"80\", expected u16 at line 2, column 10"
+/// );
+/// ```
+pub fn from_str<T>(s: &str) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    let node = crate::syntax::parse(s).map_err(<Error as de::Error>::custom)?;
+    let data = crate::data::read(s, &node).map_err(<Error as de::Error>::custom)?;
+    T::deserialize(DataDeserializer { src: s, data }).map_err(|e| {
+        let msg = match e.offset {
+            Some(offset) => SyntaxError::new(s, offset, e.message).to_string(),
+            None => e.message,
+        };
+        <Error as de::Error>::custom(msg)
+    })
+}
+
+/// An error while deserializing, with the offset of the value it is about
+/// once known
+#[derive(Debug)]
+pub(crate) struct DeError {
+    message: String,
+    offset: Option<usize>,
+}
+
+impl fmt::Display for DeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for DeError {}
+
+impl de::Error for DeError {
+    fn custom<T>(msg: T) -> Self
+    where
+        T: fmt::Display,
+    {
+        DeError {
+            message: msg.to_string(),
+            offset: None,
+        }
+    }
+}
+
+/// Attributes errors that are not about a more deeply nested value to the
+/// value at `offset`
+fn locate<T>(result: Result<T, DeError>, offset: usize) -> Result<T, DeError> {
+    result.map_err(|mut e| {
+        e.offset.get_or_insert(offset);
+        e
+    })
+}
+
+struct DataDeserializer<'s> {
+    src: &'s str,
+    data: Data,
+}
+
+impl DataDeserializer<'_> {
+    fn unexpected(&self) -> Unexpected<'_> {
+        match &self.data.kind {
+            DataKind::Null => Unexpected::Unit,
+            DataKind::Bool(b) => Unexpected::Bool(*b),
+            DataKind::Int(i) => Unexpected::Signed(*i),
+            DataKind::Float(f) => Unexpected::Float(*f),
+            DataKind::String(s) => Unexpected::Str(s),
+            DataKind::Path(_) => Unexpected::Other("path"),
+            DataKind::List(_) => Unexpected::Other("list"),
+            DataKind::Attrs(_) => Unexpected::Other("attribute set"),
+            DataKind::Literal(_) => Unexpected::Other("expression"),
+        }
+    }
+}
+
+impl<'de> de::Deserializer<'de> for DataDeserializer<'_> {
+    type Error = DeError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        let src = self.src;
+        let offset = self.data.span.start;
+        let result = match self.data.kind {
+            DataKind::Null => visitor.visit_unit(),
+            DataKind::Bool(b) => visitor.visit_bool(b),
+            DataKind::Int(i) => visitor.visit_i64(i),
+            DataKind::Float(f) => visitor.visit_f64(f),
+            DataKind::String(s) | DataKind::Path(s) => visitor.visit_string(s),
+            DataKind::List(elements) => {
+                let mut seq = SeqDeserializer::new(
+                    elements
+                        .into_iter()
+                        .map(|data| DataDeserializer { src, data }),
+                );
+                visitor
+                    .visit_seq(&mut seq)
+                    .and_then(|value| seq.end().map(|()| value))
+            }
+            DataKind::Attrs(attrs) => {
+                let mut map = MapDeserializer::new(attrs.into_iter().map(|attr| {
+                    let key = KeyDeserializer {
+                        name: attr.name,
+                        offset: attr.name_span.start,
+                    };
+                    (
+                        key,
+                        DataDeserializer {
+                            src,
+                            data: attr.value,
+                        },
+                    )
+                }));
+                visitor
+                    .visit_map(&mut map)
+                    .and_then(|value| map.end().map(|()| value))
+            }
+            DataKind::Literal(expr) => Err(de::Error::custom(format!(
+                "expected data, found the expression `{expr}`"
+            ))),
+        };
+        locate(result, offset)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        match self.data.kind {
+            DataKind::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        // Unit structs can be written as empty attrsets
+        match &self.data.kind {
+            DataKind::Attrs(attrs) if attrs.is_empty() => visitor.visit_unit(),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        let offset = self.data.span.start;
+        if name == Token::Literal.name() {
+            let expr = match self.data.kind {
+                DataKind::Literal(expr) => expr,
+                // Any value is read as the text it was written with
+                _ => {
+                    let mut expr = String::new();
+                    crate::literal::reindent_into(&self.src[self.data.span], "", &mut expr);
+                    expr
+                }
+            };
+            return locate(visitor.visit_string(expr), offset);
+        }
+        if name == Token::Path.name() {
+            return match self.data.kind {
+                DataKind::Path(path) | DataKind::String(path) => {
+                    locate(visitor.visit_string(path), offset)
+                }
+                _ => Err(DeError {
+                    offset: Some(offset),
+                    ..de::Error::invalid_type(self.unexpected(), &"a path")
+                }),
+            };
+        }
+        locate(visitor.visit_newtype_struct(self), offset)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        let src = self.src;
+        let offset = self.data.span.start;
+        let result = match self.data.kind {
+            DataKind::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            DataKind::Attrs(mut attrs) if attrs.len() == 1 => {
+                let attr = attrs.remove(0);
+                // Variants are written with their first letter lowercased
+                let name = match variants.contains(&attr.name.as_str()) {
+                    true => attr.name,
+                    false => variants
+                        .iter()
+                        .find(|variant| variant_key(variant) == attr.name)
+                        .map_or(attr.name, |variant| variant.to_string()),
+                };
+                visitor.visit_enum(Enum {
+                    variant: KeyDeserializer {
+                        name,
+                        offset: attr.name_span.start,
+                    },
+                    value: DataDeserializer {
+                        src,
+                        data: attr.value,
+                    },
+                })
+            }
+            _ => Err(de::Error::invalid_type(
+                self.unexpected(),
+                &"a string or an attribute set with a single attribute",
+            )),
+        };
+        locate(result, offset)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit seq tuple tuple_struct map struct identifier
+    }
+}
+
+impl<'de, 's> IntoDeserializer<'de, DeError> for DataDeserializer<'s> {
+    type Deserializer = DataDeserializer<'s>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+/// The name of an attribute, which can be read as a number for the keys of
+/// maps such as `HashMap<u32, T>`
+struct KeyDeserializer {
+    name: String,
+    offset: usize,
+}
+
+macro_rules! deserialize_parsed_key {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value, DeError>
+            where
+                V: Visitor<'de>,
+            {
+                match self.name.parse() {
+                    Ok(n) => locate(visitor.$visit(n), self.offset),
+                    Err(_) => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for KeyDeserializer {
+    type Error = DeError;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        locate(visitor.visit_string(self.name), self.offset)
+    }
+
+    deserialize_parsed_key! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        let offset = self.offset;
+        locate(visitor.visit_enum(self.name.into_deserializer()), offset)
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 f32 f64 char str string bytes byte_buf unit unit_struct seq
+        tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, DeError> for KeyDeserializer {
+    type Deserializer = KeyDeserializer;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+/// A variant written as an attrset with a single attribute
+struct Enum<'s> {
+    variant: KeyDeserializer,
+    value: DataDeserializer<'s>,
+}
+
+impl<'de, 's> EnumAccess<'de> for Enum<'s> {
+    type Error = DeError;
+    type Variant = DataDeserializer<'s>;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), DeError>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, self.value))
+    }
+}
+
+impl<'de> VariantAccess<'de> for DataDeserializer<'_> {
+    type Error = DeError;
+
+    fn unit_variant(self) -> Result<(), DeError> {
+        de::Deserialize::deserialize(self)
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, DeError>
+    where
+        T: DeserializeSeed<'de>,
+    {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, DeError>
+    where
+        V: Visitor<'de>,
+    {
+        de::Deserializer::deserialize_map(self, visitor)
+    }
+}
+
+/// Deserializes an instance of `T` from a [`Value`].
+///
+/// Values are read like the Nix text they stand for with
+/// [`from_str`]: paths are read as strings, into
+/// [`PathBuf`](std::path::PathBuf) or into [`NixPathBuf`](crate::NixPathBuf),
+/// and a [`Value::Literal`] can only be read into a
+/// [`NixLiteral`](crate::NixLiteral), which also accepts any other value as
+/// the Nix it is written as.
+///
+/// # Errors
+///
+/// Fails if `value` does not match the layout of `T`.
+///
+/// # Example
+///
+/// ```
+/// use serde::Deserialize;
+/// use ser_nix::{from_value, parse, Value};
+///
+/// #[derive(Debug, Deserialize)]
+/// struct Service {
+///     port: u16,
+/// }
+///
+/// let mut value = parse("{ port = 80; }").unwrap();
+/// let service: Service = from_value(value.clone()).unwrap();
+/// assert_eq!(service.port, 80);
+///
+/// if let Value::Attrs(attrs) = &mut value {
+///     attrs.insert("port".to_string(), Value::Int(-1));
+/// }
+/// let error = from_value::<Service>(value).unwrap_err();
+/// assert_eq!(error.to_string(), "invalid value: integer `-1`, expected u16");
+/// ```
+pub fn from_value<T>(value: Value) -> Result<T, Error>
+where
+    T: DeserializeOwned,
+{
+    T::deserialize(value)
+}
+
+impl Value {
+    fn unexpected(&self) -> Unexpected<'_> {
+        match self {
+            Value::Null => Unexpected::Unit,
+            Value::Bool(b) => Unexpected::Bool(*b),
+            Value::Int(i) => Unexpected::Signed(*i),
+            Value::Float(f) => Unexpected::Float(*f),
+            Value::String(s) => Unexpected::Str(s),
+            Value::Path(_) => Unexpected::Other("path"),
+            Value::List(_) => Unexpected::Other("list"),
+            Value::Attrs(_) => Unexpected::Other("attribute set"),
+            Value::Literal(_) => Unexpected::Other("expression"),
+        }
+    }
+}
+
+fn found_expression(expr: &NixLiteral) -> Error {
+    de::Error::custom(format!(
+        "expected data, found the expression `{}`",
+        expr.as_str()
+    ))
+}
+
+fn expected_enum(value: &Value) -> Error {
+    de::Error::invalid_type(
+        value.unexpected(),
+        &"a string or an attribute set with a single attribute",
+    )
+}
+
+/// Finds the variant written as the attribute `name`
+fn variant_name<'a>(name: &'a str, variants: &'static [&'static str]) -> Cow<'a, str> {
+    match variants.contains(&name) {
+        true => Cow::Borrowed(name),
+        false => variants
+            .iter()
+            .find(|variant| variant_key(variant) == name)
+            .map_or(Cow::Borrowed(name), |variant| Cow::Borrowed(*variant)),
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Value {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(b),
+            Value::Int(i) => visitor.visit_i64(i),
+            Value::Float(f) => visitor.visit_f64(f),
+            Value::String(s) => visitor.visit_string(s),
+            Value::Path(path) => visitor.visit_string(path.as_path().to_string_lossy().into()),
+            Value::List(elements) => {
+                let mut seq = SeqDeserializer::new(elements.into_iter());
+                visitor
+                    .visit_seq(&mut seq)
+                    .and_then(|value| seq.end().map(|()| value))
+            }
+            Value::Attrs(attrs) => {
+                let mut map = MapDeserializer::new(
+                    attrs
+                        .into_iter()
+                        .map(|(name, value)| (ValueKey(Cow::Owned(name)), value)),
+                );
+                visitor
+                    .visit_map(&mut map)
+                    .and_then(|value| map.end().map(|()| value))
+            }
+            Value::Literal(expr) => Err(found_expression(&expr)),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match &self {
+            Value::Attrs(attrs) if attrs.is_empty() => visitor.visit_unit(),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        if name == Token::Literal.name() {
+            return match self {
+                Value::Literal(expr) => visitor.visit_string(expr.into_string()),
+                // Any value is read as the Nix it is written as
+                value => visitor.visit_string(crate::to_string(&value)?),
+            };
+        }
+        if name == Token::Path.name() {
+            return match self {
+                Value::Path(path) => visitor.visit_string(path.as_path().to_string_lossy().into()),
+                Value::String(path) => visitor.visit_string(path),
+                value => Err(de::Error::invalid_type(value.unexpected(), &"a path")),
+            };
+        }
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::String(variant) => visitor.visit_enum(variant.into_deserializer()),
+            Value::Attrs(attrs) if attrs.len() == 1 => {
+                let (name, value) = attrs.into_iter().next().unwrap();
+                let name = variant_name(&name, variants).into_owned();
+                visitor.visit_enum(ValueEnum {
+                    variant: ValueKey(Cow::Owned(name)),
+                    value,
+                })
+            }
+            value => Err(expected_enum(&value)),
+        }
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit seq tuple tuple_struct map struct identifier
+    }
+}
+
+impl<'de> de::Deserializer<'de> for &'de Value {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Null => visitor.visit_unit(),
+            Value::Bool(b) => visitor.visit_bool(*b),
+            Value::Int(i) => visitor.visit_i64(*i),
+            Value::Float(f) => visitor.visit_f64(*f),
+            Value::String(s) => visitor.visit_borrowed_str(s),
+            Value::Path(path) => match path.as_path().to_str() {
+                Some(path) => visitor.visit_borrowed_str(path),
+                None => visitor.visit_string(path.as_path().to_string_lossy().into()),
+            },
+            Value::List(elements) => {
+                let mut seq = SeqDeserializer::new(elements.iter());
+                visitor
+                    .visit_seq(&mut seq)
+                    .and_then(|value| seq.end().map(|()| value))
+            }
+            Value::Attrs(attrs) => {
+                let mut map = MapDeserializer::new(
+                    attrs
+                        .iter()
+                        .map(|(name, value)| (ValueKey(Cow::Borrowed(name.as_str())), value)),
+                );
+                visitor
+                    .visit_map(&mut map)
+                    .and_then(|value| map.end().map(|()| value))
+            }
+            Value::Literal(expr) => Err(found_expression(expr)),
+        }
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::Attrs(attrs) if attrs.is_empty() => visitor.visit_unit(),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        if name == Token::Literal.name() {
+            return match self {
+                Value::Literal(expr) => visitor.visit_borrowed_str(expr.as_str()),
+                // Any value is read as the Nix it is written as
+                value => visitor.visit_string(crate::to_string(value)?),
+            };
+        }
+        if name == Token::Path.name() {
+            return match self {
+                Value::Path(_) | Value::String(_) => self.deserialize_any(visitor),
+                value => Err(de::Error::invalid_type(value.unexpected(), &"a path")),
+            };
+        }
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self {
+            Value::String(variant) => visitor.visit_enum(variant.as_str().into_deserializer()),
+            Value::Attrs(attrs) if attrs.len() == 1 => {
+                let (name, value) = attrs.iter().next().unwrap();
+                visitor.visit_enum(ValueEnum {
+                    variant: ValueKey(variant_name(name, variants)),
+                    value,
+                })
+            }
+            value => Err(expected_enum(value)),
+        }
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit seq tuple tuple_struct map struct identifier
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for Value {
+    type Deserializer = Value;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for &'de Value {
+    type Deserializer = &'de Value;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+/// The name of an attribute of a [`Value`], which can be read as a number
+/// for the keys of maps such as `HashMap<u32, T>`
+struct ValueKey<'de>(Cow<'de, str>);
+
+macro_rules! deserialize_parsed_value_key {
+    ($($method:ident => $visit:ident,)*) => {
+        $(
+            fn $method<V>(self, visitor: V) -> Result<V::Value, Error>
+            where
+                V: Visitor<'de>,
+            {
+                match self.0.parse() {
+                    Ok(n) => visitor.$visit(n),
+                    Err(_) => self.deserialize_any(visitor),
+                }
+            }
+        )*
+    };
+}
+
+impl<'de> de::Deserializer<'de> for ValueKey<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Cow::Borrowed(name) => visitor.visit_borrowed_str(name),
+            Cow::Owned(name) => visitor.visit_string(name),
+        }
+    }
+
+    deserialize_parsed_value_key! {
+        deserialize_bool => visit_bool,
+        deserialize_i8 => visit_i8,
+        deserialize_i16 => visit_i16,
+        deserialize_i32 => visit_i32,
+        deserialize_i64 => visit_i64,
+        deserialize_u8 => visit_u8,
+        deserialize_u16 => visit_u16,
+        deserialize_u32 => visit_u32,
+        deserialize_u64 => visit_u64,
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_enum<V>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Error>
+    where
+        V: Visitor<'de>,
+    {
+        match self.0 {
+            Cow::Borrowed(name) => visitor.visit_enum(name.into_deserializer()),
+            Cow::Owned(name) => visitor.visit_enum(name.into_deserializer()),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 f32 f64 char str string bytes byte_buf unit unit_struct seq
+        tuple tuple_struct map struct identifier ignored_any
+    }
+}
+
+impl<'de> IntoDeserializer<'de, Error> for ValueKey<'de> {
+    type Deserializer = ValueKey<'de>;
+
+    fn into_deserializer(self) -> Self::Deserializer {
+        self
+    }
+}
+
+/// A variant of a [`Value`] written as an attrset with a single attribute
+struct ValueEnum<'de, D> {
+    variant: ValueKey<'de>,
+    value: D,
+}
+
+impl<'de, D> EnumAccess<'de> for ValueEnum<'de, D>
+where
+    D: VariantAccess<'de, Error = Error>,
+{
+    type Error = Error;
+    type Variant = D;
+
+    fn variant_seed<V>(self, seed: V) -> Result<(V::Value, Self::Variant), Error>
+    where
+        V: DeserializeSeed<'de>,
+    {
+        let variant = seed.deserialize(self.variant)?;
+        Ok((variant, self.value))
+    }
+}
+
+macro_rules! value_variant_access {
+    ($($ty:ty),*) => {
+        $(
+            impl<'de> VariantAccess<'de> for $ty {
+                type Error = Error;
+
+                fn unit_variant(self) -> Result<(), Error> {
+                    de::Deserialize::deserialize(self)
+                }
+
+                fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Error>
+                where
+                    T: DeserializeSeed<'de>,
+                {
+                    seed.deserialize(self)
+                }
+
+                fn tuple_variant<V>(self, _len: usize, visitor: V) -> Result<V::Value, Error>
+                where
+                    V: Visitor<'de>,
+                {
+                    de::Deserializer::deserialize_seq(self, visitor)
+                }
+
+                fn struct_variant<V>(
+                    self,
+                    _fields: &'static [&'static str],
+                    visitor: V,
+                ) -> Result<V::Value, Error>
+                where
+                    V: Visitor<'de>,
+                {
+                    de::Deserializer::deserialize_map(self, visitor)
+                }
+            }
+        )*
+    };
+}
+
+value_variant_access!(Value, &'de Value);
@@ -0,0 +1,758 @@
+//! A typed syntax tree of Nix expressions, for what cannot be written as
+//! serialized values, such as conditionals and operators.
+//!
+//! Expressions are built with the functions of this module and the methods of
+//! [`Expr`]. They are written with [`to_string`](crate::to_string) like any
+//! other value, and can be the fields of serialized structs. Parentheses are
+//! added where the precedence of the operators requires them, and attribute
+//! sets and lists are laid out by the [`Formatter`](crate::Formatter) of the
+//! configuration.
+//!
+//! # Example
+//!
+//! ```
+//! use ser_nix::expr::{self, ident, interpolate, StringPart};
+//! use ser_nix::to_string;
+//!
+//! let cfg = ident("config").select("services").select("app");
+//! let port = ident("toString").apply(cfg.clone().select("port"));
+//!
+//! let url = expr::if_then_else(
+//!     cfg.select("enable"),
+//!     interpolate([StringPart::from("http://localhost:"), port.into()]),
+//!     expr::value(&None::<String>),
+//! );
+//!
+//! let result = to_string(&url).unwrap();
+//! assert_eq!(
+//!     result,
+//!     concat!(
+//!         "if config.services.app.enable ",
+//!         "then \"http://localhost:${toString config.services.app.port}\" ",
+//!         "else null",
+//!     )
+//! );
+//! ```
+use crate::apply::needs_parens;
+use crate::function::{NixLambda, NixPattern};
+use crate::literal::{NixLiteral, Raw};
+use crate::map::is_nix_identifier;
+use crate::path::NixPathBuf;
+use crate::scope::NixLet;
+use crate::ser::Form;
+use crate::token::Token;
+use crate::unpack::Payload;
+use serde::ser::{Error as _, SerializeMap, SerializeSeq, SerializeTuple};
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+
+/// Keywords, which are not identifiers even though they look like one
+/// Precedence of the expressions that extend as far to the right as possible
+const OPEN: u8 = 15;
+
+/// A Nix expression.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    /// A variable, such as `pkgs`.
+    Ident(String),
+    /// The selection of an attribute path, such as `pkgs.hello`, with an
+    /// optional default written after `or`.
+    Select {
+        expr: Box<Expr>,
+        path: Vec<String>,
+        default: Option<Box<Expr>>,
+    },
+    /// A test of whether an attribute path exists: `expr ? path`.
+    HasAttr { expr: Box<Expr>, path: Vec<String> },
+    /// A function applied to an argument: `function arg`.
+    Apply { function: Box<Expr>, arg: Box<Expr> },
+    /// A function: `param: body`.
+    Lambda { param: Param, body: Box<Expr> },
+    /// A conditional: `if condition then then else otherwise`.
+    If {
+        condition: Box<Expr>,
+        then: Box<Expr>,
+        otherwise: Box<Expr>,
+    },
+    /// Bindings of names in scope of the body: `let bindings in body`.
+    Let {
+        bindings: Vec<(String, Expr)>,
+        body: Box<Expr>,
+    },
+    /// The attributes of `scope` brought into scope of the body:
+    /// `with scope; body`.
+    With { scope: Box<Expr>, body: Box<Expr> },
+    /// An assertion that must hold to evaluate the body:
+    /// `assert condition; body`.
+    Assert {
+        condition: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// A binary operator, such as `lhs + rhs`.
+    BinOp {
+        op: BinOp,
+        lhs: Box<Expr>,
+        rhs: Box<Expr>,
+    },
+    /// A unary operator, such as `!expr`.
+    UnaryOp { op: UnaryOp, expr: Box<Expr> },
+    /// A double-quoted string, which may interpolate expressions.
+    String(Vec<StringPart>),
+    /// A path, such as `./default.nix`.
+    Path(NixPathBuf),
+    /// A list.
+    List(Vec<Expr>),
+    /// An attribute set.
+    Attrs(Vec<(String, Expr)>),
+    /// A serialized value, created with [`value`].
+    Value(Embedded),
+}
+
+/// The parameter of a [`Expr::Lambda`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Param {
+    /// A single argument, such as `x`.
+    Ident(String),
+    /// An attribute set pattern, such as `{ lib, ... }`.
+    Pattern(NixPattern),
+}
+
+/// A part of an [`Expr::String`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StringPart {
+    /// Text, which is escaped when written.
+    Literal(String),
+    /// An interpolated expression: `${expr}`.
+    Interpolation(Expr),
+}
+
+/// A binary operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BinOp {
+    /// `++`
+    Concat,
+    /// `*`
+    Mul,
+    /// `/`
+    Div,
+    /// `+`
+    Add,
+    /// `-`
+    Sub,
+    /// `//`
+    Update,
+    /// `<`
+    Lt,
+    /// `<=`
+    Le,
+    /// `>`
+    Gt,
+    /// `>=`
+    Ge,
+    /// `==`
+    Eq,
+    /// `!=`
+    NotEq,
+    /// `&&`
+    And,
+    /// `||`
+    Or,
+    /// `->`
+    Implies,
+}
+
+/// A unary operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UnaryOp {
+    /// `!`
+    Not,
+    /// `-`
+    Neg,
+}
+
+/// A serialized value in an [`Expr`], rendered by
+/// [`to_string`](crate::to_string), or the message of the error rendering it
+/// failed with.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Embedded(Result<String, String>);
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Assoc {
+    Left,
+    Right,
+    None,
+}
+
+impl BinOp {
+    fn symbol(self) -> &'static str {
+        match self {
+            BinOp::Concat => "++",
+            BinOp::Mul => "*",
+            BinOp::Div => "/",
+            BinOp::Add => "+",
+            BinOp::Sub => "-",
+            BinOp::Update => "//",
+            BinOp::Lt => "<",
+            BinOp::Le => "<=",
+            BinOp::Gt => ">",
+            BinOp::Ge => ">=",
+            BinOp::Eq => "==",
+            BinOp::NotEq => "!=",
+            BinOp::And => "&&",
+            BinOp::Or => "||",
+            BinOp::Implies => "->",
+        }
+    }
+
+    /// Precedence of the operator, lower binding tighter, as listed in the
+    /// Nix manual
+    fn precedence(self) -> u8 {
+        match self {
+            BinOp::Concat => 5,
+            BinOp::Mul | BinOp::Div => 6,
+            BinOp::Add | BinOp::Sub => 7,
+            BinOp::Update => 9,
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => 10,
+            BinOp::Eq | BinOp::NotEq => 11,
+            BinOp::And => 12,
+            BinOp::Or => 13,
+            BinOp::Implies => 14,
+        }
+    }
+
+    fn assoc(self) -> Assoc {
+        match self {
+            BinOp::Concat | BinOp::Update | BinOp::Implies => Assoc::Right,
+            BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge | BinOp::Eq | BinOp::NotEq => Assoc::None,
+            _ => Assoc::Left,
+        }
+    }
+}
+
+/// A variable.
+///
+/// Serialization fails if `name` is not a valid identifier.
+pub fn ident(name: impl Into<String>) -> Expr {
+    Expr::Ident(name.into())
+}
+
+/// A member of `builtins`, such as `builtins.toJSON`.
+pub fn builtins(name: impl Into<String>) -> Expr {
+    ident("builtins").select(name)
+}
+
+/// A string without interpolations.
+pub fn string(text: impl Into<String>) -> Expr {
+    Expr::String(vec![StringPart::Literal(text.into())])
+}
+
+/// A string made of text and interpolated expressions.
+pub fn interpolate(parts: impl IntoIterator<Item = StringPart>) -> Expr {
+    Expr::String(parts.into_iter().collect())
+}
+
+/// A path.
+pub fn path(path: impl Into<NixPathBuf>) -> Expr {
+    Expr::Path(path.into())
+}
+
+/// A list.
+pub fn list(elements: impl IntoIterator<Item = Expr>) -> Expr {
+    Expr::List(elements.into_iter().collect())
+}
+
+/// An attribute set.
+pub fn attrs<K: Into<String>>(attrs: impl IntoIterator<Item = (K, Expr)>) -> Expr {
+    Expr::Attrs(attrs.into_iter().map(|(k, v)| (k.into(), v)).collect())
+}
+
+/// A function taking a single argument called `arg`.
+pub fn lambda(arg: impl Into<String>, body: Expr) -> Expr {
+    Expr::Lambda {
+        param: Param::Ident(arg.into()),
+        body: Box::new(body),
+    }
+}
+
+/// A function taking its arguments with an attribute set pattern.
+pub fn lambda_pattern(pattern: NixPattern, body: Expr) -> Expr {
+    Expr::Lambda {
+        param: Param::Pattern(pattern),
+        body: Box::new(body),
+    }
+}
+
+/// A conditional: `if condition then then else otherwise`.
+pub fn if_then_else(condition: Expr, then: Expr, otherwise: Expr) -> Expr {
+    Expr::If {
+        condition: Box::new(condition),
+        then: Box::new(then),
+        otherwise: Box::new(otherwise),
+    }
+}
+
+/// Binds names in scope of `body`: `let bindings in body`.
+pub fn let_in<K: Into<String>>(bindings: impl IntoIterator<Item = (K, Expr)>, body: Expr) -> Expr {
+    Expr::Let {
+        bindings: bindings.into_iter().map(|(k, v)| (k.into(), v)).collect(),
+        body: Box::new(body),
+    }
+}
+
+/// Brings the attributes of `scope` into scope of `body`: `with scope; body`.
+pub fn with(scope: Expr, body: Expr) -> Expr {
+    Expr::With {
+        scope: Box::new(scope),
+        body: Box::new(body),
+    }
+}
+
+/// Asserts that `condition` holds before evaluating `body`:
+/// `assert condition; body`.
+pub fn assert(condition: Expr, body: Expr) -> Expr {
+    Expr::Assert {
+        condition: Box::new(condition),
+        body: Box::new(body),
+    }
+}
+
+/// A serialized value, rendered with [`to_string`](crate::to_string).
+///
+/// If `value` cannot be serialized, serializing the expression fails.
+pub fn value<T>(value: &T) -> Expr
+where
+    T: ?Sized + Serialize,
+{
+    Expr::Value(Embedded(crate::to_string(value).map_err(|e| e.to_string())))
+}
+
+impl Expr {
+    /// Selects the attribute `name`, extending the attribute path if this is
+    /// already a selection.
+    pub fn select(self, name: impl Into<String>) -> Expr {
+        match self {
+            Expr::Select {
+                expr,
+                mut path,
+                default: None,
+            } => {
+                path.push(name.into());
+                Expr::Select {
+                    expr,
+                    path,
+                    default: None,
+                }
+            }
+            expr => Expr::Select {
+                expr: Box::new(expr),
+                path: vec![name.into()],
+                default: None,
+            },
+        }
+    }
+
+    /// Sets the value of a selection when its attribute path does not exist:
+    /// `expr.path or default`.
+    ///
+    /// Serialization fails if this is not a selection.
+    pub fn or(self, default: Expr) -> Expr {
+        let (expr, path) = match self {
+            Expr::Select {
+                expr,
+                path,
+                default: None,
+            } => (expr, path),
+            expr => (Box::new(expr), Vec::new()),
+        };
+        Expr::Select {
+            expr,
+            path,
+            default: Some(Box::new(default)),
+        }
+    }
+
+    /// Tests whether the attribute `name` exists: `expr ? name`.
+    pub fn has_attr(self, name: impl Into<String>) -> Expr {
+        Expr::HasAttr {
+            expr: Box::new(self),
+            path: vec![name.into()],
+        }
+    }
+
+    /// Applies this function to `arg`.
+    pub fn apply(self, arg: Expr) -> Expr {
+        Expr::Apply {
+            function: Box::new(self),
+            arg: Box::new(arg),
+        }
+    }
+
+    /// Applies the binary operator `op` to this expression and `rhs`.
+    pub fn binary(self, op: BinOp, rhs: Expr) -> Expr {
+        Expr::BinOp {
+            op,
+            lhs: Box::new(self),
+            rhs: Box::new(rhs),
+        }
+    }
+
+    /// Updates this attribute set with the attributes of `rhs`:
+    /// `self // rhs`.
+    pub fn update(self, rhs: Expr) -> Expr {
+        self.binary(BinOp::Update, rhs)
+    }
+
+    /// Concatenates this list with `rhs`: `self ++ rhs`.
+    pub fn concat(self, rhs: Expr) -> Expr {
+        self.binary(BinOp::Concat, rhs)
+    }
+
+    fn unary(self, op: UnaryOp) -> Expr {
+        Expr::UnaryOp {
+            op,
+            expr: Box::new(self),
+        }
+    }
+
+    /// Precedence of the expression, lower binding tighter
+    fn precedence(&self) -> u8 {
+        match self {
+            Expr::Ident(_) | Expr::String(_) | Expr::Path(_) | Expr::List(_) | Expr::Attrs(_) => 0,
+            Expr::Value(Embedded(Ok(rendered))) if needs_parens(rendered) => OPEN,
+            Expr::Value(_) => 0,
+            Expr::Select { .. } => 1,
+            Expr::Apply { .. } => 2,
+            Expr::UnaryOp {
+                op: UnaryOp::Neg, ..
+            } => 3,
+            Expr::HasAttr { .. } => 4,
+            Expr::BinOp { op, .. } => op.precedence(),
+            Expr::UnaryOp {
+                op: UnaryOp::Not, ..
+            } => 8,
+            Expr::Lambda { .. }
+            | Expr::If { .. }
+            | Expr::Let { .. }
+            | Expr::With { .. }
+            | Expr::Assert { .. } => OPEN,
+        }
+    }
+}
+
+macro_rules! operator {
+    ($trait:ident, $method:ident, $op:expr) => {
+        impl std::ops::$trait for Expr {
+            type Output = Expr;
+
+            fn $method(self, rhs: Expr) -> Expr {
+                self.binary($op, rhs)
+            }
+        }
+    };
+}
+
+operator!(Add, add, BinOp::Add);
+operator!(Sub, sub, BinOp::Sub);
+operator!(Mul, mul, BinOp::Mul);
+operator!(Div, div, BinOp::Div);
+
+impl std::ops::Not for Expr {
+    type Output = Expr;
+
+    fn not(self) -> Expr {
+        self.unary(UnaryOp::Not)
+    }
+}
+
+impl std::ops::Neg for Expr {
+    type Output = Expr;
+
+    fn neg(self) -> Expr {
+        self.unary(UnaryOp::Neg)
+    }
+}
+
+impl From<&str> for StringPart {
+    fn from(text: &str) -> Self {
+        StringPart::Literal(text.to_string())
+    }
+}
+
+impl From<String> for StringPart {
+    fn from(text: String) -> Self {
+        StringPart::Literal(text)
+    }
+}
+
+impl From<Expr> for StringPart {
+    fn from(expr: Expr) -> Self {
+        StringPart::Interpolation(expr)
+    }
+}
+
+impl Serialize for Expr {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Expr::Ident(name) => {
+                if !is_nix_identifier(name) {
+                    return Err(S::Error::custom(format!("invalid identifier `{name}`")));
+                }
+                NixLiteral::new(name).serialize(serializer)
+            }
+            Expr::Select {
+                expr,
+                path,
+                default,
+            } => {
+                if path.is_empty() {
+                    return Err(S::Error::custom("selection of an empty attribute path"));
+                }
+                // `a.b.c` is the same as `(a.b).c`, unless `a.b` has a default
+                let chained = matches!(**expr, Expr::Select { default: None, .. });
+                let mut parts = vec![operand(expr, chained as u8), raw(attr_path(path))];
+                if let Some(default) = default {
+                    parts.push(raw(" or "));
+                    parts.push(operand(default, 1));
+                }
+                Concat::term(parts).serialize(serializer)
+            }
+            Expr::HasAttr { expr, path } => {
+                let parts = vec![
+                    operand(expr, 3),
+                    raw(" ? "),
+                    raw(attr_path(path)[1..].to_string()),
+                ];
+                Concat::open(parts).serialize(serializer)
+            }
+            Expr::Apply { function, arg } => {
+                let parts = vec![operand(function, 2), raw(" "), operand(arg, 1)];
+                Concat {
+                    kind: Form::Apply,
+                    parts,
+                }
+                .serialize(serializer)
+            }
+            Expr::Lambda { param, body } => match param {
+                Param::Ident(arg) => NixLambda::new(arg, &**body).serialize(serializer),
+                Param::Pattern(pattern) => {
+                    NixLambda::with_pattern(pattern.clone(), &**body).serialize(serializer)
+                }
+            },
+            Expr::If {
+                condition,
+                then,
+                otherwise,
+            } => Concat::open(vec![
+                raw("if "),
+                Part::Expr(condition),
+                raw(" then "),
+                Part::Expr(then),
+                raw(" else "),
+                Part::Expr(otherwise),
+            ])
+            .serialize(serializer),
+            Expr::Let { bindings, body } => {
+                NixLet::new(Attrs(bindings), &**body).serialize(serializer)
+            }
+            Expr::With { scope, body } => serializer.serialize_newtype_struct(
+                Token::Unpack(Payload::With).name(),
+                &(&**scope, &**body),
+            ),
+            Expr::Assert { condition, body } => Concat::open(vec![
+                raw("assert "),
+                Part::Expr(condition),
+                raw("; "),
+                Part::Expr(body),
+            ])
+            .serialize(serializer),
+            Expr::BinOp { op, lhs, rhs } => {
+                let precedence = op.precedence();
+                let max = |assoc| match op.assoc() == assoc {
+                    true => precedence,
+                    false => precedence - 1,
+                };
+                Concat::open(vec![
+                    operand(lhs, max(Assoc::Left)),
+                    raw(format!(" {} ", op.symbol())),
+                    operand(rhs, max(Assoc::Right)),
+                ])
+                .serialize(serializer)
+            }
+            Expr::UnaryOp { op, expr } => {
+                let parts = match op {
+                    UnaryOp::Not => vec![raw("!"), operand(expr, 8)],
+                    UnaryOp::Neg => vec![raw("-"), operand(expr, 2)],
+                };
+                Concat::open(parts).serialize(serializer)
+            }
+            Expr::String(parts) => {
+                let mut text = String::from("\"");
+                let mut concat = Vec::new();
+                for part in parts {
+                    match part {
+                        StringPart::Literal(literal) => escape_into(literal, &mut text),
+                        StringPart::Interpolation(expr) => {
+                            text += "${";
+                            concat.push(raw(std::mem::take(&mut text)));
+                            concat.push(Part::Expr(expr));
+                            text.push('}');
+                        }
+                    }
+                }
+                text.push('"');
+                concat.push(raw(text));
+                Concat::term(concat).serialize(serializer)
+            }
+            Expr::Path(path) => path.serialize(serializer),
+            Expr::List(elements) => {
+                let mut seq = serializer.serialize_seq(Some(elements.len()))?;
+                for element in elements {
+                    seq.serialize_element(&operand(element, 1))?;
+                }
+                seq.end()
+            }
+            Expr::Attrs(attrs) => Attrs(attrs).serialize(serializer),
+            Expr::Value(Embedded(Ok(rendered))) => {
+                serializer.serialize_newtype_struct(Token::Rendered.name(), rendered)
+            }
+            Expr::Value(Embedded(Err(msg))) => Err(S::Error::custom(msg)),
+        }
+    }
+}
+
+/// Writes an attribute path, starting with a `.`
+fn attr_path(path: &[String]) -> String {
+    let mut rendered = String::new();
+    for name in path {
+        rendered.push('.');
+        match is_nix_identifier(name) {
+            true => rendered += name,
+            false => {
+                rendered.push('"');
+                escape_into(name, &mut rendered);
+                rendered.push('"');
+            }
+        }
+    }
+    rendered
+}
+
+/// Escapes `text` to be written inside a double-quoted string
+pub(crate) fn escape_into(text: &str, out: &mut String) {
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c => out.push(c),
+        }
+    }
+}
+
+/// Attributes or bindings, written as a map
+struct Attrs<'a>(&'a [(String, Expr)]);
+
+impl Serialize for Attrs<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in self.0 {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+/// A part of an expression written by [`Concat`]
+enum Part<'a> {
+    Raw(Cow<'a, str>),
+    Expr(&'a Expr),
+    Parens(&'a Expr),
+}
+
+fn raw<'a>(text: impl Into<Cow<'a, str>>) -> Part<'a> {
+    Part::Raw(text.into())
+}
+
+/// An operand, parenthesized if it binds less tightly than `max`
+fn operand(expr: &Expr, max: u8) -> Part<'_> {
+    match expr.precedence() > max {
+        true => Part::Parens(expr),
+        false => Part::Expr(expr),
+    }
+}
+
+impl Serialize for Part<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Part::Raw(text) => Raw(text).serialize(serializer),
+            Part::Expr(expr) => expr.serialize(serializer),
+            Part::Parens(expr) => {
+                Concat::term(vec![raw("("), Part::Expr(expr), raw(")")]).serialize(serializer)
+            }
+        }
+    }
+}
+
+/// Parts written one after the other
+struct Concat<'a> {
+    /// How the parts combine with what surrounds them
+    kind: Form,
+    parts: Vec<Part<'a>>,
+}
+
+impl<'a> Concat<'a> {
+    fn term(parts: Vec<Part<'a>>) -> Self {
+        Concat {
+            kind: Form::Term,
+            parts,
+        }
+    }
+
+    fn open(parts: Vec<Part<'a>>) -> Self {
+        Concat {
+            kind: Form::Open,
+            parts,
+        }
+    }
+}
+
+impl Serialize for Concat<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(
+            Token::Unpack(Payload::Concat(self.kind)).name(),
+            &ConcatParts(self),
+        )
+    }
+}
+
+/// `(part, ...)`
+struct ConcatParts<'a, 'b>(&'a Concat<'b>);
+
+impl Serialize for ConcatParts<'_, '_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(self.0.parts.len())?;
+        for part in &self.0.parts {
+            tuple.serialize_element(part)?;
+        }
+        tuple.end()
+    }
+}
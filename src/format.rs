@@ -0,0 +1,833 @@
+use crate::comment::CommentStyle;
+use crate::map::is_nix_identifier;
+
+/// Describes the attribute set or list currently being written by a [`Formatter`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct Context {
+    /// Indentation depth of the line the container starts on.
+    pub depth: usize,
+    /// Number of attributes or elements in the container.
+    pub len: usize,
+    /// Whether the container comes from a tuple or an enum variant, which
+    /// [`DefaultFormatter`] writes on a single line.
+    pub compact: bool,
+    /// Whether the attribute set holds the fields of a struct rather than
+    /// the entries of a map.
+    pub fields: bool,
+    /// Whether any of the values in the container spans several lines.
+    pub multiline: bool,
+    /// Width in characters of the longest attribute name, or zero for lists.
+    pub key_width: usize,
+}
+
+/// Controls the whitespace and punctuation around serialized Nix values.
+///
+/// The serializer decides *what* is written, and calls the hooks of a
+/// `Formatter` to decide *how* it is laid out. Every hook has a default
+/// implementation producing the layout of [`DefaultFormatter`], so custom
+/// formatters only need to override the hooks they want to change.
+///
+/// Attribute sets are written as
+/// `begin_attrset`, then for every attribute
+/// (`between_attrs` unless it is the first) `before_key`, the key,
+/// `begin_value`, the value and `end_value`, and finally `end_attrset`.
+/// Lists follow the same pattern with the `*_list` and `*_element` hooks.
+///
+/// # Example
+///
+/// A formatter that aligns the `=` signs of every attribute set:
+///
+/// ```
+/// use ser_nix::{to_string_with_config, Config, Context, Formatter};
+/// use std::collections::BTreeMap;
+///
+/// struct Aligned;
+///
+/// impl Formatter for Aligned {
+///     fn begin_value(&self, out: &mut String, key: &str, ctx: &Context) {
+///         let padding = ctx.key_width - key.chars().count();
+///         out.push_str(&" ".repeat(padding));
+///         out.push_str(" = ");
+///     }
+/// }
+///
+/// let mut map = BTreeMap::new();
+/// map.insert("a", 1);
+/// map.insert("abc", 2);
+///
+/// let config = Config::new().formatter(Aligned);
+/// let result = to_string_with_config(&map, &config).unwrap();
+///
+/// assert_eq!(result, "{\n  a   = 1;\n  abc = 2;\n}");
+/// ```
+pub trait Formatter {
+    /// Writes the indentation for a line at `depth`.
+    fn write_indent(&self, out: &mut String, depth: usize) {
+        for _ in 0..depth {
+            out.push_str("  ");
+        }
+    }
+
+    /// Writes an attribute set without any attributes.
+    fn empty_attrset(&self, out: &mut String, ctx: &Context) {
+        out.push('{');
+        if !ctx.fields {
+            out.push('\n');
+        }
+        self.write_indent(out, ctx.depth);
+        out.push('}');
+    }
+
+    /// Writes the start of a non-empty attribute set.
+    fn begin_attrset(&self, out: &mut String, _ctx: &Context) {
+        out.push('{');
+    }
+
+    /// Called between two attributes of the same attribute set.
+    fn between_attrs(&self, _out: &mut String, _ctx: &Context) {}
+
+    /// Writes whatever precedes the name of an attribute.
+    fn before_key(&self, out: &mut String, ctx: &Context) {
+        if ctx.compact {
+            out.push(' ');
+        } else {
+            out.push('\n');
+            self.write_indent(out, ctx.depth + 1);
+        }
+    }
+
+    /// Writes the separator between the attribute name `key` and its value.
+    fn begin_value(&self, out: &mut String, _key: &str, _ctx: &Context) {
+        out.push_str(" = ");
+    }
+
+    /// Writes whatever follows the value of an attribute.
+    fn end_value(&self, out: &mut String, _ctx: &Context) {
+        out.push(';');
+    }
+
+    /// Writes a comment followed by a newline and the indentation for `depth`,
+    /// so the commented attribute starts on the next line.
+    fn write_comment(&self, out: &mut String, text: &str, style: CommentStyle, depth: usize) {
+        match style {
+            CommentStyle::Block if !text.contains('\n') => {
+                out.push_str("/* ");
+                out.push_str(text);
+                out.push_str(" */");
+            }
+            CommentStyle::Block => {
+                out.push_str("/*");
+                for line in text.lines() {
+                    out.push('\n');
+                    if !line.is_empty() {
+                        self.write_indent(out, depth + 1);
+                        out.push_str(line);
+                    }
+                }
+                out.push('\n');
+                self.write_indent(out, depth);
+                out.push_str("*/");
+            }
+            _ => {
+                for (i, line) in text.split('\n').enumerate() {
+                    if i > 0 {
+                        out.push('\n');
+                        self.write_indent(out, depth);
+                    }
+                    out.push('#');
+                    if !line.is_empty() {
+                        out.push(' ');
+                        out.push_str(line);
+                    }
+                }
+            }
+        }
+        out.push('\n');
+        self.write_indent(out, depth);
+    }
+
+    /// Writes an attribute left unset by
+    /// [`NonePolicy::Placeholder`](crate::NonePolicy::Placeholder), in place
+    /// of its key and value.
+    ///
+    /// `hint` describes the type of the attribute when it is known.
+    fn write_placeholder(&self, out: &mut String, key: &str, hint: Option<&str>, ctx: &Context) {
+        out.push_str("# ");
+        out.push_str(key);
+        self.begin_value(out, key, ctx);
+        out.push_str("null");
+        self.end_value(out, ctx);
+        if let Some(hint) = hint {
+            out.push_str(" # type: ");
+            out.push_str(hint);
+        }
+    }
+
+    /// Writes consecutive attributes inherited from the same place, in place
+    /// of their keys and values: `inherit names;`, or `inherit (from) names;`.
+    fn write_inherit(&self, out: &mut String, from: Option<&str>, names: &[&str], _ctx: &Context) {
+        out.push_str("inherit");
+        if let Some(from) = from {
+            out.push_str(" (");
+            out.push_str(from);
+            out.push(')');
+        }
+        for name in names {
+            out.push(' ');
+            out.push_str(name);
+        }
+        out.push(';');
+    }
+
+    /// Writes the end of a non-empty attribute set.
+    fn end_attrset(&self, out: &mut String, ctx: &Context) {
+        if ctx.compact {
+            out.push_str(" }");
+        } else {
+            out.push('\n');
+            self.write_indent(out, ctx.depth);
+            out.push('}');
+        }
+    }
+
+    /// Writes a list without any elements.
+    fn empty_list(&self, out: &mut String, ctx: &Context) {
+        if ctx.compact {
+            out.push_str("[ ]");
+            return;
+        }
+        out.push_str("[\n");
+        self.write_indent(out, ctx.depth);
+        out.push(']');
+    }
+
+    /// Writes the start of a non-empty list.
+    fn begin_list(&self, out: &mut String, _ctx: &Context) {
+        out.push('[');
+    }
+
+    /// Called between two elements of the same list.
+    fn between_elements(&self, out: &mut String, ctx: &Context) {
+        if ctx.compact {
+            out.push(' ');
+        }
+    }
+
+    /// Writes whatever precedes an element of a list.
+    fn before_element(&self, out: &mut String, ctx: &Context) {
+        if !ctx.compact {
+            out.push('\n');
+            self.write_indent(out, ctx.depth + 1);
+        }
+    }
+
+    /// Writes the end of a non-empty list.
+    fn end_list(&self, out: &mut String, ctx: &Context) {
+        if ctx.compact {
+            out.push_str(" ]");
+        } else {
+            out.push('\n');
+            self.write_indent(out, ctx.depth);
+            out.push(']');
+        }
+    }
+
+    /// Whether attributes are written sorted by name instead of in the order
+    /// they were serialized.
+    fn sort_attrs(&self) -> bool {
+        false
+    }
+
+    /// Whether enum variants holding data are written as attribute sets laid
+    /// out by the other hooks, named after the variant with its first letter
+    /// lowercased: `{ mirror = [ ... ]; }`.
+    ///
+    /// Otherwise they are written on one line the way earlier versions did,
+    /// with the name of tuple and struct variants quoted as is:
+    /// `{ "Mirror" = [... ] }`, which Nix does not accept.
+    fn expand_variants(&self) -> bool {
+        false
+    }
+
+    /// Writes a finite floating point number.
+    ///
+    /// Numbers without a fractional part are written with `.0`, so that Nix
+    /// reads them back as floats rather than integers.
+    fn write_float(&self, out: &mut String, value: f64) {
+        let float = value.to_string();
+        out.push_str(&float);
+        if !float.contains('.') {
+            out.push_str(".0");
+        }
+    }
+
+    /// Writes a string value starting on a line at `depth`.
+    ///
+    /// Strings of at least 80 characters that contain a newline are written
+    /// as indented `''` strings, everything else as a double-quoted string.
+    fn write_string(&self, out: &mut String, value: &str, depth: usize) {
+        let multiline = value.contains('\n') && value.len() >= 80;
+
+        match multiline {
+            true => {
+                out.push_str("''\n");
+                self.write_indent(out, depth + 1);
+            }
+            false => out.push('"'),
+        }
+
+        let mut chars = value.chars().peekable();
+
+        while let Some(c) = chars.next() {
+            match c {
+                '\'' => match chars.peek() {
+                    Some(&'\'') if multiline => out.push_str("''"),
+                    _ => out.push('\''),
+                },
+                '"' => match multiline {
+                    true => out.push('"'),
+                    false => out.push_str("\\\""),
+                },
+                '\\' => match multiline {
+                    true => out.push('\\'),
+                    false => out.push_str("\\\\"),
+                },
+                '$' => match chars.peek() {
+                    Some(&'{') => out.push_str("''$"),
+                    _ => out.push('$'),
+                },
+                '\n' => match multiline {
+                    true => {
+                        out.push('\n');
+                        self.write_indent(out, depth + 1);
+                    }
+                    false => out.push_str("\\n"),
+                },
+                '\t' => match multiline {
+                    true => out.push('\t'),
+                    false => out.push_str("\\t"),
+                },
+                c => out.push(c),
+            }
+        }
+
+        match multiline {
+            true => {
+                out.push('\n');
+                self.write_indent(out, depth);
+                out.push_str("''");
+            }
+            false => out.push('"'),
+        }
+    }
+
+    /// Writes an attribute name that is not an identifier.
+    ///
+    /// Names are always double-quoted, escaped like the double-quoted
+    /// strings of [`write_string`](Formatter::write_string), as an indented
+    /// `''` string cannot be an attribute name.
+    fn write_attr_name(&self, out: &mut String, name: &str) {
+        const ESCAPES: &[(&str, &str)] = &[
+            ("\"", "\\\""),
+            ("\\", "\\\\"),
+            ("${", "''${"),
+            ("\n", "\\n"),
+            ("\t", "\\t"),
+        ];
+
+        out.push('"');
+        escape_into(name, ESCAPES, out);
+        out.push('"');
+    }
+
+    /// Writes a number or a boolean that is the key of a map.
+    ///
+    /// By default it is written as is, although Nix only accepts numbers as
+    /// attribute names when they are quoted.
+    fn write_scalar_key(&self, out: &mut String, key: &str) {
+        out.push_str(key);
+    }
+
+    /// Writes a string value starting on a line at `depth` as an indented
+    /// `''` string, whatever its contents.
+    fn write_indented_string(&self, out: &mut String, value: &str, depth: usize) {
+        out.push_str("''");
+        let body = value.strip_suffix('\n');
+        for line in body.unwrap_or(value).split('\n') {
+            out.push('\n');
+            if !line.is_empty() {
+                self.write_indent(out, depth + 1);
+                escape_into(line, &[("''", "'''"), ("${", "''${")], out);
+            }
+        }
+        if body.is_some() {
+            out.push('\n');
+            self.write_indent(out, depth);
+        }
+        out.push_str("''");
+    }
+
+    /// Writes the attribute set pattern of a function starting on a line at
+    /// `depth`, such as `{ config, lib, ... }`, without the following `:`.
+    ///
+    /// `args` are the names of the arguments with their default values,
+    /// rendered as if they started on a line at `depth + 1`, and `ellipsis`
+    /// whether other arguments are accepted.
+    fn write_pattern(
+        &self,
+        out: &mut String,
+        args: &[(&str, Option<&str>)],
+        ellipsis: bool,
+        _depth: usize,
+    ) {
+        if args.is_empty() && !ellipsis {
+            out.push_str("{ }");
+            return;
+        }
+        out.push('{');
+        for (i, (name, default)) in args.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+            out.push(' ');
+            write_pattern_arg(out, name, *default);
+        }
+        if ellipsis {
+            if !args.is_empty() {
+                out.push(',');
+            }
+            out.push_str(" ...");
+        }
+        out.push_str(" }");
+    }
+
+    /// Post-processes the complete output before it is returned.
+    ///
+    /// By default this removes the whitespace left on otherwise empty lines.
+    fn finish(&self, output: String) -> String {
+        strip_blank_lines(&output)
+    }
+}
+
+/// Writes an argument of a pattern, with its default value if it has one
+fn write_pattern_arg(out: &mut String, name: &str, default: Option<&str>) {
+    out.push_str(name);
+    if let Some(default) = default {
+        out.push_str(" ? ");
+        out.push_str(default);
+    }
+}
+
+/// Starts an element of a list with one element per line
+fn expand_element<F>(formatter: &F, out: &mut String, ctx: &Context)
+where
+    F: Formatter + ?Sized,
+{
+    out.push('\n');
+    formatter.write_indent(out, ctx.depth + 1);
+}
+
+/// Closes a list with one element per line
+fn expand_end_list<F>(formatter: &F, out: &mut String, ctx: &Context)
+where
+    F: Formatter + ?Sized,
+{
+    out.push('\n');
+    formatter.write_indent(out, ctx.depth);
+    out.push(']');
+}
+
+/// Removes extra whitespace that gets left behind due to indentation
+fn strip_blank_lines(serialized: &str) -> String {
+    serialized
+        .split('\n')
+        .map(|l| if l.chars().any(|c| c != ' ') { l } else { "" })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The layout used by [`to_string`](crate::to_string).
+///
+/// Attribute sets and lists are expanded with one entry per line, while
+/// tuples and enum variants stay on a single line when they fit.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DefaultFormatter;
+
+impl Formatter for DefaultFormatter {}
+
+/// Output that is left unchanged by [nixfmt](https://github.com/NixOS/nixfmt),
+/// the official formatter implementing the RFC 166 style.
+///
+/// Compared to [`DefaultFormatter`], empty attribute sets and lists are
+/// written as `{ }` and `[ ]`, lists of a single element that fits on a line
+/// as `[ 1 ]`, tuples and enum variants are expanded over
+/// several lines, function patterns with several arguments have one argument
+/// per line, and the output ends with a trailing newline. Strings and
+/// attribute names are escaped so that Nix reads them back unchanged, and
+/// numbers used as attribute names are quoted.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NixfmtFormatter;
+
+impl Formatter for NixfmtFormatter {
+    fn empty_attrset(&self, out: &mut String, _ctx: &Context) {
+        out.push_str("{ }");
+    }
+
+    fn before_key(&self, out: &mut String, ctx: &Context) {
+        out.push('\n');
+        self.write_indent(out, ctx.depth + 1);
+    }
+
+    fn end_attrset(&self, out: &mut String, ctx: &Context) {
+        out.push('\n');
+        self.write_indent(out, ctx.depth);
+        out.push('}');
+    }
+
+    fn empty_list(&self, out: &mut String, _ctx: &Context) {
+        out.push_str("[ ]");
+    }
+
+    fn between_elements(&self, _out: &mut String, _ctx: &Context) {}
+
+    // A list of a single element stays on one line, unless the element
+    // spans several lines itself
+    fn before_element(&self, out: &mut String, ctx: &Context) {
+        match ctx.len == 1 && !ctx.multiline {
+            true => out.push(' '),
+            false => expand_element(self, out, ctx),
+        }
+    }
+
+    fn end_list(&self, out: &mut String, ctx: &Context) {
+        match ctx.len == 1 && !ctx.multiline {
+            true => out.push_str(" ]"),
+            false => expand_end_list(self, out, ctx),
+        }
+    }
+
+    fn expand_variants(&self) -> bool {
+        true
+    }
+
+    /// Strings are written like [`DefaultFormatter`] writes them, except that
+    /// indented strings end on their last line and every character is
+    /// escaped so that Nix reads back the same string.
+    fn write_string(&self, out: &mut String, value: &str, depth: usize) {
+        if value.contains('\n') && value.len() >= 80 {
+            write_indented_lines(self, out, value, depth);
+            return;
+        }
+
+        out.push('"');
+        escape_into(value, QUOTED, out);
+        out.push('"');
+    }
+
+    fn write_attr_name(&self, out: &mut String, name: &str) {
+        out.push('"');
+        escape_into(name, QUOTED, out);
+        out.push('"');
+    }
+
+    fn write_scalar_key(&self, out: &mut String, key: &str) {
+        match is_nix_identifier(key) {
+            true => out.push_str(key),
+            false => self.write_attr_name(out, key),
+        }
+    }
+
+    fn write_pattern(
+        &self,
+        out: &mut String,
+        args: &[(&str, Option<&str>)],
+        ellipsis: bool,
+        depth: usize,
+    ) {
+        if args.len() < 2 {
+            return DefaultFormatter.write_pattern(out, args, ellipsis, depth);
+        }
+        out.push('{');
+        for (name, default) in args {
+            out.push('\n');
+            self.write_indent(out, depth + 1);
+            write_pattern_arg(out, name, *default);
+            out.push(',');
+        }
+        if ellipsis {
+            out.push('\n');
+            self.write_indent(out, depth + 1);
+            out.push_str("...");
+        }
+        out.push('\n');
+        self.write_indent(out, depth);
+        out.push('}');
+    }
+
+    fn finish(&self, output: String) -> String {
+        strip_blank_lines(&output) + "\n"
+    }
+}
+
+/// Output matching nixpkgs' `lib.generators.toPretty { }`.
+///
+/// Containers are laid out like [`NixfmtFormatter`], except that lists of a
+/// single element are expanded too. Attributes are sorted by name as Nix
+/// orders them, every string containing a newline becomes an indented `''`
+/// string, and floats and attribute names are rendered like
+/// `builtins.toJSON`, with `$` escaped in names as `escapeNixIdentifier` does.
+/// Negative numbers in lists are put between parentheses, which `toPretty`
+/// leaves out, as Nix would not read them back otherwise.
+/// Lines inside indented strings keep their indentation even when empty, and
+/// no trailing newline is added, so the result can be compared directly with
+/// the string produced by Nix.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ToPrettyFormatter;
+
+impl Formatter for ToPrettyFormatter {
+    fn empty_attrset(&self, out: &mut String, ctx: &Context) {
+        NixfmtFormatter.empty_attrset(out, ctx)
+    }
+
+    fn before_key(&self, out: &mut String, ctx: &Context) {
+        NixfmtFormatter.before_key(out, ctx)
+    }
+
+    fn end_attrset(&self, out: &mut String, ctx: &Context) {
+        NixfmtFormatter.end_attrset(out, ctx)
+    }
+
+    fn empty_list(&self, out: &mut String, ctx: &Context) {
+        NixfmtFormatter.empty_list(out, ctx)
+    }
+
+    fn between_elements(&self, out: &mut String, ctx: &Context) {
+        NixfmtFormatter.between_elements(out, ctx)
+    }
+
+    fn before_element(&self, out: &mut String, ctx: &Context) {
+        expand_element(self, out, ctx)
+    }
+
+    fn end_list(&self, out: &mut String, ctx: &Context) {
+        expand_end_list(self, out, ctx)
+    }
+
+    // Nix keeps attributes sorted by name, so `toPretty` always lists them in order
+    fn sort_attrs(&self) -> bool {
+        true
+    }
+
+    fn expand_variants(&self) -> bool {
+        true
+    }
+
+    fn write_float(&self, out: &mut String, value: f64) {
+        out.push_str(&json_float(value));
+    }
+
+    fn write_string(&self, out: &mut String, value: &str, depth: usize) {
+        const SINGLE_LINE: &[(&str, &str)] = &[("\\", "\\\\"), ("\"", "\\\""), ("${", "\\${")];
+        const MULTILINE: &[(&str, &str)] = &[("${", "''${"), ("''", "'''")];
+
+        let lines: Vec<&str> = value.split('\n').collect();
+
+        if lines.len() == 1 {
+            out.push('"');
+            escape_into(value, SINGLE_LINE, out);
+            out.push('"');
+            return;
+        }
+
+        let mut intro = String::from("\n");
+        self.write_indent(&mut intro, depth + 1);
+        let (last, init) = lines.split_last().expect("split always yields a line");
+
+        out.push_str("''");
+        for line in init {
+            out.push_str(&intro);
+            escape_into(line, MULTILINE, out);
+        }
+
+        // A trailing newline puts the closing quotes on their own line
+        if last.is_empty() {
+            out.push('\n');
+            self.write_indent(out, depth);
+        } else {
+            out.push_str(&intro);
+            escape_into(last, MULTILINE, out);
+        }
+        out.push_str("''");
+    }
+
+    // `escapeNixIdentifier` writes names as `builtins.toJSON` does, with `$`
+    // escaped as well
+    fn write_attr_name(&self, out: &mut String, name: &str) {
+        out.push('"');
+        for c in name.chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                '\t' => out.push_str("\\t"),
+                '\u{8}' => out.push_str("\\b"),
+                '\u{c}' => out.push_str("\\f"),
+                '$' => out.push_str("\\$"),
+                c if c < ' ' => out.push_str(&format!("\\u{:04x}", c as u32)),
+                c => out.push(c),
+            }
+        }
+        out.push('"');
+    }
+
+    fn write_scalar_key(&self, out: &mut String, key: &str) {
+        match is_nix_identifier(key) {
+            true => out.push_str(key),
+            false => self.write_attr_name(out, key),
+        }
+    }
+
+    fn finish(&self, output: String) -> String {
+        output
+    }
+}
+
+/// Escapes of a double-quoted string that Nix reads back unchanged
+const QUOTED: &[(&str, &str)] = &[
+    ("\\", "\\\\"),
+    ("\"", "\\\""),
+    ("\n", "\\n"),
+    ("\r", "\\r"),
+    ("\t", "\\t"),
+    ("${", "\\${"),
+];
+
+/// Writes `value` as an indented `''` string whose lines start at
+/// `depth + 1`, escaping what Nix would otherwise read as indentation or as
+/// the closing quotes
+fn write_indented_lines<F>(formatter: &F, out: &mut String, value: &str, depth: usize)
+where
+    F: Formatter + ?Sized,
+{
+    // A quote before the escape of `${` would be read as part of an escape
+    const ESCAPES: &[(&str, &str)] = &[("''", "'''"), ("'${", "''\\'''${"), ("${", "''${")];
+    let blank = |line: &str| line.bytes().all(|b| b == b' ');
+
+    // A space starting every line would be stripped as indentation
+    let mut escape_space = value
+        .split('\n')
+        .filter(|line| !blank(line))
+        .all(|line| line.starts_with(' '));
+
+    out.push_str("''");
+    let lines: Vec<&str> = value.split('\n').collect();
+    let (last, init) = lines.split_last().expect("split always yields a line");
+    for line in init {
+        out.push('\n');
+        if line.is_empty() {
+            continue;
+        }
+        formatter.write_indent(out, depth + 1);
+        let mut line = *line;
+        if blank(line) {
+            write_spaces(out, line);
+            continue;
+        }
+        if escape_space {
+            out.push_str("''\\ ");
+            line = &line[1..];
+            escape_space = false;
+        }
+        escape_into(line, ESCAPES, out);
+    }
+
+    // A trailing newline puts the closing quotes on their own line
+    out.push('\n');
+    if last.is_empty() {
+        formatter.write_indent(out, depth);
+        out.push_str("''");
+        return;
+    }
+
+    formatter.write_indent(out, depth + 1);
+    let mut line = *last;
+    if blank(line) {
+        write_spaces(out, line);
+    } else {
+        if escape_space {
+            out.push_str("''\\ ");
+            line = &line[1..];
+        }
+        // Quotes right before the closing ones would be read as an escape
+        let text = line.trim_end_matches('\'');
+        escape_into(text, ESCAPES, out);
+        for _ in text.len()..line.len() {
+            out.push_str("''\\'");
+        }
+    }
+    out.push_str("''");
+}
+
+/// Writes a line of an indented string made of `spaces`, escaping the last
+/// one as a line of spaces would be emptied
+fn write_spaces(out: &mut String, spaces: &str) {
+    out.push_str(&spaces[1..]);
+    out.push_str("''\\ ");
+}
+
+/// Copies `s` into `out`, substituting each occurrence of a pattern in a
+/// single left-to-right pass like `builtins.replaceStrings`
+fn escape_into(s: &str, replacements: &[(&str, &str)], out: &mut String) {
+    let mut rest = s;
+    'outer: while let Some(c) = rest.chars().next() {
+        for (from, to) in replacements {
+            if let Some(after) = rest.strip_prefix(from) {
+                out.push_str(to);
+                rest = after;
+                continue 'outer;
+            }
+        }
+        out.push(c);
+        rest = &rest[c.len_utf8()..];
+    }
+}
+
+/// Formats a float the way `builtins.toJSON` does: the shortest digits that
+/// round-trip, written positionally for decimal exponents between -4 and 15
+/// and in scientific notation with a signed two-digit exponent otherwise.
+fn json_float(v: f64) -> String {
+    if !v.is_finite() {
+        return "null".to_string();
+    }
+
+    let sign = if v.is_sign_negative() { "-" } else { "" };
+    if v == 0.0 {
+        return format!("{}0.0", sign);
+    }
+
+    let scientific = format!("{:e}", v.abs());
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("scientific notation always contains an exponent");
+    let digits = mantissa.replace('.', "");
+    let exponent: i32 = exponent.parse().expect("exponent is an integer");
+
+    let k = digits.len() as i32;
+    let n = exponent + 1;
+
+    let body = if k <= n && n <= 15 {
+        format!("{}{}.0", digits, "0".repeat((n - k) as usize))
+    } else if 0 < n && n <= 15 {
+        let (int, frac) = digits.split_at(n as usize);
+        format!("{}.{}", int, frac)
+    } else if -4 < n && n <= 0 {
+        format!("0.{}{}", "0".repeat(-n as usize), digits)
+    } else {
+        let exp_sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", mantissa, exp_sign, exponent.abs())
+    };
+
+    format!("{}{}", sign, body)
+}
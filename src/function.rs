@@ -0,0 +1,258 @@
+use crate::apply::Apply;
+use crate::content::Content;
+use crate::literal::{NixLiteral, Raw};
+use crate::token::Token;
+use crate::unpack::{ArgKind, Payload};
+use serde::ser::{Error as _, SerializeTuple};
+use serde::{Serialize, Serializer};
+
+/// The attribute set pattern a function takes its arguments with, such as
+/// `{ config, lib, pkgs, ... }`.
+///
+/// Arguments may have a default value, written as `name ? default`, and the
+/// pattern accepts other arguments when it ends with `...`.
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string, NixLiteral, NixPattern};
+///
+/// let pattern = NixPattern::new()
+///     .arg("lib")
+///     .arg_with_default("pkgs", &NixLiteral::new("import <nixpkgs> { }"))
+///     .ellipsis(true);
+///
+/// let result = to_string(&pattern).unwrap();
+/// assert_eq!(result, "{ lib, pkgs ? import <nixpkgs> { }, ... }");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct NixPattern {
+    /// The names of the arguments with their default value, or the message
+    /// of the error recording it failed with
+    args: Vec<(String, Option<Result<Content, String>>)>,
+    ellipsis: bool,
+}
+
+impl NixPattern {
+    /// Creates a pattern without any arguments, written as `{ }`.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the argument `name`.
+    pub fn arg(mut self, name: impl Into<String>) -> Self {
+        self.args.push((name.into(), None));
+        self
+    }
+
+    /// Adds the argument `name`, with a default value written with the
+    /// configuration the pattern is serialized with.
+    ///
+    /// If `default` cannot be serialized, serializing the pattern fails.
+    pub fn arg_with_default<T>(mut self, name: impl Into<String>, default: &T) -> Self
+    where
+        T: ?Sized + Serialize,
+    {
+        self.args
+            .push((name.into(), Some(Content::record(default))));
+        self
+    }
+
+    /// Sets whether the pattern accepts other arguments, written as `...`.
+    pub fn ellipsis(mut self, ellipsis: bool) -> Self {
+        self.ellipsis = ellipsis;
+        self
+    }
+
+    /// Returns the names of the arguments.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.args.iter().map(|(name, _)| name.as_str())
+    }
+
+    /// Returns whether the pattern accepts other arguments.
+    pub fn has_ellipsis(&self) -> bool {
+        self.ellipsis
+    }
+}
+
+impl Serialize for NixPattern {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let token = Token::Unpack(Payload::Pattern {
+            ellipsis: self.ellipsis,
+        });
+        serializer.serialize_newtype_struct(token.name(), &PatternParts(self))
+    }
+}
+
+/// `(name, default, ...)`, with nothing written for missing defaults
+struct PatternParts<'a>(&'a NixPattern);
+
+impl Serialize for PatternParts<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let pattern = self.0;
+        let mut tuple = serializer.serialize_tuple(2 * pattern.args.len())?;
+        for (name, default) in &pattern.args {
+            tuple.serialize_element(name)?;
+            match default {
+                Some(Ok(default)) => tuple.serialize_element(default)?,
+                Some(Err(msg)) => return Err(S::Error::custom(msg)),
+                None => tuple.serialize_element(&Raw(""))?,
+            }
+        }
+        tuple.end()
+    }
+}
+
+/// The argument of a [`NixLambda`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Arg {
+    Name(String),
+    Pattern(NixPattern),
+}
+
+/// A function, written as `arg: body`, or as `{ ... }:` followed by the body
+/// on the next line when it takes its arguments with a [`NixPattern`].
+///
+/// Functions taking several arguments are written by nesting lambdas.
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string, NixLambda, NixLiteral};
+/// use std::collections::BTreeMap;
+///
+/// let mut overlay = BTreeMap::new();
+/// overlay.insert("hello", NixLiteral::new("prev.hello.override { }"));
+///
+/// let overlay = NixLambda::new("final", NixLambda::new("prev", overlay));
+///
+/// let result = to_string(&overlay).unwrap();
+/// assert_eq!(
+///     result,
+///     "final: prev: {\n  hello = prev.hello.override { };\n}"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NixLambda<T> {
+    arg: Arg,
+    body: T,
+}
+
+impl<T> NixLambda<T> {
+    /// Creates a function taking a single argument called `arg`.
+    ///
+    /// Serialization fails if `arg` is not a valid identifier.
+    pub fn new(arg: impl Into<String>, body: T) -> Self {
+        NixLambda {
+            arg: Arg::Name(arg.into()),
+            body,
+        }
+    }
+
+    /// Creates a function taking its arguments with an attribute set
+    /// pattern.
+    pub fn with_pattern(pattern: NixPattern, body: T) -> Self {
+        NixLambda {
+            arg: Arg::Pattern(pattern),
+            body,
+        }
+    }
+
+    /// Returns a reference to the body of the function.
+    pub fn body(&self) -> &T {
+        &self.body
+    }
+
+    /// Converts into the body of the function.
+    pub fn into_body(self) -> T {
+        self.body
+    }
+}
+
+impl<T: Serialize> Serialize for NixLambda<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let (kind, arg) = match &self.arg {
+            Arg::Name(name) => (ArgKind::Name, ArgParts::Name(NixLiteral::new(name))),
+            Arg::Pattern(pattern) => (ArgKind::Pattern, ArgParts::Pattern(pattern)),
+        };
+        let token = Token::Unpack(Payload::Lambda(kind));
+        serializer.serialize_newtype_struct(token.name(), &(arg, &self.body))
+    }
+}
+
+#[derive(Serialize)]
+#[serde(untagged)]
+enum ArgParts<'a> {
+    Name(NixLiteral<'a>),
+    Pattern(&'a NixPattern),
+}
+
+/// A function applied to an argument, written as `function arg`.
+///
+/// The function is parenthesized unless it is a single term or another
+/// application, and the argument unless it is a single term. Functions taking
+/// several arguments are applied with [`NixApply::arg`].
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string, NixApply, NixLiteral, NixPathBuf};
+/// use std::collections::BTreeMap;
+///
+/// let mut args = BTreeMap::new();
+/// args.insert("system", "x86_64-linux");
+///
+/// let import = NixApply::new(NixLiteral::new("import"), NixPathBuf::new("./x.nix")).arg(args);
+///
+/// let result = to_string(&import).unwrap();
+/// assert_eq!(
+///     result,
+///     "import ./x.nix {\n  system = \"x86_64-linux\";\n}"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct NixApply<F, A> {
+    pub function: F,
+    pub arg: A,
+}
+
+impl<F, A> NixApply<F, A> {
+    /// Applies `function` to `arg`.
+    pub fn new(function: F, arg: A) -> Self {
+        NixApply { function, arg }
+    }
+
+    /// Applies the result of this application to another argument.
+    pub fn arg<B>(self, arg: B) -> NixApply<Self, B> {
+        NixApply {
+            function: self,
+            arg,
+        }
+    }
+}
+
+impl<F, A> Serialize for NixApply<F, A>
+where
+    F: Serialize,
+    A: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Apply {
+            function: &self.function,
+            arg: &self.arg,
+        }
+        .serialize(serializer)
+    }
+}
@@ -0,0 +1,76 @@
+use crate::token::Token;
+use serde::{Serialize, Serializer};
+
+/// The value of an attribute taken from the variable of the same name,
+/// written as `inherit name;`, or from an attribute set, written as
+/// `inherit (set) name;`.
+///
+/// Consecutive attributes inherited from the same place are written as a
+/// single `inherit`. Only attributes can be inherited, so serializing a
+/// `NixInherit` anywhere else fails. An attribute path such as
+/// `services.nginx` is written as `services.nginx = set.nginx;` instead,
+/// since `inherit` only takes attribute names.
+///
+/// # Example
+///
+/// ```
+/// use serde::Serialize;
+/// use ser_nix::{to_string, NixInherit};
+///
+/// #[derive(Serialize)]
+/// struct Args {
+///     stdenv: NixInherit,
+///     fetchurl: NixInherit,
+///     #[serde(rename = "mkIf")]
+///     mk_if: NixInherit,
+///     #[serde(rename = "mkForce")]
+///     mk_force: NixInherit,
+/// }
+///
+/// let args = Args {
+///     stdenv: NixInherit::new(),
+///     fetchurl: NixInherit::new(),
+///     mk_if: NixInherit::from_set("lib"),
+///     mk_force: NixInherit::from_set("lib"),
+/// };
+///
+/// let result = to_string(&args).unwrap();
+/// assert_eq!(
+///     result,
+///     "{\n  inherit stdenv fetchurl;\n  inherit (lib) mkIf mkForce;\n}"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct NixInherit {
+    from: Option<String>,
+}
+
+impl NixInherit {
+    /// Inherits the variable in scope with the same name as the attribute.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Inherits the attribute with the same name from the attribute set
+    /// `set`, which is written as is, such as `lib` or `pkgs.python3Packages`.
+    pub fn from_set(set: impl Into<String>) -> Self {
+        NixInherit {
+            from: Some(set.into()),
+        }
+    }
+
+    /// Returns the attribute set the attribute is inherited from, if any.
+    pub fn set(&self) -> Option<&str> {
+        self.from.as_deref()
+    }
+}
+
+impl Serialize for NixInherit {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer
+            .serialize_newtype_struct(Token::Inherit.name(), self.from.as_deref().unwrap_or(""))
+    }
+}
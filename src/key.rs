@@ -0,0 +1,89 @@
+use crate::token::Token;
+use crate::unpack::{KeyKind, Payload};
+use serde::{Serialize, Serializer};
+
+/// An attribute name used as the key of a map, which can be computed by an
+/// expression.
+///
+/// Besides the static names that any string key can be, a `NixKey` can be a
+/// dynamic attribute, written as `${expr}` or as a string interpolating
+/// expressions such as `"prefix-${name}"`. Serialization fails if a dynamic
+/// attribute is used where Nix does not allow it, such as the bindings of a
+/// [`NixLet`](crate::NixLet) or an attribute that is
+/// [inherited](crate::NixInherit), and if a `NixKey` is used anywhere but as
+/// the key of a map.
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string, NixKey};
+/// use std::collections::BTreeMap;
+///
+/// let mut users = BTreeMap::new();
+/// users.insert(NixKey::dynamic("config.user.name"), true);
+/// users.insert(NixKey::interpolated("backup-${hostName}"), false);
+///
+/// let result = to_string(&users).unwrap();
+/// assert_eq!(
+///     result,
+///     "{\n  ${config.user.name} = true;\n  \"backup-${hostName}\" = false;\n}"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NixKey(Repr);
+
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+enum Repr {
+    Static(String),
+    Quoted(String),
+    Dynamic(String),
+    Interpolated(String),
+}
+
+impl NixKey {
+    /// A static name, written unquoted if it is a valid identifier, like any
+    /// string key.
+    pub fn new(name: impl Into<String>) -> Self {
+        NixKey(Repr::Static(name.into()))
+    }
+
+    /// A static name, always written as a quoted string.
+    pub fn quoted(name: impl Into<String>) -> Self {
+        NixKey(Repr::Quoted(name.into()))
+    }
+
+    /// A name computed by the expression `expr`, written as `${expr}`.
+    pub fn dynamic(expr: impl Into<String>) -> Self {
+        NixKey(Repr::Dynamic(expr.into()))
+    }
+
+    /// A name computed by a string interpolating expressions, written as
+    /// `"template"`, such as `"prefix-${name}"`.
+    ///
+    /// The template is written as is, so quotes and backslashes must already
+    /// be escaped.
+    pub fn interpolated(template: impl Into<String>) -> Self {
+        NixKey(Repr::Interpolated(template.into()))
+    }
+
+    /// Returns whether the name is computed by an expression.
+    pub fn is_dynamic(&self) -> bool {
+        matches!(self.0, Repr::Dynamic(_) | Repr::Interpolated(_))
+    }
+}
+
+impl Serialize for NixKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let (kind, text) = match &self.0 {
+            Repr::Static(name) => (KeyKind::Static, name),
+            Repr::Quoted(name) => (KeyKind::Quoted, name),
+            Repr::Dynamic(expr) => (KeyKind::Dynamic, expr),
+            Repr::Interpolated(template) => (KeyKind::Interpolated, template),
+        };
+        let token = Token::Unpack(Payload::Key(kind));
+        serializer.serialize_newtype_struct(token.name(), &(text,))
+    }
+}
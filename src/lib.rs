@@ -13,9 +13,9 @@
 //!
 //! ser_nix can be used to serialise arbitrary rust types into
 //! corresponding nix data types. As the name implies, ser_nix
-//! does *not* provide deserialisation capabilities, as the
-//! process for doing so is non-trivial, and requires evaluating
-//! nix code.
+//! is mainly a serialiser: [`from_str`] reads back data files
+//! such as the ones it writes, but it does not evaluate nix
+//! code, which deserialising arbitrary nix would require.
 //!
 //! ser_nix tries to follow the idioms of other serde libraries,
 //! like [serde_json](https://docs.rs/serde_json/latest/serde_json/index.html).
@@ -68,6 +68,11 @@
 //! // Output: { enabled = null; }
 //! ```
 //!
+//! To leave out every `None` attribute, including those of types you cannot
+//! annotate, use [`Config::none_policy`] with [`NonePolicy::Omit`].
+//! [`NonePolicy::Placeholder`] instead keeps them as commented-out lines like
+//! `# hostName = null;`, which is useful for generating starting configurations.
+//!
 //! ## Nix paths
 //!
 //! In Nix, paths like `./foo.nix` or `/etc/nixos/configuration.nix` are written
@@ -126,22 +131,178 @@
 //! // source is unquoted: ./hardware-configuration.nix
 //! // description is quoted: "Hardware config"
 //! ```
+//!
+//! ## `#[derive(NixSerialize)]`
+//!
+//! With the `derive` feature enabled, `#[derive(NixSerialize)]` can be used
+//! instead of `#[derive(Serialize)]` to mark fields with `#[nix(path)]`,
+//! `#[nix(literal)]`, `#[nix(indented)]`, `#[nix(comment = "...")]` or
+//! `#[nix(attr_path)]`. These apply through `Option`, `Vec` and maps, so no
+//! `serialize_with` helper is needed for each shape of field.
+//!
+//! ## NixOS options
+//!
+//! The [`NixOptions`] trait declares the options of a NixOS module as
+//! `lib.mkOption { ... }` calls, with types from [`NixType`]. With the `derive`
+//! feature, `#[derive(NixOptions)]` implements it for a configuration struct,
+//! rendering the defaults with [`to_string`].
+//!
+//! Definitions of options can be given a priority with wrappers such as
+//! [`MkForce`] and [`MkDefault`], written as `lib.mkForce value`, and made
+//! conditional or combined with [`MkIf`] and [`MkMerge`]. A whole file is
+//! written as a module taking `{ config, lib, pkgs, ... }` with [`NixModule`].
+//!
+//! With the `json-schema` feature, `options_from_json_schema` converts a JSON
+//! Schema document, such as one generated by
+//! [schemars](https://docs.rs/schemars), into the same options.
+//!
+//! ## Formatting presets
+//!
+//! The layout of the output can be adjusted with a [`Config`]. For example,
+//! [`Config::nixfmt`] produces output that
+//! [nixfmt](https://github.com/NixOS/nixfmt) leaves untouched, and
+//! [`Config::to_pretty`] reproduces the layout of nixpkgs'
+//! `lib.generators.toPretty { }`:
+//!
+//! ```rust
+//! use ser_nix::{to_string_with_config, Config};
+//! use std::collections::BTreeMap;
+//!
+//! let mut hosts: BTreeMap<&str, (u8, u8)> = BTreeMap::new();
+//! hosts.insert("alpha", (1, 2));
+//!
+//! let serialized = to_string_with_config(&hosts, &Config::nixfmt()).unwrap();
+//!
+//! let expected = "{\n  alpha = [\n    1\n    2\n  ];\n}\n";
+//!
+//! assert_eq!(serialized, expected);
+//! ```
+//!
+//! Other layouts, such as blank lines between attributes or aligned `=` signs,
+//! can be implemented with a custom [`Formatter`] and passed to
+//! [`Config::formatter`].
+//!
+//! ## Comments
+//!
+//! Wrapping a value in [`NixCommented`] writes a comment above the attribute
+//! it is the value of, and [`Config::header`] adds a comment at the top of the
+//! output:
+//!
+//! ```rust
+//! use serde::Serialize;
+//! use ser_nix::{to_string_with_config, Config, NixCommented};
+//!
+//! #[derive(Serialize)]
+//! struct Firewall {
+//!     allowed_ports: NixCommented<Vec<u16>>,
+//! }
+//!
+//! let firewall = Firewall {
+//!     allowed_ports: NixCommented::block("SSH\nHTTPS", vec![22, 443]),
+//! };
+//!
+//! let config = Config::new().header("Generated file, do not edit.");
+//! let serialized = to_string_with_config(&firewall, &config).unwrap();
+//!
+//! let expected = "# Generated file, do not edit.\n{\n  /*\n    SSH\n    HTTPS\n  */\n  allowed_ports = [\n    22\n    443\n  ];\n}";
+//!
+//! assert_eq!(serialized, expected);
+//! ```
+//!
+//! ## Functions
+//!
+//! Functions such as overlays are written with [`NixLambda`], taking a single
+//! argument or a [`NixPattern`], and applied to arguments with [`NixApply`].
+//! Both nest with the indentation of the surrounding value, and are
+//! parenthesized where Nix requires it.
+//!
+//! Names are bound with [`NixLet`], and attributes taken from the variables
+//! in scope with [`NixInherit`]. The attributes of a [`NixRec`] can refer to
+//! each other with [`NixAttrRef`], and [`NixWith`] brings the attributes of a
+//! set into scope. Large values that are written several times can also be
+//! bound once in a `let` enclosing the output, with
+//! [`Config::share_repeated`].
+//!
+//! Expressions that cannot be written as serialized values, such as
+//! conditionals and operators, are built with the typed syntax tree of the
+//! [`expr`] module.
+mod apply;
+mod comment;
+mod conditional;
+mod config;
+mod content;
+mod data;
+mod de;
 mod error;
+pub mod expr;
+mod format;
+mod function;
+mod inherit;
+mod key;
 mod literal;
 mod map;
+mod mode;
+mod module;
+mod options;
 mod path;
+mod placeholder;
+mod priority;
+mod rec;
+#[cfg(feature = "json-schema")]
+mod schema;
+mod scope;
+mod select;
 mod seq;
 mod ser;
+mod share;
 mod r#struct;
+mod syntax;
+#[allow(clippy::module_inception)]
 mod test;
+mod token;
 mod tuple;
+mod unpack;
+mod value;
+mod with;
 
+pub use comment::{CommentStyle, NixCommented};
+pub use conditional::{MkIf, MkMerge};
+pub use config::{Config, NonePolicy};
+pub use de::{from_str, from_value};
 pub use error::Error;
+pub use format::{Context, DefaultFormatter, Formatter, NixfmtFormatter, ToPrettyFormatter};
+pub use function::{NixApply, NixLambda, NixPattern};
+pub use inherit::NixInherit;
+pub use key::NixKey;
 pub use literal::{NixLiteral, as_literal, as_literal_seq, as_optional_literal};
+pub use module::NixModule;
+pub use options::{NixOption, NixOptionSet, NixOptionType, NixOptions, NixType};
 pub use path::{NixPath, NixPathBuf, as_nix_path, as_optional_nix_path};
+pub use placeholder::as_hinted_option;
+pub use priority::{MkAfter, MkBefore, MkDefault, MkForce, MkOrder, MkOverride};
+pub use rec::{NixAttrRef, NixRec};
+#[cfg(feature = "json-schema")]
+pub use schema::options_from_json_schema;
+pub use scope::NixLet;
+pub use select::NixAttrSelect;
 use ser::Serializer;
+#[cfg(feature = "derive")]
+pub use ser_nix_derive::{NixOptions, NixSerialize};
+use share::Sharing;
+pub use value::{Value, parse};
+pub use with::NixWith;
 
 use serde::Serialize;
+use std::cell::RefCell;
+
+// Lets the code generated by `ser_nix_derive` name this crate from within it
+extern crate self as ser_nix;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use crate::mode::{ATTR_PATH, INDENTED, LITERAL, Mode, PATH};
+    pub use serde;
+}
 
 /// Serialise the given data structure as a String of Nix data
 ///
@@ -151,23 +312,46 @@ use serde::Serialize;
 /// fails.
 pub fn to_string<T>(value: &T) -> Result<String, Error>
 where
-    T: Serialize,
+    T: ?Sized + Serialize,
+{
+    to_string_with_config(value, &Config::default())
+}
+
+/// Serialise the given data structure as a String of Nix data, using the
+/// layout described by `config`
+///
+/// # Errors
+///
+/// Serialization can fail if the implemenatation of `Serialize` for `T`
+/// fails.
+pub fn to_string_with_config<T>(value: &T, config: &Config) -> Result<String, Error>
+where
+    T: ?Sized + Serialize,
 {
-    let mut serializer = Serializer {
-        output: String::new(),
-        pending_key: None,
-        indent_depth: 0,
-    };
+    let mut serializer = Serializer::new(config);
+    let sharing = config
+        .share_repeated
+        .map(|min_lines| RefCell::new(Sharing::new(min_lines)));
+    if let Some(sharing) = &sharing {
+        // The first pass finds the repeated values, the second replaces them
+        serializer.env.sharing = Some(sharing);
+        value.serialize(&mut serializer)?;
+        sharing.borrow_mut().plan();
+        serializer = Serializer::new(config);
+        serializer.env.sharing = Some(sharing);
+    }
     value.serialize(&mut serializer)?;
 
-    Ok(post_processor(&serializer.output))
-}
+    let mut output = String::new();
+    if let Some(header) = &config.header {
+        config
+            .formatter
+            .write_comment(&mut output, header, CommentStyle::Line, 0);
+    }
+    let body = std::mem::take(&mut serializer.output);
+    serializer.write_shared_bindings();
+    output += &serializer.output;
+    output += &body;
 
-/// Removes extra whitespace that gets left behind due to indentation
-fn post_processor(serialized: &str) -> String {
-    serialized
-        .lines()
-        .map(|l| if l.chars().any(|c| c != ' ') { l } else { "" })
-        .collect::<Vec<_>>()
-        .join("\n")
+    Ok(config.formatter.finish(output))
 }
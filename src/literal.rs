@@ -1,8 +1,9 @@
+use crate::error::Error;
+use crate::token::Token;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
 use serde::{Serialize, Serializer, ser::SerializeSeq};
 use std::borrow::Cow;
 
-pub(crate) const TOKEN: &str = "$ser_nix::private::Literal";
-
 /// A raw Nix expression that serializes without quotes.
 ///
 /// Use this wrapper type when you want to output a raw Nix expression
@@ -26,6 +27,13 @@ pub(crate) const TOKEN: &str = "$ser_nix::private::Literal";
 /// let result = to_string(&config).unwrap();
 /// assert!(result.contains("package = pkgs.hello;"));
 /// ```
+///
+/// The expression is written as is, except that the common indentation of
+/// its lines after the first is replaced with the depth it is written at,
+/// leaving the lines inside strings alone. [`NixLiteral::parse`] checks that
+/// it is a single complete Nix expression, and
+/// [`Config::strict_literals`](crate::Config::strict_literals) checks every
+/// literal when it is serialized.
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct NixLiteral<'a>(Cow<'a, str>);
 
@@ -35,6 +43,46 @@ impl<'a> NixLiteral<'a> {
         NixLiteral(Cow::Borrowed(expr))
     }
 
+    /// Creates a new `NixLiteral` from a string slice, failing if it is not a
+    /// single complete Nix expression.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ser_nix::NixLiteral;
+    ///
+    /// assert!(NixLiteral::try_new("lib.mkForce true").is_ok());
+    ///
+    /// let error = NixLiteral::try_new("[ pkgs.git").unwrap_err();
+    /// assert_eq!(
+    ///     error.to_string(),
+    ///     "invalid Nix literal `[ pkgs.git`: expected `]`, found end of input at column 11"
+    /// );
+    /// ```
+    pub fn try_new(expr: &'a str) -> Result<Self, Error> {
+        validate(expr, None)?;
+        Ok(NixLiteral(Cow::Borrowed(expr)))
+    }
+
+    /// Creates an owned `NixLiteral`, failing if `expr` is not a single
+    /// complete Nix expression.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use ser_nix::NixLiteral;
+    ///
+    /// let literal = NixLiteral::parse(format!("pkgs.{}", "hello")).unwrap();
+    /// assert_eq!(literal.as_str(), "pkgs.hello");
+    ///
+    /// assert!(NixLiteral::parse("{ a = 1 }").is_err());
+    /// ```
+    pub fn parse(expr: impl Into<String>) -> Result<NixLiteral<'static>, Error> {
+        let expr = expr.into();
+        validate(&expr, None)?;
+        Ok(NixLiteral(Cow::Owned(expr)))
+    }
+
     /// Returns the underlying expression as a string slice.
     pub fn as_str(&self) -> &str {
         &self.0
@@ -70,11 +118,133 @@ impl AsRef<str> for NixLiteral<'_> {
     }
 }
 
+/// Checks that `expr` is a single complete Nix expression, naming the
+/// attribute `field` holding it in the error
+pub(crate) fn validate(expr: &str, field: Option<&str>) -> Result<(), Error> {
+    let Err(e) = crate::syntax::parse(expr) else {
+        return Ok(());
+    };
+    let msg = match field {
+        Some(field) => format!("invalid Nix literal `{expr}` in `{field}`: {e}"),
+        None => format!("invalid Nix literal `{expr}`: {e}"),
+    };
+    Err(serde::ser::Error::custom(msg))
+}
+
+/// Writes `expr` to `out`, replacing the common indentation of its lines
+/// after the first with `indent`, except for the lines inside strings
+pub(crate) fn reindent_into(expr: &str, indent: &str, out: &mut String) {
+    if !expr.contains('\n') {
+        *out += expr;
+        return;
+    }
+
+    let in_string = lines_in_string(expr);
+    let lines = || expr.split('\n').zip(in_string.iter().copied()).skip(1);
+    let spaces = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+    let common = lines()
+        .filter(|(line, in_string)| !in_string && !line.trim().is_empty())
+        .map(|(line, _)| spaces(line))
+        .min()
+        .unwrap_or(0);
+
+    *out += expr.split('\n').next().unwrap_or_default();
+    for (line, in_string) in lines() {
+        out.push('\n');
+        if in_string {
+            *out += line;
+        } else if !line.trim().is_empty() {
+            *out += indent;
+            *out += &line[common..];
+        }
+    }
+}
+
+/// Returns, for each line of `expr`, whether it starts inside a string
+pub(crate) fn lines_in_string(expr: &str) -> Vec<bool> {
+    enum Context {
+        /// Code, with the number of braces opened in it
+        Code(usize),
+        Str,
+        IndStr,
+    }
+
+    let bytes = expr.as_bytes();
+    let mut stack = vec![Context::Code(0)];
+    let mut lines = vec![false];
+    let mut i = 0;
+    while i < bytes.len() {
+        let rest = &bytes[i..];
+        // An escaped newline is left to be counted as a line of the string
+        let escape = |len: usize| match rest.get(len) {
+            Some(b'\n') => len,
+            _ => len + 1,
+        };
+        let in_string = matches!(stack.last(), Some(Context::Str | Context::IndStr));
+        let nested = stack.len() > 1;
+        let len = match stack.last_mut().expect("context") {
+            _ if rest[0] == b'\n' => {
+                lines.push(in_string);
+                1
+            }
+            Context::Code(_) if rest[0] == b'#' => {
+                rest.iter().position(|&b| b == b'\n').unwrap_or(rest.len())
+            }
+            Context::Code(_) if rest.starts_with(b"/*") => {
+                let end = expr[i + 2..].find("*/").map_or(rest.len(), |end| end + 4);
+                let newlines = expr[i..i + end].matches('\n').count();
+                lines.extend(std::iter::repeat_n(false, newlines));
+                end
+            }
+            Context::Code(_) if rest[0] == b'"' => {
+                stack.push(Context::Str);
+                1
+            }
+            Context::Code(_) if rest.starts_with(b"''") => {
+                stack.push(Context::IndStr);
+                2
+            }
+            Context::Code(braces) if rest[0] == b'{' => {
+                *braces += 1;
+                1
+            }
+            Context::Code(0) if rest[0] == b'}' && nested => {
+                stack.pop();
+                1
+            }
+            Context::Code(braces) if rest[0] == b'}' => {
+                *braces = braces.saturating_sub(1);
+                1
+            }
+            Context::Code(_) => 1,
+            _ if rest.starts_with(b"${") => {
+                stack.push(Context::Code(0));
+                2
+            }
+            _ if rest.starts_with(b"$$") => 2,
+            Context::Str if rest[0] == b'\\' => escape(1),
+            Context::Str if rest[0] == b'"' => {
+                stack.pop();
+                1
+            }
+            Context::IndStr if rest.starts_with(b"'''") || rest.starts_with(b"''$") => 3,
+            Context::IndStr if rest.starts_with(b"''\\") => escape(3),
+            Context::IndStr if rest.starts_with(b"''") => {
+                stack.pop();
+                2
+            }
+            _ => 1,
+        };
+        i += len;
+    }
+    lines
+}
+
 fn serialize_literal<S>(expr: &str, serializer: S) -> Result<S::Ok, S::Error>
 where
     S: Serializer,
 {
-    serializer.serialize_newtype_struct(TOKEN, expr)
+    serializer.serialize_newtype_struct(Token::Literal.name(), expr)
 }
 
 impl Serialize for NixLiteral<'_> {
@@ -86,6 +256,44 @@ impl Serialize for NixLiteral<'_> {
     }
 }
 
+/// Reads the text of an expression, which [`from_str`](crate::from_str) gives
+/// for any value
+impl<'de> Deserialize<'de> for NixLiteral<'_> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct LiteralVisitor;
+
+        impl<'de> Visitor<'de> for LiteralVisitor {
+            type Value = String;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a Nix expression")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<String, E> {
+                Ok(v.to_string())
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<String, E> {
+                Ok(v)
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<String, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                String::deserialize(deserializer)
+            }
+        }
+
+        let expr =
+            deserializer.deserialize_newtype_struct(Token::Literal.name(), LiteralVisitor)?;
+        Ok(NixLiteral(Cow::Owned(expr)))
+    }
+}
+
 /// Serialize a string as a raw Nix expression (without quotes).
 ///
 /// Use this function with `#[serde(serialize_with = "...")]` to serialize
@@ -192,3 +400,32 @@ where
     }
     seq.end()
 }
+
+/// A value written as a string holding its Nix source, such as the argument
+/// of `lib.literalExpression`.
+pub(crate) struct Source<'a, T: ?Sized>(pub &'a T);
+
+impl<T> Serialize for Source<'_, T>
+where
+    T: ?Sized + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(Token::Source.name(), self.0)
+    }
+}
+
+/// A fragment of Nix written by this crate, which is written as is without
+/// being checked like a [`NixLiteral`].
+pub(crate) struct Raw<'a>(pub &'a str);
+
+impl Serialize for Raw<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(Token::Raw.name(), self.0)
+    }
+}
@@ -1,14 +1,24 @@
 use super::error::Error;
-use super::ser::Serializer;
+use super::ser::{Role, Serializer};
+use super::unpack::KeyKind;
 
 use serde::{Serialize, ser};
 
+/// The keywords of Nix, which cannot be used as identifiers
+pub(crate) const KEYWORDS: [&str; 10] = [
+    "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+];
+
 /// Returns `true` if `s` is a valid Nix identifier that can appear unquoted
 /// as an attribute name (e.g. `foo`, `build-inputs`, `x86_64-linux`).
 ///
-/// Keys that are not valid identifiers (e.g. `8080/tcp`, `/var/data`) must
-/// remain quoted in Nix attribute sets.
-fn is_nix_identifier(s: &str) -> bool {
+/// Keys that are not valid identifiers (e.g. `8080/tcp`, `/var/data`) or
+/// that are keywords (e.g. `let`, `in`) must remain quoted in Nix attribute
+/// sets.
+pub(crate) fn is_nix_identifier(s: &str) -> bool {
+    if KEYWORDS.contains(&s) {
+        return false;
+    }
     let mut chars = s.chars();
     match chars.next() {
         Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
@@ -17,7 +27,7 @@ fn is_nix_identifier(s: &str) -> bool {
     chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '\'')
 }
 
-impl ser::SerializeMap for &mut Serializer {
+impl ser::SerializeMap for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -25,26 +35,28 @@ impl ser::SerializeMap for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        let mut key_serializer = Serializer {
-            output: String::new(),
-            pending_key: None,
-            indent_depth: self.indent_depth,
-        };
+        let mut key_serializer = self.nested();
+        key_serializer.role = Role::MapKey;
         key.serialize(&mut key_serializer)?;
         let mut base_key = key_serializer.output;
 
         // Only strip quotes from keys that are valid Nix identifiers.
         // Keys like "8080/tcp" must remain quoted because they contain
         // characters not allowed in bare Nix attribute names.
-        if let Some(stripped) = base_key
-            .strip_prefix("\"")
-            .and_then(|s| s.strip_suffix("\""))
+        if key_serializer.key_kind.is_none()
+            && let Some(stripped) = base_key
+                .strip_prefix("\"")
+                .and_then(|s| s.strip_suffix("\""))
             && is_nix_identifier(stripped)
         {
             base_key = stripped.to_string();
         }
 
-        self.pending_key = Some(base_key);
+        let dynamic = matches!(
+            key_serializer.key_kind,
+            Some(KeyKind::Dynamic | KeyKind::Interpolated)
+        );
+        self.pending_key = Some((base_key, dynamic));
         Ok(())
     }
 
@@ -52,28 +64,15 @@ impl ser::SerializeMap for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        let mut value_serializer = Serializer {
-            output: String::new(),
-            pending_key: None,
-            indent_depth: self.indent_depth,
-        };
-
-        value.serialize(&mut value_serializer)?;
-        let val = value_serializer.output;
-
-        self.output += "\n";
-
-        self.indent();
-        let key = self.pending_key.take().expect("Value without key.");
-        self.output += &format!("{} = {};", key, val);
-        Ok(())
+        let (key, dynamic) = self.pending_key.take().expect("Value without key.");
+        match dynamic {
+            true => self.push_dynamic_attr(key, value),
+            false => self.push_attr(key, value),
+        }
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.indent_depth -= 1;
-        self.output += "\n";
-        self.indent();
-        self.output += "}";
+        self.write_attrset();
         Ok(())
     }
 }
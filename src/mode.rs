@@ -0,0 +1,46 @@
+use serde::{Serialize, Serializer};
+
+pub const PATH: &str = "$ser_nix::private::PathMode";
+pub const LITERAL: &str = "$ser_nix::private::LiteralMode";
+pub const INDENTED: &str = "$ser_nix::private::IndentedMode";
+pub const ATTR_PATH: &str = "$ser_nix::private::AttrPath";
+
+/// How the strings inside a value are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StringMode {
+    #[default]
+    Quoted,
+    /// As Nix paths, like [`NixPathBuf`](crate::NixPathBuf)
+    Path,
+    /// As raw Nix expressions, like [`NixLiteral`](crate::NixLiteral)
+    Literal,
+    /// As indented `''` strings
+    Indented,
+}
+
+/// Applies the mode named by `token` to a value, for the code generated by
+/// `#[derive(NixSerialize)]`.
+///
+/// Serializers other than ser_nix see the value unchanged.
+pub struct Mode<'a, T: ?Sized> {
+    token: &'static str,
+    value: &'a T,
+}
+
+impl<'a, T: ?Sized> Mode<'a, T> {
+    pub fn new(token: &'static str, value: &'a T) -> Self {
+        Mode { token, value }
+    }
+}
+
+impl<T> Serialize for Mode<'_, T>
+where
+    T: ?Sized + Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(self.token, self.value)
+    }
+}
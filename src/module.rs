@@ -0,0 +1,128 @@
+use crate::function::NixPattern;
+use crate::path::NixPathBuf;
+use crate::token::Token;
+use crate::unpack::Payload;
+use serde::ser::SerializeTuple;
+use serde::{Serialize, Serializer};
+
+/// A NixOS module, written as a function from its arguments to an attribute
+/// set: `{ config, lib, pkgs, ... }: { ... }`.
+///
+/// The body must serialize to an attribute set, such as a struct or a map.
+/// The paths added with [`NixModule::import`] are written as its `imports`
+/// attribute, before the attributes of the body.
+///
+/// # Example
+///
+/// ```
+/// use serde::Serialize;
+/// use ser_nix::{to_string, NixModule};
+///
+/// #[derive(Serialize)]
+/// struct Host {
+///     networking: Networking,
+/// }
+///
+/// #[derive(Serialize)]
+/// #[serde(rename_all = "camelCase")]
+/// struct Networking {
+///     host_name: String,
+/// }
+///
+/// let host = Host {
+///     networking: Networking { host_name: "alpha".into() },
+/// };
+/// let module = NixModule::new(host).import("./hardware-configuration.nix");
+///
+/// let result = to_string(&module).unwrap();
+///
+/// let expected = concat!(
+///     "{ config, lib, pkgs, ... }:\n",
+///     "{\n",
+///     "  imports = [\n",
+///     "    ./hardware-configuration.nix\n",
+///     "  ];\n",
+///     "  networking = {\n",
+///     "    hostName = \"alpha\";\n",
+///     "  };\n",
+///     "}",
+/// );
+///
+/// assert_eq!(result, expected);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NixModule<T> {
+    args: NixPattern,
+    imports: Vec<NixPathBuf>,
+    body: T,
+}
+
+impl<T> NixModule<T> {
+    /// Creates a module taking `{ config, lib, pkgs, ... }`.
+    pub fn new(body: T) -> Self {
+        NixModule {
+            args: NixPattern::new()
+                .arg("config")
+                .arg("lib")
+                .arg("pkgs")
+                .ellipsis(true),
+            imports: Vec::new(),
+            body,
+        }
+    }
+
+    /// Replaces the arguments of the module.
+    pub fn args(mut self, args: NixPattern) -> Self {
+        self.args = args;
+        self
+    }
+
+    /// Adds a path to the `imports` of the module.
+    ///
+    /// The body must not have an `imports` attribute of its own then, or
+    /// serializing the module fails.
+    pub fn import(mut self, path: impl Into<NixPathBuf>) -> Self {
+        self.imports.push(path.into());
+        self
+    }
+
+    /// Returns a reference to the body of the module.
+    pub fn body(&self) -> &T {
+        &self.body
+    }
+
+    /// Converts into the body of the module.
+    pub fn into_body(self) -> T {
+        self.body
+    }
+}
+
+impl<T: Serialize> Serialize for NixModule<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer
+            .serialize_newtype_struct(Token::Unpack(Payload::Module).name(), &ModuleParts(self))
+    }
+}
+
+/// `(args, imports, body)`, leaving out empty imports
+struct ModuleParts<'a, T>(&'a NixModule<T>);
+
+impl<T: Serialize> Serialize for ModuleParts<'_, T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let module = self.0;
+        let imports = !module.imports.is_empty();
+        let mut tuple = serializer.serialize_tuple(2 + imports as usize)?;
+        tuple.serialize_element(&module.args)?;
+        if imports {
+            tuple.serialize_element(&module.imports)?;
+        }
+        tuple.serialize_element(&module.body)?;
+        tuple.end()
+    }
+}
@@ -0,0 +1,377 @@
+use crate::apply::{Apply, Lib, Term};
+use crate::content::Content;
+use crate::literal::{NixLiteral, Source};
+use crate::path::{NixPath, NixPathBuf};
+use crate::value::Value;
+use serde::ser::{Error as _, SerializeMap};
+use serde::{Serialize, Serializer};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// Types that can declare the NixOS options of a module.
+///
+/// Implemented by `#[derive(NixOptions)]` with the `derive` feature, which
+/// also implements [`NixType`] as a `lib.types.submodule` of the options.
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string, NixOption, NixOptionSet, NixOptions, NixType};
+///
+/// struct Service {
+///     port: u16,
+/// }
+///
+/// impl NixOptions for Service {
+///     fn nix_options() -> NixOptionSet {
+///         let mut options = NixOptionSet::new();
+///         options.push(
+///             "port",
+///             NixOption::new(u16::nix_type())
+///                 .default(&8080)
+///                 .description("Port to listen on"),
+///         );
+///         options
+///     }
+/// }
+///
+/// let expected = concat!(
+///     "{\n",
+///     "  port = lib.mkOption {\n",
+///     "    type = lib.types.ints.u16;\n",
+///     "    default = 8080;\n",
+///     "    description = \"Port to listen on\";\n",
+///     "  };\n",
+///     "}",
+/// );
+///
+/// assert_eq!(to_string(&Service::nix_options()).unwrap(), expected);
+/// ```
+pub trait NixOptions {
+    /// Returns the option declarations, in the order they are written.
+    fn nix_options() -> NixOptionSet;
+}
+
+/// Rust types with a corresponding NixOS option type.
+pub trait NixType {
+    /// Returns the option type values of this type are checked against.
+    fn nix_type() -> NixOptionType;
+}
+
+/// The type of a NixOS option, written as a `lib.types` expression.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum NixOptionType {
+    /// A type of `lib.types` taking no arguments, such as `bool` or `ints.u8`.
+    Named(Cow<'static, str>),
+    /// `lib.types.listOf` the element type.
+    ListOf(Box<NixOptionType>),
+    /// `lib.types.attrsOf` the value type.
+    AttrsOf(Box<NixOptionType>),
+    /// `lib.types.nullOr` the type.
+    NullOr(Box<NixOptionType>),
+    /// `lib.types.enum` of the allowed values.
+    Enum(Vec<Value>),
+    /// `lib.types.oneOf` the types.
+    OneOf(Vec<NixOptionType>),
+    /// `lib.types.submodule` with the given options.
+    Submodule(NixOptionSet),
+    /// Any Nix expression evaluating to an option type, written as is.
+    Custom(String),
+}
+
+impl NixOptionType {
+    fn named(name: &'static str) -> Self {
+        NixOptionType::Named(Cow::Borrowed(name))
+    }
+}
+
+impl Serialize for NixOptionType {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        use NixOptionType::*;
+
+        match self {
+            Named(name) => Lib(&format!("types.{name}")).serialize(serializer),
+            Custom(expr) => NixLiteral::from(expr).serialize(serializer),
+            ListOf(inner) => apply("types.listOf", inner, serializer),
+            AttrsOf(inner) => apply("types.attrsOf", inner, serializer),
+            NullOr(inner) => apply("types.nullOr", inner, serializer),
+            Enum(values) => apply("types.enum", values, serializer),
+            OneOf(types) => {
+                let types: Vec<_> = types.iter().map(Term).collect();
+                apply("types.oneOf", &types, serializer)
+            }
+            Submodule(options) => {
+                let mut arg = BTreeMap::new();
+                arg.insert("options", options);
+                apply("types.submodule", &arg, serializer)
+            }
+        }
+    }
+}
+
+/// Applies the `lib` function `function` to `arg`
+fn apply<T, S>(function: &str, arg: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: ?Sized + Serialize,
+    S: Serializer,
+{
+    Apply {
+        function: &Lib(function),
+        arg,
+    }
+    .serialize(serializer)
+}
+
+/// A single option declaration, written as `lib.mkOption { ... }`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NixOption {
+    ty: NixOptionType,
+    /// The default value, written by the serializer of the option, or the
+    /// message of the error recording it failed with
+    default: Option<Result<Content, String>>,
+    /// The example value, recorded like `default`
+    example: Option<Result<Content, String>>,
+    description: Option<String>,
+}
+
+impl NixOption {
+    /// Creates an option of the given type, without a default or description.
+    pub fn new(ty: NixOptionType) -> Self {
+        NixOption {
+            ty,
+            default: None,
+            example: None,
+            description: None,
+        }
+    }
+
+    /// Sets the default value of the option, written with the configuration
+    /// the option is serialized with.
+    ///
+    /// If `value` cannot be serialized, serializing the option fails.
+    pub fn default<T>(mut self, value: &T) -> Self
+    where
+        T: ?Sized + Serialize,
+    {
+        self.default = Some(Content::record(value));
+        self
+    }
+
+    /// Sets an example value of the option, written as a
+    /// `lib.literalExpression` of its source with the configuration the
+    /// option is serialized with.
+    ///
+    /// If `value` cannot be serialized, serializing the option fails.
+    pub fn example<T>(mut self, value: &T) -> Self
+    where
+        T: ?Sized + Serialize,
+    {
+        self.example = Some(Content::record(value));
+        self
+    }
+
+    /// Sets the description of the option.
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = Some(description.into());
+        self
+    }
+
+    /// Returns the type of the option.
+    pub fn option_type(&self) -> &NixOptionType {
+        &self.ty
+    }
+}
+
+/// The attrset passed to `lib.mkOption`
+struct OptionAttrs<'a>(&'a NixOption);
+
+impl Serialize for OptionAttrs<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let option = self.0;
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("type", &option.ty)?;
+        match &option.default {
+            Some(Ok(default)) => map.serialize_entry("default", default)?,
+            Some(Err(msg)) => return Err(S::Error::custom(msg)),
+            None => {}
+        }
+        match &option.example {
+            Some(Ok(example)) => {
+                let example = Apply {
+                    function: &Lib("literalExpression"),
+                    arg: &Source(example),
+                };
+                map.serialize_entry("example", &example)?
+            }
+            Some(Err(msg)) => return Err(S::Error::custom(msg)),
+            None => {}
+        }
+        if let Some(description) = &option.description {
+            map.serialize_entry("description", description)?;
+        }
+        map.end()
+    }
+}
+
+impl Serialize for NixOption {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        apply("mkOption", &OptionAttrs(self), serializer)
+    }
+}
+
+/// Named option declarations, written as an attribute set in the order they
+/// were added.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NixOptionSet {
+    options: Vec<(String, NixOption)>,
+}
+
+impl NixOptionSet {
+    /// Creates an empty set of options.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the option `name`.
+    pub fn push(&mut self, name: impl Into<String>, option: NixOption) {
+        self.options.push((name.into(), option));
+    }
+
+    /// Iterates over the options in the order they were added.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &NixOption)> {
+        self.options
+            .iter()
+            .map(|(name, option)| (name.as_str(), option))
+    }
+
+    /// Returns the number of options.
+    pub fn len(&self) -> usize {
+        self.options.len()
+    }
+
+    /// Whether there are no options.
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty()
+    }
+}
+
+impl Serialize for NixOptionSet {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_map(self.iter())
+    }
+}
+
+macro_rules! named_types {
+    ($($name:literal => $($ty:ty),+;)*) => {
+        $($(
+            impl NixType for $ty {
+                fn nix_type() -> NixOptionType {
+                    NixOptionType::named($name)
+                }
+            }
+        )+)*
+    };
+}
+
+named_types! {
+    "bool" => bool;
+    "int" => i64, i128, isize;
+    "ints.s8" => i8;
+    "ints.s16" => i16;
+    "ints.s32" => i32;
+    "ints.u8" => u8;
+    "ints.u16" => u16;
+    "ints.u32" => u32;
+    "ints.unsigned" => u64, u128, usize;
+    "float" => f32, f64;
+    "str" => str, String, char;
+    "path" => Path, PathBuf, NixPathBuf, NixPath<'_>;
+}
+
+impl<T: NixType> NixType for Vec<T> {
+    fn nix_type() -> NixOptionType {
+        NixOptionType::ListOf(Box::new(T::nix_type()))
+    }
+}
+
+impl<T: NixType> NixType for VecDeque<T> {
+    fn nix_type() -> NixOptionType {
+        NixOptionType::ListOf(Box::new(T::nix_type()))
+    }
+}
+
+impl<T: NixType> NixType for BTreeSet<T> {
+    fn nix_type() -> NixOptionType {
+        NixOptionType::ListOf(Box::new(T::nix_type()))
+    }
+}
+
+impl<K, V: NixType> NixType for BTreeMap<K, V> {
+    fn nix_type() -> NixOptionType {
+        NixOptionType::AttrsOf(Box::new(V::nix_type()))
+    }
+}
+
+impl<T: NixType> NixType for Option<T> {
+    fn nix_type() -> NixOptionType {
+        NixOptionType::NullOr(Box::new(T::nix_type()))
+    }
+}
+
+impl<T: NixType> NixType for [T] {
+    fn nix_type() -> NixOptionType {
+        NixOptionType::ListOf(Box::new(T::nix_type()))
+    }
+}
+
+impl<T: NixType, S> NixType for HashSet<T, S> {
+    fn nix_type() -> NixOptionType {
+        NixOptionType::ListOf(Box::new(T::nix_type()))
+    }
+}
+
+impl<K, V: NixType, S> NixType for HashMap<K, V, S> {
+    fn nix_type() -> NixOptionType {
+        NixOptionType::AttrsOf(Box::new(V::nix_type()))
+    }
+}
+
+impl<T: ?Sized + NixType> NixType for &T {
+    fn nix_type() -> NixOptionType {
+        T::nix_type()
+    }
+}
+
+impl<T: ?Sized + NixType> NixType for Box<T> {
+    fn nix_type() -> NixOptionType {
+        T::nix_type()
+    }
+}
+
+impl<T: ?Sized + NixType> NixType for Rc<T> {
+    fn nix_type() -> NixOptionType {
+        T::nix_type()
+    }
+}
+
+impl<T: ?Sized + NixType> NixType for Arc<T> {
+    fn nix_type() -> NixOptionType {
+        T::nix_type()
+    }
+}
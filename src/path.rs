@@ -1,11 +1,11 @@
 use crate::error::Error;
+use crate::token::Token;
+use serde::de::{self, Deserialize, Deserializer, Visitor};
 use serde::{Serialize, Serializer, ser};
 use std::borrow::Cow;
 use std::ops::Deref;
 use std::path::{Component, Path, PathBuf};
 
-pub(crate) const TOKEN: &str = "$ser_nix::private::Path";
-
 /// Internal serializer that emits raw strings without quoting.
 pub(crate) struct RawEmitter<'a> {
     pub output: &'a mut String,
@@ -265,6 +265,40 @@ impl Serialize for NixPathBuf {
     }
 }
 
+impl<'de> Deserialize<'de> for NixPathBuf {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct PathVisitor;
+
+        impl<'de> Visitor<'de> for PathVisitor {
+            type Value = NixPathBuf;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a path")
+            }
+
+            fn visit_str<E: de::Error>(self, v: &str) -> Result<NixPathBuf, E> {
+                Ok(NixPathBuf::new(v))
+            }
+
+            fn visit_string<E: de::Error>(self, v: String) -> Result<NixPathBuf, E> {
+                Ok(NixPathBuf::new(v))
+            }
+
+            fn visit_newtype_struct<D>(self, deserializer: D) -> Result<NixPathBuf, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                PathBuf::deserialize(deserializer).map(NixPathBuf)
+            }
+        }
+
+        deserializer.deserialize_newtype_struct(Token::Path.name(), PathVisitor)
+    }
+}
+
 /// A borrowed path that serializes as a Nix path literal.
 ///
 /// Use this wrapper type for borrowed paths. For owned paths, use [`NixPathBuf`].
@@ -358,9 +392,16 @@ where
         .to_str()
         .ok_or_else(|| serde::ser::Error::custom("path contains invalid UTF-8 characters"))?;
 
+    serializer.serialize_newtype_struct(Token::Path.name(), render_path(path_str).as_ref())
+}
+
+/// Renders a path as a Nix path expression, quoting the parts that cannot be
+/// written as a path literal.
+pub(crate) fn render_path(path_str: &str) -> Cow<'_, str> {
+    let path = Path::new(path_str);
     let has_special_chars = needs_quoting(path_str);
 
-    let result: Cow<str> = if path.is_absolute() {
+    if path.is_absolute() {
         if has_special_chars {
             // /. + "/path with spaces"
             let mut buf = String::with_capacity(6 + path_str.len());
@@ -375,11 +416,11 @@ where
         match path.components().next() {
             Some(Component::CurDir) => {
                 if has_special_chars {
-                    // ./. + "rest" (strip the ./ prefix, quote the rest)
+                    // ./. + "/rest" (strip the ./ prefix, quote the rest)
                     let rest = path.strip_prefix(Component::CurDir).unwrap();
                     let rest_str = rest.to_str().unwrap();
-                    let mut buf = String::with_capacity(7 + rest_str.len());
-                    buf.push_str("./. + \"");
+                    let mut buf = String::with_capacity(8 + rest_str.len());
+                    buf.push_str("./. + \"/");
                     escape_nix_string_into(rest_str, &mut buf);
                     buf.push('"');
                     Cow::Owned(buf)
@@ -389,11 +430,11 @@ where
             }
             Some(Component::ParentDir) => {
                 if has_special_chars {
-                    // ../. + "rest" (strip the ../ prefix, quote the rest)
+                    // ../. + "/rest" (strip the ../ prefix, quote the rest)
                     let rest = path.strip_prefix(Component::ParentDir).unwrap();
                     let rest_str = rest.to_str().unwrap();
-                    let mut buf = String::with_capacity(8 + rest_str.len());
-                    buf.push_str("../. + \"");
+                    let mut buf = String::with_capacity(9 + rest_str.len());
+                    buf.push_str("../. + \"/");
                     escape_nix_string_into(rest_str, &mut buf);
                     buf.push('"');
                     Cow::Owned(buf)
@@ -404,9 +445,9 @@ where
             _ => {
                 // Bare relative path - needs ./ prefix to avoid Nix search path interpretation
                 if has_special_chars {
-                    // ./. + "path"
-                    let mut buf = String::with_capacity(7 + path_str.len());
-                    buf.push_str("./. + \"");
+                    // ./. + "/path"
+                    let mut buf = String::with_capacity(8 + path_str.len());
+                    buf.push_str("./. + \"/");
                     escape_nix_string_into(path_str, &mut buf);
                     buf.push('"');
                     Cow::Owned(buf)
@@ -419,9 +460,7 @@ where
                 }
             }
         }
-    };
-
-    serializer.serialize_newtype_struct(TOKEN, result.as_ref())
+    }
 }
 
 /// Serialize a `Path` or `PathBuf` as a Nix path literal.
@@ -0,0 +1,129 @@
+use crate::token::Token;
+use serde::{Serialize, Serializer};
+
+/// Carries the Rust type name of a `None` value to the serializer.
+///
+/// Serializes as a unit struct named after the type, which other serializers
+/// write the same way as `None`.
+struct TypeHint(&'static str);
+
+impl Serialize for TypeHint {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_unit_struct(self.0)
+    }
+}
+
+/// Serialize an `Option<T>`, remembering the type of `T` when it is `None`.
+///
+/// With [`NonePolicy::Placeholder`](crate::NonePolicy::Placeholder) the
+/// commented-out attribute is followed by a description of the type, for
+/// example `# hostName = null; # type: string`. Otherwise this behaves
+/// exactly like serializing the `Option` directly.
+///
+/// # Example
+///
+/// ```
+/// use serde::Serialize;
+/// use ser_nix::{to_string_with_config, Config, NonePolicy};
+///
+/// #[derive(Serialize)]
+/// struct Networking {
+///     #[serde(serialize_with = "ser_nix::as_hinted_option")]
+///     host_name: Option<String>,
+///     #[serde(serialize_with = "ser_nix::as_hinted_option")]
+///     name_servers: Option<Vec<String>>,
+/// }
+///
+/// let networking = Networking {
+///     host_name: None,
+///     name_servers: None,
+/// };
+///
+/// let config = Config::new().none_policy(NonePolicy::Placeholder);
+/// let result = to_string_with_config(&networking, &config).unwrap();
+///
+/// assert_eq!(
+///     result,
+///     concat!(
+///         "{\n",
+///         "  # host_name = null; # type: string\n",
+///         "  # name_servers = null; # type: list of string\n",
+///         "}",
+///     ),
+/// );
+/// ```
+pub fn as_hinted_option<T, S>(value: &Option<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    match value {
+        Some(v) => serializer.serialize_some(v),
+        None => serializer.serialize_newtype_struct(
+            Token::Placeholder.name(),
+            &TypeHint(std::any::type_name::<T>()),
+        ),
+    }
+}
+
+/// Describes a Rust type name the way NixOS describes option types
+/// (e.g. `list of string`), falling back to the unqualified Rust name.
+pub(crate) fn describe_type(type_name: &str) -> String {
+    let type_name = type_name.trim().trim_start_matches('&');
+
+    if let Some(element) = type_name
+        .strip_prefix('[')
+        .and_then(|t| t.strip_suffix(']'))
+    {
+        return format!("list of {}", describe_type(element));
+    }
+
+    let (base, args) = match type_name.split_once('<') {
+        Some((base, rest)) => (base, split_args(rest.strip_suffix('>').unwrap_or(rest))),
+        None => (type_name, Vec::new()),
+    };
+    let base = base.rsplit("::").next().unwrap_or(base);
+
+    match (base, args.as_slice()) {
+        ("bool", _) => "boolean".to_string(),
+        ("i8" | "i16" | "i32" | "i64" | "i128" | "isize", _)
+        | ("u8" | "u16" | "u32" | "u64" | "u128" | "usize", _) => "integer".to_string(),
+        ("f32" | "f64", _) => "float".to_string(),
+        ("str" | "String" | "char" | "NixLiteral", _) => "string".to_string(),
+        ("Path" | "PathBuf" | "NixPath" | "NixPathBuf", _) => "path".to_string(),
+        ("Option", [inner]) => format!("null or {}", describe_type(inner)),
+        ("Vec" | "VecDeque" | "HashSet" | "BTreeSet" | "IndexSet", [element, ..]) => {
+            format!("list of {}", describe_type(element))
+        }
+        ("HashMap" | "BTreeMap" | "IndexMap", [_, value, ..]) => {
+            format!("attribute set of {}", describe_type(value))
+        }
+        ("Box" | "Rc" | "Arc" | "Cow", [.., inner]) => describe_type(inner),
+        _ => base.to_string(),
+    }
+}
+
+/// Splits the generic arguments of a type name at top-level commas
+fn split_args(args: &str) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in args.char_indices() {
+        match c {
+            '<' | '[' | '(' => depth += 1,
+            '>' | ']' | ')' => depth -= 1,
+            ',' if depth == 0 => {
+                parts.push(args[start..i].trim());
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(args[start..].trim());
+
+    parts
+}
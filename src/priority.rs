@@ -0,0 +1,171 @@
+use crate::apply::{Apply, Lib};
+use crate::options::{NixOptionType, NixType};
+use serde::{Serialize, Serializer};
+
+macro_rules! priority_wrappers {
+    ($($(#[$doc:meta])* $name:ident => $function:literal,)*) => {
+        $(
+            $(#[$doc])*
+            #[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+            pub struct $name<T>(pub T);
+
+            impl<T: Serialize> Serialize for $name<T> {
+                fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+                where
+                    S: Serializer,
+                {
+                    Apply {
+                        function: &Lib($function),
+                        arg: &self.0,
+                    }
+                    .serialize(serializer)
+                }
+            }
+
+            impl<T: NixType> NixType for $name<T> {
+                fn nix_type() -> NixOptionType {
+                    T::nix_type()
+                }
+            }
+        )*
+    };
+}
+
+priority_wrappers! {
+    /// A value overriding every other definition of an option, written as
+    /// `lib.mkForce value`.
+    ///
+    /// The value is parenthesized when it is not a single term, and `lib` can
+    /// be changed with [`Config::lib`](crate::Config::lib).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use serde::Serialize;
+    /// use ser_nix::{to_string, MkForce};
+    ///
+    /// #[derive(Serialize)]
+    /// struct Firewall {
+    ///     enable: MkForce<bool>,
+    ///     ports: MkForce<Vec<u16>>,
+    /// }
+    ///
+    /// let firewall = Firewall {
+    ///     enable: MkForce(false),
+    ///     ports: MkForce(vec![22]),
+    /// };
+    ///
+    /// let result = to_string(&firewall).unwrap();
+    /// assert_eq!(
+    ///     result,
+    ///     "{\n  enable = lib.mkForce false;\n  ports = lib.mkForce [\n    22\n  ];\n}"
+    /// );
+    /// ```
+    MkForce => "mkForce",
+    /// A value that other definitions of an option override, written as
+    /// `lib.mkDefault value`.
+    MkDefault => "mkDefault",
+    /// A list sorted before the other definitions of an option, written as
+    /// `lib.mkBefore value`.
+    MkBefore => "mkBefore",
+    /// A list sorted after the other definitions of an option, written as
+    /// `lib.mkAfter value`.
+    MkAfter => "mkAfter",
+}
+
+/// A value with an explicit priority, written as
+/// `lib.mkOverride priority value`.
+///
+/// Lower priorities take precedence: [`MkForce`] is priority 50 and
+/// [`MkDefault`] is priority 1000.
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string, MkOverride};
+///
+/// let result = to_string(&MkOverride::new(900, "alice")).unwrap();
+/// assert_eq!(result, "lib.mkOverride 900 \"alice\"");
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MkOverride<T> {
+    pub priority: u32,
+    pub value: T,
+}
+
+impl<T> MkOverride<T> {
+    /// Creates a value with the given priority.
+    pub fn new(priority: u32, value: T) -> Self {
+        MkOverride { priority, value }
+    }
+}
+
+/// A list with an explicit position among the other definitions of an
+/// option, written as `lib.mkOrder priority value`.
+///
+/// Lower priorities are sorted first: [`MkBefore`] is priority 500 and
+/// [`MkAfter`] is priority 1500.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct MkOrder<T> {
+    pub priority: u32,
+    pub value: T,
+}
+
+impl<T> MkOrder<T> {
+    /// Creates a list with the given priority.
+    pub fn new(priority: u32, value: T) -> Self {
+        MkOrder { priority, value }
+    }
+}
+
+/// Writes `lib.<function> priority value`
+fn prioritize<T, S>(
+    function: &str,
+    priority: u32,
+    value: &T,
+    serializer: S,
+) -> Result<S::Ok, S::Error>
+where
+    T: Serialize,
+    S: Serializer,
+{
+    let function = Apply {
+        function: &Lib(function),
+        arg: &priority,
+    };
+    Apply {
+        function: &function,
+        arg: value,
+    }
+    .serialize(serializer)
+}
+
+impl<T: Serialize> Serialize for MkOverride<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        prioritize("mkOverride", self.priority, &self.value, serializer)
+    }
+}
+
+impl<T: Serialize> Serialize for MkOrder<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        prioritize("mkOrder", self.priority, &self.value, serializer)
+    }
+}
+
+impl<T: NixType> NixType for MkOverride<T> {
+    fn nix_type() -> NixOptionType {
+        T::nix_type()
+    }
+}
+
+impl<T: NixType> NixType for MkOrder<T> {
+    fn nix_type() -> NixOptionType {
+        T::nix_type()
+    }
+}
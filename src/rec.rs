@@ -0,0 +1,85 @@
+use crate::token::Token;
+use serde::{Serialize, Serializer};
+
+/// A recursive attribute set, written as `rec { ... }`, whose attributes can
+/// refer to each other with [`NixAttrRef`].
+///
+/// The inner value must serialize to an attribute set, such as a struct or a
+/// map. Serialization fails if it refers to an attribute that it does not
+/// have.
+///
+/// # Example
+///
+/// ```
+/// use serde::Serialize;
+/// use ser_nix::{to_string, NixAttrRef, NixLiteral, NixRec};
+///
+/// #[derive(Serialize)]
+/// struct Package {
+///     pname: &'static str,
+///     version: &'static str,
+///     name: NixLiteral<'static>,
+///     tag: NixAttrRef,
+/// }
+///
+/// let package = NixRec(Package {
+///     pname: "hello",
+///     version: "2.12",
+///     name: NixLiteral::new("\"${pname}-${version}\""),
+///     tag: NixAttrRef::new("version"),
+/// });
+///
+/// let result = to_string(&package).unwrap();
+/// assert_eq!(
+///     result,
+///     concat!(
+///         "rec {\n",
+///         "  pname = \"hello\";\n",
+///         "  version = \"2.12\";\n",
+///         "  name = \"${pname}-${version}\";\n",
+///         "  tag = version;\n",
+///         "}",
+///     )
+/// );
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct NixRec<T>(pub T);
+
+impl<T: Serialize> Serialize for NixRec<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(Token::Rec.name(), &self.0)
+    }
+}
+
+/// A reference to an attribute of an enclosing [`NixRec`], written as its
+/// bare name.
+///
+/// Serialization fails if no enclosing recursive attribute set has the
+/// attribute, or if `name` is not a valid identifier. References written
+/// inside a [`NixLiteral`](crate::NixLiteral) are not checked.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NixAttrRef(String);
+
+impl NixAttrRef {
+    /// Refers to the attribute `name`.
+    pub fn new(name: impl Into<String>) -> Self {
+        NixAttrRef(name.into())
+    }
+
+    /// Returns the name of the attribute referred to.
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Serialize for NixAttrRef {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(Token::AttrRef.name(), &self.0)
+    }
+}
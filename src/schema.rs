@@ -0,0 +1,312 @@
+use crate::error::Error;
+use crate::options::{NixOption, NixOptionSet, NixOptionType};
+use serde::ser::Error as _;
+use serde_json::{Map, Value};
+use std::borrow::Cow;
+
+/// Convert a JSON Schema document into the options of a NixOS module.
+///
+/// Every property of the root object becomes a `lib.mkOption` with a
+/// `lib.types` type, and the `default`, `description` (or `title`) and
+/// `examples` (or `example`) of the property. Examples are written as
+/// `lib.literalExpression`. The options are in the order of the properties
+/// in `schema`, which is alphabetical unless serde_json's `preserve_order`
+/// feature is enabled.
+///
+/// Types are converted as follows:
+///
+/// - `boolean`, `string`, `integer` and `number` become `bool`, `str`, `int`
+///   and `number`, with the `format`s emitted by
+///   [schemars](https://docs.rs/schemars) mapped to `ints.u8`, `float`, ...
+/// - `array` becomes `listOf` its `items`.
+/// - `object` becomes a `submodule` of its `properties`, or `attrsOf` its
+///   `additionalProperties`.
+/// - `enum` and `const` become `enum`, and `oneOf` or `anyOf` become `oneOf`
+///   unless every alternative is an `enum`.
+/// - A `null` type, either in a list of types, as an alternative of `oneOf`
+///   or with `nullable: true`, wraps the type in `nullOr`.
+/// - `$ref` is resolved against the document.
+/// - Anything else becomes `anything`.
+///
+/// # Errors
+///
+/// Fails if the root of the schema is not an object with properties, or if a
+/// `$ref` cannot be resolved or refers to itself.
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{options_from_json_schema, to_string};
+///
+/// let schema = serde_json::json!({
+///     "type": "object",
+///     "properties": {
+///         "port": {
+///             "type": "integer",
+///             "format": "uint16",
+///             "default": 8080,
+///             "description": "Port to listen on"
+///         }
+///     }
+/// });
+///
+/// let options = options_from_json_schema(&schema).unwrap();
+///
+/// let expected = concat!(
+///     "{\n",
+///     "  port = lib.mkOption {\n",
+///     "    type = lib.types.ints.u16;\n",
+///     "    default = 8080;\n",
+///     "    description = \"Port to listen on\";\n",
+///     "  };\n",
+///     "}",
+/// );
+///
+/// assert_eq!(to_string(&options).unwrap(), expected);
+/// ```
+pub fn options_from_json_schema(schema: &Value) -> Result<NixOptionSet, Error> {
+    let converter = Converter {
+        root: schema,
+        refs: Vec::new(),
+    };
+    let schema = schema
+        .as_object()
+        .ok_or_else(|| Error::custom("JSON Schema must be an object"))?;
+    let properties = schema
+        .get("properties")
+        .and_then(Value::as_object)
+        .ok_or_else(|| Error::custom("root of the JSON Schema must have properties"))?;
+
+    converter.options(properties)
+}
+
+struct Converter<'a> {
+    root: &'a Value,
+    /// The `$ref`s being resolved, to detect recursive schemas
+    refs: Vec<&'a str>,
+}
+
+impl<'a> Converter<'a> {
+    fn options(&self, properties: &'a Map<String, Value>) -> Result<NixOptionSet, Error> {
+        let mut options = NixOptionSet::new();
+
+        for (name, property) in properties {
+            let mut option = NixOption::new(self.option_type(property)?);
+
+            let schema = self.resolve(property)?;
+            if let Some(default) = field(property, schema, "default") {
+                option = option.default(default);
+            }
+            let example = field(property, schema, "examples")
+                .and_then(|e| e.as_array())
+                .and_then(|e| e.first())
+                .or_else(|| field(property, schema, "example"));
+            if let Some(example) = example {
+                option = option.example(example);
+            }
+            let description = field(property, schema, "description")
+                .or_else(|| field(property, schema, "title"))
+                .and_then(Value::as_str);
+            if let Some(description) = description {
+                option = option.description(description);
+            }
+
+            options.push(name.clone(), option);
+        }
+
+        Ok(options)
+    }
+
+    /// Follows the `$ref` of `schema`, if it has one
+    fn resolve(&self, schema: &'a Value) -> Result<&'a Value, Error> {
+        let Some(reference) = schema.get("$ref").and_then(Value::as_str) else {
+            return Ok(schema);
+        };
+        let pointer = reference
+            .strip_prefix('#')
+            .ok_or_else(|| Error::custom(format!("cannot resolve `$ref` {reference}")))?;
+        self.root
+            .pointer(pointer)
+            .ok_or_else(|| Error::custom(format!("cannot resolve `$ref` {reference}")))
+    }
+
+    fn option_type(&self, schema: &'a Value) -> Result<NixOptionType, Error> {
+        if let Some(reference) = schema.get("$ref").and_then(Value::as_str) {
+            if self.refs.contains(&reference) {
+                return Err(Error::custom(format!(
+                    "`$ref` {reference} refers to itself"
+                )));
+            }
+            let mut converter = Converter {
+                root: self.root,
+                refs: self.refs.clone(),
+            };
+            converter.refs.push(reference);
+            return converter.option_type(self.resolve(schema)?);
+        }
+
+        let ty = self.non_null_type(schema)?;
+        let nullable = schema.get("nullable") == Some(&Value::Bool(true))
+            || types(schema).any(|t| t == "null")
+            || enum_values(schema).is_some_and(|values| values.iter().any(Value::is_null));
+
+        Ok(match (ty, nullable) {
+            (NixOptionType::NullOr(ty), _) => NixOptionType::NullOr(ty),
+            (ty, true) => NixOptionType::NullOr(Box::new(ty)),
+            (ty, false) => ty,
+        })
+    }
+
+    /// Converts `schema`, ignoring whether it allows `null`
+    fn non_null_type(&self, schema: &'a Value) -> Result<NixOptionType, Error> {
+        if let Some(values) = enum_values(schema) {
+            return Ok(enum_type(values));
+        }
+
+        if let Some(all_of) = schema.get("allOf").and_then(Value::as_array)
+            && let [schema] = all_of.as_slice()
+        {
+            return self.option_type(schema);
+        }
+
+        let alternatives = schema
+            .get("oneOf")
+            .or_else(|| schema.get("anyOf"))
+            .and_then(Value::as_array);
+        if let Some(alternatives) = alternatives {
+            return self.one_of(alternatives);
+        }
+
+        let ty = types(schema).find(|t| *t != "null");
+        let format = schema.get("format").and_then(Value::as_str);
+
+        Ok(match ty {
+            Some("boolean") => named("bool"),
+            Some("string") => named("str"),
+            Some("integer") => named(match format {
+                Some("int8") => "ints.s8",
+                Some("int16") => "ints.s16",
+                Some("int32") => "ints.s32",
+                Some("uint8") => "ints.u8",
+                Some("uint16") => "ints.u16",
+                Some("uint32") => "ints.u32",
+                Some("uint" | "uint64" | "uint128") => "ints.unsigned",
+                _ => "int",
+            }),
+            Some("number") => named(match format {
+                Some("float" | "double") => "float",
+                _ => "number",
+            }),
+            Some("array") => match schema.get("items") {
+                Some(items) => NixOptionType::ListOf(Box::new(self.option_type(items)?)),
+                None => NixOptionType::ListOf(Box::new(named("anything"))),
+            },
+            Some("object") => self.object(schema)?,
+            _ => named("anything"),
+        })
+    }
+
+    fn object(&self, schema: &'a Value) -> Result<NixOptionType, Error> {
+        if let Some(properties) = schema.get("properties").and_then(Value::as_object) {
+            return Ok(NixOptionType::Submodule(self.options(properties)?));
+        }
+
+        Ok(match schema.get("additionalProperties") {
+            Some(Value::Bool(false)) => NixOptionType::Submodule(NixOptionSet::new()),
+            Some(Value::Bool(true)) | None => NixOptionType::AttrsOf(Box::new(named("anything"))),
+            Some(values) => NixOptionType::AttrsOf(Box::new(self.option_type(values)?)),
+        })
+    }
+
+    fn one_of(&self, alternatives: &'a [Value]) -> Result<NixOptionType, Error> {
+        let (nulls, alternatives): (Vec<_>, Vec<_>) = alternatives
+            .iter()
+            .map(|a| self.resolve(a))
+            .collect::<Result<Vec<_>, _>>()?
+            .into_iter()
+            .partition(|a| types(a).eq(["null"]));
+
+        // Unit variants of an enum, as written by schemars
+        let ty = if alternatives.iter().all(|a| enum_values(a).is_some()) {
+            enum_type(
+                alternatives
+                    .iter()
+                    .flat_map(|a| enum_values(a).unwrap_or_default())
+                    .collect(),
+            )
+        } else {
+            let mut types = alternatives
+                .iter()
+                .map(|a| self.option_type(a))
+                .collect::<Result<Vec<_>, _>>()?;
+            match types.len() {
+                1 => types.remove(0),
+                _ => NixOptionType::OneOf(types),
+            }
+        };
+
+        Ok(match nulls.is_empty() {
+            true => ty,
+            false => NixOptionType::NullOr(Box::new(ty)),
+        })
+    }
+}
+
+/// Looks up `key` on a property, falling back to the schema it refers to
+fn field<'a>(property: &'a Value, schema: &'a Value, key: &str) -> Option<&'a Value> {
+    property.get(key).or_else(|| schema.get(key))
+}
+
+fn named(name: &'static str) -> NixOptionType {
+    NixOptionType::Named(Cow::Borrowed(name))
+}
+
+/// The `type` of a schema, which may be a single type or a list of them
+fn types(schema: &Value) -> impl Iterator<Item = &str> {
+    let types: Vec<&str> = match schema.get("type") {
+        Some(Value::String(ty)) => vec![ty],
+        Some(Value::Array(types)) => types.iter().filter_map(Value::as_str).collect(),
+        _ => Vec::new(),
+    };
+    types.into_iter()
+}
+
+/// The values allowed by `enum` or `const`
+fn enum_values(schema: &Value) -> Option<Vec<Value>> {
+    match (schema.get("enum"), schema.get("const")) {
+        (Some(Value::Array(values)), _) => Some(values.clone()),
+        (_, Some(value)) => Some(vec![value.clone()]),
+        _ => None,
+    }
+}
+
+/// An `enum` of the values, without `null`
+fn enum_type(values: Vec<Value>) -> NixOptionType {
+    NixOptionType::Enum(
+        values
+            .iter()
+            .filter(|v| !v.is_null())
+            .map(nix_value)
+            .collect(),
+    )
+}
+
+/// Converts a JSON value to the Nix value it stands for
+fn nix_value(value: &Value) -> crate::Value {
+    match value {
+        Value::Null => crate::Value::Null,
+        Value::Bool(b) => crate::Value::Bool(*b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => crate::Value::Int(i),
+            None => crate::Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        Value::String(s) => crate::Value::String(s.clone()),
+        Value::Array(elements) => crate::Value::List(elements.iter().map(nix_value).collect()),
+        Value::Object(attrs) => crate::Value::Attrs(
+            attrs
+                .iter()
+                .map(|(name, value)| (name.clone(), nix_value(value)))
+                .collect(),
+        ),
+    }
+}
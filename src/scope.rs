@@ -0,0 +1,59 @@
+use crate::token::Token;
+use crate::unpack::Payload;
+use serde::{Serialize, Serializer};
+
+/// A `let` expression, binding names for use in its body:
+/// `let ... in body`.
+///
+/// The bindings must serialize to an attribute set, such as a struct or a
+/// map, and are written in the same way as its attributes.
+///
+/// # Example
+///
+/// ```
+/// use serde::Serialize;
+/// use ser_nix::{to_string, NixLet, NixLiteral};
+/// use std::collections::BTreeMap;
+///
+/// #[derive(Serialize)]
+/// struct Bindings {
+///     port: u16,
+/// }
+///
+/// let mut body = BTreeMap::new();
+/// body.insert("listen", NixLiteral::new("\"0.0.0.0:${toString port}\""));
+///
+/// let result = to_string(&NixLet::new(Bindings { port: 8080 }, body)).unwrap();
+/// assert_eq!(
+///     result,
+///     "let\n  port = 8080;\nin\n{\n  listen = \"0.0.0.0:${toString port}\";\n}"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct NixLet<B, T> {
+    pub bindings: B,
+    pub body: T,
+}
+
+impl<B, T> NixLet<B, T> {
+    /// Binds the attributes of `bindings` in `body`.
+    pub fn new(bindings: B, body: T) -> Self {
+        NixLet { bindings, body }
+    }
+}
+
+impl<B, T> Serialize for NixLet<B, T>
+where
+    B: Serialize,
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_newtype_struct(
+            Token::Unpack(Payload::Let).name(),
+            &(&self.bindings, &self.body),
+        )
+    }
+}
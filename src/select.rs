@@ -0,0 +1,237 @@
+use crate::apply::Term;
+use crate::content::Content;
+use crate::error::Error;
+use crate::expr::escape_into;
+use crate::literal::Raw;
+use crate::map::is_nix_identifier;
+use crate::ser::Form;
+use crate::token::Token;
+use crate::unpack::Payload;
+use serde::ser::Error as _;
+use serde::{Serialize, Serializer};
+use std::str::FromStr;
+
+/// The selection of an attribute path from a variable, such as
+/// `pkgs.python3Packages.requests`, with an optional default written after
+/// `or`.
+///
+/// Names that are not valid identifiers are quoted, such as
+/// `pkgs."foo.bar"`, and names computed by an expression are written as
+/// `${expr}`. A path can also be parsed from a string with
+/// [`str::parse`].
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string, NixAttrSelect};
+///
+/// let hello = NixAttrSelect::new("inputs")
+///     .attr("nixpkgs")
+///     .attr("legacyPackages")
+///     .interpolated("system")
+///     .attr("hello");
+/// assert_eq!(
+///     to_string(&hello).unwrap(),
+///     "inputs.nixpkgs.legacyPackages.${system}.hello"
+/// );
+///
+/// let package: NixAttrSelect = "pkgs.\"foo.bar\"".parse().unwrap();
+/// let package = package.or(&false);
+/// assert_eq!(to_string(&package).unwrap(), "pkgs.\"foo.bar\" or false");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct NixAttrSelect {
+    root: String,
+    segments: Vec<Segment>,
+    /// The default value, or the message of the error recording it failed
+    /// with
+    default: Option<Result<Content, String>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Segment {
+    Static(String),
+    Interpolated(String),
+}
+
+impl NixAttrSelect {
+    /// Starts a path at the variable `root`.
+    ///
+    /// Serialization fails if `root` is not a valid identifier.
+    pub fn new(root: impl Into<String>) -> Self {
+        NixAttrSelect {
+            root: root.into(),
+            segments: Vec::new(),
+            default: None,
+        }
+    }
+
+    /// Selects the attribute `name`, quoted if it is not a valid identifier.
+    pub fn attr(mut self, name: impl Into<String>) -> Self {
+        self.segments.push(Segment::Static(name.into()));
+        self
+    }
+
+    /// Selects the attribute whose name is computed by `expr`, written as
+    /// `${expr}`.
+    pub fn interpolated(mut self, expr: impl Into<String>) -> Self {
+        self.segments.push(Segment::Interpolated(expr.into()));
+        self
+    }
+
+    /// Sets the value of the selection when the path does not exist, written
+    /// with the configuration the selection is serialized with.
+    ///
+    /// If `default` cannot be serialized, serializing the selection fails.
+    pub fn or<T>(mut self, default: &T) -> Self
+    where
+        T: ?Sized + Serialize,
+    {
+        self.default = Some(Content::record(default));
+        self
+    }
+
+    /// Returns the variable the path starts at.
+    pub fn root(&self) -> &str {
+        &self.root
+    }
+
+    /// Renders the attribute path of the selection
+    fn render(&self) -> Result<String, String> {
+        if !is_nix_identifier(&self.root) {
+            return Err(format!("invalid attribute path root `{}`", self.root));
+        }
+
+        let mut rendered = self.root.clone();
+        for segment in &self.segments {
+            rendered.push('.');
+            match segment {
+                Segment::Static(name) if is_nix_identifier(name) => rendered += name,
+                Segment::Static(name) => {
+                    rendered.push('"');
+                    escape_into(name, &mut rendered);
+                    rendered.push('"');
+                }
+                Segment::Interpolated(expr) if expr.trim().is_empty() => {
+                    return Err("interpolated attribute name without an expression".into());
+                }
+                Segment::Interpolated(expr) => {
+                    rendered += "${";
+                    rendered += expr;
+                    rendered.push('}');
+                }
+            }
+        }
+
+        if self.default.is_some() && self.segments.is_empty() {
+            return Err(format!(
+                "`{}` has a default without an attribute path",
+                self.root
+            ));
+        }
+        Ok(rendered)
+    }
+}
+
+impl Serialize for NixAttrSelect {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let rendered = self.render().map_err(S::Error::custom)?;
+        match &self.default {
+            None => serializer.serialize_newtype_struct(Token::Rendered.name(), &rendered),
+            // The default is a single term, such as `a.b or (f x)`
+            Some(Ok(default)) => serializer.serialize_newtype_struct(
+                Token::Unpack(Payload::Concat(Form::Term)).name(),
+                &(Raw(&rendered), Raw(" or "), Term(default)),
+            ),
+            Some(Err(msg)) => Err(S::Error::custom(msg)),
+        }
+    }
+}
+
+impl FromStr for NixAttrSelect {
+    type Err = Error;
+
+    /// Parses a dotted attribute path such as `pkgs."foo.bar".${system}`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: &str| -> Error {
+            serde::ser::Error::custom(format!("invalid attribute path `{s}`: {reason}"))
+        };
+
+        let (root, mut rest) = match s.find('.') {
+            Some(i) => (&s[..i], &s[i..]),
+            None => (s, ""),
+        };
+        if !is_nix_identifier(root) {
+            return Err(invalid("it does not start with an identifier"));
+        }
+
+        let mut select = NixAttrSelect::new(root);
+        while let Some(after_dot) = rest.strip_prefix('.') {
+            let (segment, len) = if let Some(quoted) = after_dot.strip_prefix('"') {
+                let (name, len) = parse_quoted(quoted).map_err(invalid)?;
+                (Segment::Static(name), len + 2)
+            } else if let Some(interpolated) = after_dot.strip_prefix("${") {
+                let len =
+                    interpolation_len(interpolated).ok_or_else(|| invalid("unterminated `${`"))?;
+                (
+                    Segment::Interpolated(interpolated[..len].to_string()),
+                    len + 3,
+                )
+            } else {
+                let len = after_dot.find('.').unwrap_or(after_dot.len());
+                let name = &after_dot[..len];
+                if !is_nix_identifier(name) {
+                    return Err(invalid(&format!("`{name}` is not an identifier")));
+                }
+                (Segment::Static(name.to_string()), len)
+            };
+            select.segments.push(segment);
+            rest = &after_dot[len..];
+        }
+        if !rest.is_empty() {
+            return Err(invalid(&format!("unexpected `{rest}`")));
+        }
+        Ok(select)
+    }
+}
+
+/// Unescapes the contents of a double-quoted string up to its closing quote,
+/// returning them with the length of the contents
+fn parse_quoted(s: &str) -> Result<(String, usize), &'static str> {
+    let mut name = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((name, i)),
+            '\\' => match chars.next().map(|(_, c)| c) {
+                Some('n') => name.push('\n'),
+                Some('r') => name.push('\r'),
+                Some('t') => name.push('\t'),
+                Some(c) => name.push(c),
+                None => break,
+            },
+            '$' if s[i + 1..].starts_with('{') => {
+                return Err("quoted names cannot interpolate expressions");
+            }
+            c => name.push(c),
+        }
+    }
+    Err("unterminated string")
+}
+
+/// Length of an interpolated expression up to its closing brace
+fn interpolation_len(s: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    for (i, c) in s.char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            _ => {}
+        }
+    }
+    None
+}
@@ -3,7 +3,7 @@ use super::ser::Serializer;
 
 use serde::{Serialize, ser};
 
-impl ser::SerializeSeq for &mut Serializer {
+impl ser::SerializeSeq for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -11,16 +11,11 @@ impl ser::SerializeSeq for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        self.output += "\n";
-        self.indent();
-        value.serialize(&mut **self)
+        self.push_element(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.indent_depth -= 1;
-        self.output += "\n";
-        self.indent();
-        self.output += "]";
+        self.write_list();
         Ok(())
     }
 }
@@ -1,33 +1,840 @@
+use super::apply::needs_parens;
+use super::comment::Comment;
+use super::config::{Config, NonePolicy};
+use super::content::Content;
 use super::error::Error;
+use super::format::{Context, Formatter};
+use super::map::is_nix_identifier;
+use super::mode::StringMode;
+use super::path::{RawEmitter, render_path};
+use super::placeholder::describe_type;
+use super::share::Sharing;
+use super::token::Token;
+use super::unpack::{KeyKind, Unpacker};
+use super::with::strip_common_scope;
 
 use serde::{Serialize, ser};
+use std::cell::RefCell;
 
-pub struct Serializer {
+pub struct Serializer<'a> {
     pub output: String,
-    pub pending_key: Option<String>,
+    /// Key of the map entry being serialized, and whether it is dynamic
+    pub pending_key: Option<(String, bool)>,
     pub indent_depth: usize,
+    /// Attributes of the attrset being serialized
+    pub entries: Vec<Attr>,
+    /// Rendered elements of the list being serialized
+    pub elements: Vec<String>,
+    /// Number of elements of the list being serialized that are literals
+    pub literal_elements: usize,
+    /// Kind of the container being serialized, which decides its layout
+    pub container: Container,
+    /// What the value written is part of
+    pub role: Role,
+    /// How strings are written, inherited by the values inside this one
+    pub strings: StringMode,
+    /// What the values written inside this one inherit from it
+    pub env: Env<'a>,
+    /// What becomes of the value written as the value of an attribute
+    pub outcome: Outcome,
+    /// How the value written combines with what surrounds it
+    pub form: Form,
+    /// Whether the value written is a `NixLiteral`, or a string written as
+    /// one
+    pub literal: bool,
+    /// How the key written was given, when it is a `NixKey`, whose quotes
+    /// are kept
+    pub(crate) key_kind: Option<KeyKind>,
+    /// Whether the next unit struct carries the type name of a `None` value
+    pub hinting: bool,
+    /// Comment to write above the attribute whose value was written
+    pub comment: Option<Comment>,
+    /// Whether the value written should be spliced into the enclosing attrset
+    /// if it is a struct or a map
+    pub attr_path: bool,
+    /// Whether the attributes written should be spliced into the enclosing
+    /// attrset as `key.attr` instead of being written as an attrset
+    pub splice: bool,
+    /// Attributes to splice into the enclosing attrset
+    pub spliced: Option<Vec<Attr>>,
+    pub config: &'a Config,
 }
 
-impl Serializer {
-    pub fn indent(&mut self) {
-        self.output += &"  ".repeat(self.indent_depth);
+/// What a value written by a [`Serializer`] is part of
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Role {
+    /// A list, the body of an expression, or nothing at all
+    #[default]
+    Value,
+    /// The value of an attribute
+    AttrValue,
+    /// The key of a map
+    MapKey,
+}
+
+/// What the values written inside a value inherit from it
+#[derive(Clone, Default)]
+pub struct Env<'a> {
+    /// Names referred to with `NixAttrRef` inside the innermost recursive
+    /// attrset being serialized
+    pub references: Option<&'a RefCell<Vec<String>>>,
+    /// Path of the attribute being serialized, for errors about literals
+    pub field: Option<String>,
+    /// Whether the value written is inside a `let`, a recursive attrset, a
+    /// function or a module written by the serializer, whose bindings take
+    /// precedence over the attributes brought into scope by `with`
+    pub scoped: bool,
+    /// Repeated values found so far, when they are shared
+    pub sharing: Option<&'a RefCell<Sharing>>,
+}
+
+/// What becomes of a value written by a [`Serializer`] when it is the value
+/// of an attribute
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Outcome {
+    /// The attribute is written with the value
+    #[default]
+    Written,
+    /// The attribute is left out of the enclosing attrset
+    Omitted,
+    /// The attribute is commented out, with a description of the type of
+    /// the `None` value when it is known
+    Placeholder { hint: Option<String> },
+    /// The attribute is inherited from the attribute set `from`, or from the
+    /// variables in scope when it is empty
+    Inherited { from: String },
+}
+
+/// How a value written by a [`Serializer`] combines with what surrounds it
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Form {
+    /// A value that needs no parentheses in a list
+    #[default]
+    Term,
+    /// A function application, which can be applied to more arguments
+    /// without parentheses
+    Apply,
+    /// A function or a `let` expression, whose body extends as far to the
+    /// right as possible, or an operator, which must be parenthesized to be
+    /// an element of a list
+    Open,
+}
+
+impl<'a> Serializer<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        Serializer {
+            output: String::new(),
+            pending_key: None,
+            indent_depth: 0,
+            entries: Vec::new(),
+            elements: Vec::new(),
+            literal_elements: 0,
+            container: Container::default(),
+            role: Role::default(),
+            strings: StringMode::default(),
+            env: Env::default(),
+            outcome: Outcome::default(),
+            form: Form::default(),
+            literal: false,
+            key_kind: None,
+            hinting: false,
+            comment: None,
+            attr_path: false,
+            splice: false,
+            spliced: None,
+            config,
+        }
+    }
+
+    /// Creates an empty serializer for a key at the current depth
+    pub fn nested(&self) -> Serializer<'a> {
+        Serializer {
+            indent_depth: self.indent_depth,
+            env: self.env.clone(),
+            ..Serializer::new(self.config)
+        }
+    }
+
+    /// Creates an empty serializer for a value inside the current container
+    pub fn child(&self) -> Serializer<'a> {
+        Serializer {
+            indent_depth: self.indent_depth + 1,
+            strings: self.strings,
+            env: self.env.clone(),
+            ..Serializer::new(self.config)
+        }
+    }
+
+    pub fn formatter(&self) -> &'a dyn Formatter {
+        let config: &'a Config = self.config;
+        config.formatter.as_ref()
+    }
+
+    /// Serializes `value` as the next element of the current list
+    pub fn push_element<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let mut element = self.child();
+        let id = self.begin_shared();
+        value.serialize(&mut element)?;
+        self.end_shared(id, value, &mut element.output);
+        self.literal_elements += element.literal as usize;
+        // Applications and functions would be split into several elements,
+        // and Nix does not accept a negation as an element
+        if element.form != Form::Term || element.output.starts_with('-') {
+            element.output = format!("({})", element.output);
+        }
+        self.elements.push(element.output);
+        Ok(())
+    }
+
+    /// Serializes `value` as the attribute `key` of the current attrset
+    pub fn push_attr<T>(&mut self, key: String, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.insert_attr(key, false, value)
+    }
+
+    /// Serializes `value` as the attribute of the current attrset whose name
+    /// is computed by `key`, such as `${name}`
+    pub fn push_dynamic_attr<T>(&mut self, key: String, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        self.insert_attr(key, true, value)
+    }
+
+    fn insert_attr<T>(&mut self, key: String, dynamic: bool, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let mut value_serializer = self.child();
+        value_serializer.role = Role::AttrValue;
+        if self.config.strict_literals {
+            value_serializer.env.field = Some(match &self.env.field {
+                Some(field) => format!("{field}.{key}"),
+                None => key.clone(),
+            });
+        }
+        let id = self.begin_shared();
+        value.serialize(&mut value_serializer)?;
+        self.end_shared(id, value, &mut value_serializer.output);
+
+        if let Some(mut spliced) = value_serializer.spliced {
+            if let Some(first) = spliced.first_mut() {
+                first.comment = first.comment.take().or(value_serializer.comment);
+            }
+            for attr in &mut spliced {
+                attr.key = format!("{key}.{}", attr.key);
+                attr.dynamic |= dynamic;
+                attr.select_inherited()?;
+            }
+            self.entries.extend(spliced);
+            return Ok(());
+        }
+
+        let value = match value_serializer.outcome {
+            Outcome::Written => AttrValue::Rendered(value_serializer.output),
+            Outcome::Omitted => return Ok(()),
+            Outcome::Placeholder { hint } => AttrValue::Placeholder { hint },
+            Outcome::Inherited { .. } if dynamic => {
+                return Err(ser::Error::custom(format!(
+                    "cannot inherit the dynamic attribute `{key}`"
+                )));
+            }
+            Outcome::Inherited { from } => AttrValue::Inherited {
+                from: Some(from).filter(|from| !from.is_empty()),
+            },
+        };
+        let mut attr = Attr {
+            key,
+            value,
+            comment: value_serializer.comment,
+            dynamic,
+        };
+        attr.select_inherited()?;
+        self.entries.push(attr);
+        Ok(())
+    }
+
+    /// Starts serializing a value that may be shared
+    fn begin_shared(&self) -> Option<usize> {
+        self.env
+            .sharing
+            .and_then(|sharing| sharing.borrow_mut().begin())
+    }
+
+    /// Finishes serializing a value that may be shared, written on a line one
+    /// level deeper than this one
+    fn end_shared<T>(&self, id: Option<usize>, value: &T, output: &mut String)
+    where
+        T: ?Sized + Serialize,
+    {
+        if let Some(sharing) = self.env.sharing {
+            let formatter = self.formatter();
+            let depth = self.indent_depth + 1;
+            let record = || Content::record(value).ok();
+            sharing
+                .borrow_mut()
+                .end(id, output, depth, formatter, record);
+        }
+    }
+
+    /// Writes Nix rendered at depth zero, indenting every line after the
+    /// first to the current depth
+    pub fn write_rendered(&mut self, rendered: &str) {
+        let formatter = self.formatter();
+        for (i, line) in rendered.split('\n').enumerate() {
+            if i > 0 {
+                self.output.push('\n');
+                if !line.is_empty() {
+                    formatter.write_indent(&mut self.output, self.indent_depth);
+                }
+            }
+            self.output += line;
+        }
+    }
+
+    /// Writes a `NixLiteral`, replacing the common indentation of its lines
+    /// after the first with the current depth, except for the lines inside
+    /// strings, which are written as is
+    pub fn write_literal(&mut self, literal: &str) {
+        let mut indent = String::new();
+        self.formatter()
+            .write_indent(&mut indent, self.indent_depth);
+        crate::literal::reindent_into(literal, &indent, &mut self.output);
+    }
+
+    /// Writes `null` for a `None` value, which becomes a placeholder or is
+    /// left out as the value of an attribute depending on the configuration
+    fn write_none(&mut self, hint: Option<String>) {
+        self.outcome = match self.config.none {
+            NonePolicy::Omit => Outcome::Omitted,
+            NonePolicy::Placeholder => Outcome::Placeholder { hint },
+            NonePolicy::Null => Outcome::Written,
+        };
+        self.output += "null";
+    }
+
+    /// Leaves out an empty attrset or list if the configuration says so
+    fn omit_empty(&mut self) {
+        if self.config.omit_empty {
+            self.outcome = Outcome::Omitted;
+        }
+    }
+
+    /// Writes a number or a boolean, which the formatter may quote if it is
+    /// the key of a map
+    fn write_scalar(&mut self, scalar: &str) {
+        match self.role {
+            Role::MapKey => self.formatter().write_scalar_key(&mut self.output, scalar),
+            _ => self.output += scalar,
+        }
+    }
+
+    /// Writes a rendered expression, parenthesized unless it is a single term
+    pub fn write_term(&mut self, rendered: &str) {
+        if needs_parens(rendered) {
+            self.output.push('(');
+            self.output += rendered;
+            self.output.push(')');
+        } else {
+            self.output += rendered;
+        }
+    }
+
+    /// Writes the buffered elements as a list
+    pub fn write_list(&mut self) {
+        let formatter = self.formatter();
+        let mut elements = std::mem::take(&mut self.elements);
+        let container = std::mem::take(&mut self.container);
+        let literals = std::mem::take(&mut self.literal_elements) == elements.len();
+
+        let ctx = Context {
+            depth: self.indent_depth,
+            len: elements.len(),
+            compact: container == Container::Compact,
+            fields: false,
+            multiline: elements.iter().any(|e| e.contains('\n')),
+            key_width: 0,
+        };
+
+        if elements.is_empty() {
+            formatter.empty_list(&mut self.output, &ctx);
+            self.omit_empty();
+            return;
+        }
+
+        // The bindings of shared values enclose the whole output, so no
+        // element may be stripped to one of their names
+        let sharing = self.env.sharing.is_some();
+        if self.config.with_common_prefix
+            && literals
+            && !self.env.scoped
+            && let Some(scope) = strip_common_scope(&mut elements, |name| {
+                sharing && crate::share::is_binding_name(name)
+            })
+        {
+            self.output += "with ";
+            self.output += &scope;
+            self.output += "; ";
+            self.form = Form::Open;
+        }
+
+        formatter.begin_list(&mut self.output, &ctx);
+        for (i, element) in elements.iter().enumerate() {
+            if i > 0 {
+                formatter.between_elements(&mut self.output, &ctx);
+            }
+            formatter.before_element(&mut self.output, &ctx);
+            self.output += element;
+        }
+        formatter.end_list(&mut self.output, &ctx);
+    }
+
+    /// Writes the buffered entries as an attrset
+    pub fn write_attrset(&mut self) {
+        let formatter = self.formatter();
+        let mut entries = std::mem::take(&mut self.entries);
+        let container = std::mem::take(&mut self.container);
+
+        if std::mem::take(&mut self.splice) {
+            self.spliced = Some(entries);
+            return;
+        }
+
+        if formatter.sort_attrs() {
+            entries.sort_by(|a, b| attr_name(&a.key).cmp(attr_name(&b.key)));
+        }
+
+        let ctx = Context {
+            depth: self.indent_depth,
+            len: entries.len(),
+            compact: container == Container::Compact,
+            fields: container == Container::Struct,
+            multiline: !entries.iter().all(Attr::fits_on_line),
+            key_width: key_width(&entries),
+        };
+
+        if entries.is_empty() {
+            formatter.empty_attrset(&mut self.output, &ctx);
+            self.omit_empty();
+            return;
+        }
+
+        formatter.begin_attrset(&mut self.output, &ctx);
+        self.write_attrs(&entries, &ctx);
+        formatter.end_attrset(&mut self.output, &ctx);
+    }
+
+    /// Writes the attributes of an attrset or the bindings of a `let`
+    fn write_attrs(&mut self, entries: &[Attr], ctx: &Context) {
+        let formatter = self.formatter();
+        let mut i = 0;
+        while let Some(attr) = entries.get(i) {
+            if i > 0 {
+                formatter.between_attrs(&mut self.output, ctx);
+            }
+            i += 1;
+            formatter.before_key(&mut self.output, ctx);
+            if let Some(comment) = &attr.comment {
+                formatter.write_comment(
+                    &mut self.output,
+                    &comment.text,
+                    comment.style,
+                    ctx.depth + 1,
+                );
+            }
+            let key = &attr.key;
+            match &attr.value {
+                AttrValue::Rendered(value) => {
+                    self.output += key;
+                    formatter.begin_value(&mut self.output, key, ctx);
+                    self.output += value;
+                    formatter.end_value(&mut self.output, ctx);
+                }
+                AttrValue::Placeholder { hint } => {
+                    formatter.write_placeholder(&mut self.output, key, hint.as_deref(), ctx);
+                }
+                AttrValue::Inherited { from } => {
+                    // Following attributes inherited from the same place
+                    // share the statement, unless a comment is about this one
+                    let mut names = vec![key.as_str()];
+                    while attr.comment.is_none()
+                        && let Some(next) = entries.get(i)
+                        && next.comment.is_none()
+                        && matches!(&next.value, AttrValue::Inherited { from: f } if f == from)
+                    {
+                        names.push(&next.key);
+                        i += 1;
+                    }
+                    formatter.write_inherit(&mut self.output, from.as_deref(), &names, ctx);
+                }
+            }
+        }
+    }
+
+    /// Writes `let`, the bindings and `in`, followed by the indentation of
+    /// the body on the next line
+    pub fn write_let(&mut self, bindings: &[Attr]) {
+        let formatter = self.formatter();
+        let ctx = Context {
+            depth: self.indent_depth,
+            len: bindings.len(),
+            compact: false,
+            fields: false,
+            multiline: true,
+            key_width: key_width(bindings),
+        };
+
+        self.output += "let";
+        self.write_attrs(bindings, &ctx);
+        self.output.push('\n');
+        formatter.write_indent(&mut self.output, ctx.depth);
+        self.output += "in\n";
+        formatter.write_indent(&mut self.output, ctx.depth);
+    }
+
+    /// Writes the bindings of the repeated values, if this is where they
+    /// should be placed
+    pub fn write_shared_bindings(&mut self) {
+        let Some(sharing) = self.env.sharing else {
+            return;
+        };
+        let bindings = sharing.borrow_mut().take_bindings();
+        if bindings.is_empty() {
+            return;
+        }
+
+        let bindings: Vec<Attr> = bindings
+            .into_iter()
+            .map(|(name, value)| {
+                let mut shifted = self.child();
+                shifted.write_rendered(&value);
+                Attr {
+                    key: name,
+                    value: AttrValue::Rendered(shifted.output),
+                    comment: None,
+                    dynamic: false,
+                }
+            })
+            .collect();
+        self.write_let(&bindings);
     }
 }
 
-impl ser::Serializer for &mut Serializer {
+impl Serializer<'_> {
+    /// Writes a value as a recursive attrset, checking that the attributes
+    /// it refers to exist
+    fn serialize_rec<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let references = RefCell::new(Vec::new());
+        let mut inner = Serializer {
+            indent_depth: self.indent_depth + 1,
+            strings: self.strings,
+            // A comment on the attributes is written above the attribute
+            // whose value is the recursive attrset
+            role: self.role,
+            attr_path: true,
+            env: Env {
+                references: Some(&references),
+                field: None,
+                scoped: true,
+                sharing: self.env.sharing,
+            },
+            ..Serializer::new(self.config)
+        };
+
+        // Shared values would be moved out of the scope of the attributes
+        if let Some(sharing) = self.env.sharing {
+            sharing.borrow_mut().enter_scope();
+        }
+        let result = value.serialize(&mut inner);
+        if let Some(sharing) = self.env.sharing {
+            sharing.borrow_mut().exit_scope();
+        }
+        result?;
+
+        let entries = inner.spliced.ok_or_else(|| {
+            ser::Error::custom("the value of a recursive attribute set must be an attribute set")
+        })?;
+        for name in references.take() {
+            let defined = entries.iter().any(|attr| {
+                !matches!(attr.value, AttrValue::Placeholder { .. })
+                    && attr_path_names(&attr.key)[0] == name
+            });
+            if defined {
+                continue;
+            }
+            // Enclosing recursive attrsets are in scope too
+            match self.env.references {
+                Some(outer) => outer.borrow_mut().push(name),
+                None => {
+                    return Err(ser::Error::custom(format!(
+                        "`{name}` does not refer to an attribute of the recursive attribute set"
+                    )));
+                }
+            }
+        }
+
+        self.output += "rec ";
+        self.entries = entries;
+        self.comment = inner.comment;
+        self.write_attrset();
+        Ok(())
+    }
+
+    /// Writes a reference to an attribute of the enclosing recursive attrset
+    fn write_reference(&mut self, name: String) -> Result<(), Error> {
+        if !is_nix_identifier(&name) {
+            return Err(ser::Error::custom(format!(
+                "cannot refer to `{name}`, as it is not an identifier"
+            )));
+        }
+        let Some(references) = self.env.references else {
+            return Err(ser::Error::custom(format!(
+                "`{name}` is referred to outside of a recursive attribute set"
+            )));
+        };
+        self.output += &name;
+        references.borrow_mut().push(name);
+        Ok(())
+    }
+}
+
+/// Kind of container a [`Serializer`] is writing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Container {
+    /// A list or a map, expanded with one value per line
+    #[default]
+    Collection,
+    /// The fields of a struct
+    Struct,
+    /// A tuple or an enum variant, which may stay on a single line
+    Compact,
+}
+
+/// An attribute buffered by a [`Serializer`], with its key already rendered
+pub struct Attr {
+    pub key: String,
+    pub value: AttrValue,
+    pub comment: Option<Comment>,
+    /// Whether the name of the attribute is computed by an expression
+    pub dynamic: bool,
+}
+
+pub enum AttrValue {
+    Rendered(String),
+    /// A `None` value written as a commented-out attribute
+    Placeholder {
+        hint: Option<String>,
+    },
+    /// An attribute written as `inherit key;`, or `inherit (from) key;`
+    Inherited {
+        from: Option<String>,
+    },
+}
+
+impl Attr {
+    /// Whether the attribute can share a line with the attributes around it
+    fn fits_on_line(&self) -> bool {
+        match &self.value {
+            AttrValue::Rendered(value) => self.comment.is_none() && !value.contains('\n'),
+            AttrValue::Placeholder { .. } => false,
+            AttrValue::Inherited { .. } => self.comment.is_none(),
+        }
+    }
+
+    /// Writes an inherited attribute whose key is an attribute path, which
+    /// `inherit` cannot take, as the selection of its last name
+    fn select_inherited(&mut self) -> Result<(), Error> {
+        let AttrValue::Inherited { from } = &self.value else {
+            return Ok(());
+        };
+        let name = match attr_path_names(&self.key)[..] {
+            [.., name] if name.len() < self.key.len() => name,
+            _ if from.is_some() || is_nix_identifier(&self.key) => return Ok(()),
+            _ => &self.key,
+        };
+
+        let value = match from {
+            Some(from) if needs_parens(from) => format!("({from}).{name}"),
+            Some(from) => format!("{from}.{name}"),
+            None if is_nix_identifier(name) => name.to_string(),
+            None => {
+                return Err(ser::Error::custom(format!(
+                    "cannot inherit `{}` from the variables in scope, as it is not an identifier",
+                    self.key
+                )));
+            }
+        };
+        self.value = AttrValue::Rendered(value);
+        Ok(())
+    }
+}
+
+/// Width in characters of the longest name of an attribute written with `=`
+fn key_width(entries: &[Attr]) -> usize {
+    entries
+        .iter()
+        .filter(|attr| !matches!(attr.value, AttrValue::Inherited { .. }))
+        .map(|attr| attr.key.chars().count())
+        .max()
+        .unwrap_or(0)
+}
+
+/// Splits a rendered attribute path into its names, which are still quoted
+fn attr_path_names(key: &str) -> Vec<&str> {
+    let mut names = Vec::new();
+    let mut quoted = false;
+    let mut escaped = false;
+    let mut start = 0;
+    for (i, c) in key.char_indices() {
+        match c {
+            '\\' if quoted && !escaped => {
+                escaped = true;
+                continue;
+            }
+            '"' if !escaped => quoted = !quoted,
+            '.' if !quoted => {
+                names.push(&key[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+        escaped = false;
+    }
+    names.push(&key[start..]);
+    names
+}
+
+/// Serializes the fields of a tuple or struct variant into a
+/// `{ variant = ...; }` wrapper
+pub struct VariantSerializer<'s, 'a> {
+    pub parent: &'s mut Serializer<'a>,
+    pub variant: &'static str,
+    pub inner: Serializer<'a>,
+    /// Whether the variant is written as an attribute set laid out by the
+    /// formatter, see [`Formatter::expand_variants`]
+    pub expanded: bool,
+}
+
+impl<'s, 'a> VariantSerializer<'s, 'a> {
+    fn new(parent: &'s mut Serializer<'a>, variant: &'static str) -> Self {
+        let expanded = parent.formatter().expand_variants();
+        let mut inner = match expanded {
+            true => parent.child(),
+            // The fields are written at the depth of the variant
+            false => Serializer {
+                strings: parent.strings,
+                ..parent.nested()
+            },
+        };
+        inner.container = Container::Compact;
+        VariantSerializer {
+            variant,
+            inner,
+            expanded,
+            parent,
+        }
+    }
+
+    /// Writes a field of a variant that is not expanded, after the name of
+    /// the field for struct variants
+    pub fn push_inline<T>(&mut self, key: Option<&str>, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let inner = &mut self.inner;
+        let mut field = Serializer {
+            strings: inner.strings,
+            ..inner.nested()
+        };
+        value.serialize(&mut field)?;
+        if key.is_none() && field.output.starts_with('-') {
+            field.output = format!("({})", field.output);
+        }
+
+        let formatter = inner.formatter();
+        match key {
+            Some(key) => {
+                inner.output.push(' ');
+                formatter.write_string(&mut inner.output, key, inner.indent_depth);
+                inner.output += " = { ";
+            }
+            None if !inner.output.is_empty() => inner.output.push(' '),
+            None => {}
+        }
+        inner.output += &field.output;
+        Ok(())
+    }
+
+    /// Writes the wrapper around the already written contents of the variant
+    pub fn end(self) {
+        let parent = self.parent;
+        if !self.expanded {
+            parent.output += "{ ";
+            let formatter = parent.formatter();
+            formatter.write_string(&mut parent.output, self.variant, parent.indent_depth);
+            parent.output += " = ";
+            parent.output += &self.inner.output;
+            parent.output += " }";
+            return;
+        }
+        parent.entries.push(Attr {
+            key: variant_key(self.variant),
+            value: AttrValue::Rendered(self.inner.output),
+            comment: None,
+            dynamic: false,
+        });
+        parent.container = Container::Compact;
+        parent.write_attrset();
+    }
+}
+
+/// Strips the quotes from a rendered attribute name so it can be compared
+/// with unquoted names
+fn attr_name(key: &str) -> &str {
+    key.strip_prefix('"')
+        .and_then(|k| k.strip_suffix('"'))
+        .unwrap_or(key)
+}
+
+/// Converts a variant name into an attribute name by lowercasing its first character
+pub(crate) fn variant_key(variant: &str) -> String {
+    variant
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            if i == 0 && c.is_uppercase() {
+                c.to_lowercase()
+                    .next()
+                    .expect("This should be an iterable of one character.")
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+impl<'s, 'a> ser::Serializer for &'s mut Serializer<'a> {
     type Ok = ();
     type Error = Error;
 
     type SerializeSeq = Self;
     type SerializeTuple = Self;
     type SerializeTupleStruct = Self;
-    type SerializeTupleVariant = Self;
+    type SerializeTupleVariant = VariantSerializer<'s, 'a>;
     type SerializeMap = Self;
     type SerializeStruct = Self;
-    type SerializeStructVariant = Self;
+    type SerializeStructVariant = VariantSerializer<'s, 'a>;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
-        self.output += if v { "true" } else { "false" };
+        self.write_scalar(if v { "true" } else { "false" });
         Ok(())
     }
 
@@ -44,7 +851,7 @@ impl ser::Serializer for &mut Serializer {
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
-        self.output += &v.to_string();
+        self.write_scalar(&v.to_string());
         Ok(())
     }
 
@@ -61,7 +868,7 @@ impl ser::Serializer for &mut Serializer {
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.output += &v.to_string();
+        self.write_scalar(&v.to_string());
         Ok(())
     }
 
@@ -70,7 +877,12 @@ impl ser::Serializer for &mut Serializer {
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        self.output += &v.to_string();
+        if !v.is_finite() {
+            return Err(ser::Error::custom(format!("{v} cannot be written in Nix")));
+        }
+        let mut float = String::new();
+        self.formatter().write_float(&mut float, v);
+        self.write_scalar(&float);
         Ok(())
     }
 
@@ -79,78 +891,35 @@ impl ser::Serializer for &mut Serializer {
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        let multiline = v.contains('\n') && v.len() >= 80;
-
-        match multiline {
-            true => {
-                self.output += "''\n";
-                self.indent_depth += 1;
-                self.indent();
+        let formatter = self.formatter();
+        match self.strings {
+            StringMode::Quoted if self.role == Role::MapKey => {
+                formatter.write_attr_name(&mut self.output, v)
             }
-            false => self.output += "\"",
-        }
-
-        let mut chars = v.chars().peekable();
-
-        while let Some(c) = chars.next() {
-            match c {
-                '\'' => match chars.peek() {
-                    Some(&'\'') if multiline => {
-                        self.output += "''";
-                    }
-                    _ => self.output += "'",
-                },
-                '"' => match multiline {
-                    true => self.output += "\"",
-                    false => self.output += "\\\"",
-                },
-                '\\' => match multiline {
-                    true => self.output += "\\",
-                    false => self.output += "\\\\",
-                },
-                '$' => match chars.peek() {
-                    Some(&'{') => self.output += "''$",
-                    _ => self.output += "$",
-                },
-                '\n' => match multiline {
-                    true => {
-                        self.output += "\n";
-                        self.indent();
-                    }
-                    false => self.output += "\\n",
-                },
-                '\t' => match multiline {
-                    true => self.output += "\t",
-                    false => self.output += "\\t",
-                },
-                c => self.output.push(c),
+            StringMode::Quoted => formatter.write_string(&mut self.output, v, self.indent_depth),
+            StringMode::Path => self.output += &render_path(v),
+            StringMode::Literal => {
+                if self.config.strict_literals {
+                    crate::literal::validate(v, self.env.field.as_deref())?;
+                }
+                self.literal = true;
+                self.write_literal(v);
             }
-        }
-
-        match multiline {
-            true => {
-                self.output += "\n";
-                self.indent_depth -= 1;
-                self.indent();
-                self.output += "''"
+            StringMode::Indented => {
+                formatter.write_indented_string(&mut self.output, v, self.indent_depth)
             }
-            false => self.output += "\"",
         }
-
         Ok(())
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        self.output += "[\n";
+        use ser::SerializeSeq;
 
-        for byte in v.iter() {
-            self.output += "  ";
-            self.serialize_u8(*byte)?;
-            self.output += "\n";
+        let mut seq = self.serialize_seq(Some(v.len()))?;
+        for byte in v {
+            seq.serialize_element(byte)?;
         }
-
-        self.output += "]";
-        Ok(())
+        seq.end()
     }
 
     fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
@@ -158,12 +927,21 @@ impl ser::Serializer for &mut Serializer {
         Ok(())
     }
 
-    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Self::Ok, Self::Error> {
+        if std::mem::take(&mut self.hinting) {
+            self.write_none(Some(describe_type(name)));
+            return Ok(());
+        }
+        if self.config.unit_structs_as_attrsets {
+            self.write_attrset();
+            return Ok(());
+        }
         self.serialize_unit()
     }
 
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
-        self.serialize_unit()
+        self.write_none(None);
+        Ok(())
     }
 
     fn serialize_some<T>(self, value: &T) -> Result<Self::Ok, Self::Error>
@@ -190,14 +968,92 @@ impl ser::Serializer for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        if name == crate::path::TOKEN || name == crate::literal::TOKEN {
-            use crate::path::RawEmitter;
-            let emitter = RawEmitter {
+        let Some(token) = Token::from_name(name) else {
+            return value.serialize(self);
+        };
+        match token {
+            Token::Path | Token::Raw => value.serialize(RawEmitter {
                 output: &mut self.output,
-            };
-            return value.serialize(emitter);
+            }),
+            Token::Literal => {
+                self.literal = true;
+                let mut literal = String::new();
+                value.serialize(RawEmitter {
+                    output: &mut literal,
+                })?;
+                if self.config.strict_literals {
+                    crate::literal::validate(&literal, self.env.field.as_deref())?;
+                }
+                self.write_literal(&literal);
+                Ok(())
+            }
+            Token::Rendered => {
+                let mut rendered = String::new();
+                value.serialize(RawEmitter {
+                    output: &mut rendered,
+                })?;
+                self.write_rendered(&rendered);
+                Ok(())
+            }
+            Token::Source => {
+                // The source is written on its own, where the names bound
+                // around this value are not in scope
+                let mut source = Serializer::new(self.config);
+                value.serialize(&mut source)?;
+                let formatter = self.formatter();
+                formatter.write_string(&mut self.output, &source.output, self.indent_depth);
+                Ok(())
+            }
+            Token::Lib => {
+                self.output += &self.config.lib;
+                self.output.push('.');
+                value.serialize(RawEmitter {
+                    output: &mut self.output,
+                })
+            }
+            Token::Term => {
+                let mut term = self.nested();
+                term.strings = self.strings;
+                value.serialize(&mut term)?;
+                self.write_term(&term.output);
+                Ok(())
+            }
+            Token::Inherit => {
+                if self.role != Role::AttrValue {
+                    return Err(ser::Error::custom(
+                        "only the value of an attribute can be inherited",
+                    ));
+                }
+                let mut from = String::new();
+                value.serialize(RawEmitter { output: &mut from })?;
+                self.outcome = Outcome::Inherited { from };
+                Ok(())
+            }
+            Token::Rec => {
+                self.attr_path = false;
+                self.serialize_rec(value)
+            }
+            Token::AttrRef => {
+                let mut referenced = String::new();
+                value.serialize(RawEmitter {
+                    output: &mut referenced,
+                })?;
+                self.write_reference(referenced)
+            }
+            Token::Placeholder => {
+                self.hinting = true;
+                value.serialize(self)
+            }
+            Token::AttrPath => {
+                self.attr_path = self.role == Role::AttrValue;
+                value.serialize(self)
+            }
+            Token::Strings(mode) => {
+                self.strings = mode;
+                value.serialize(self)
+            }
+            Token::Unpack(payload) => value.serialize(Unpacker::new(self, payload)),
         }
-        value.serialize(self)
     }
 
     fn serialize_newtype_variant<T>(
@@ -210,36 +1066,32 @@ impl ser::Serializer for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        let lower: String = variant
-            .chars()
-            .enumerate()
-            .map(|(i, c)| {
-                if i == 0 && c.is_uppercase() {
-                    c.to_lowercase()
-                        .next()
-                        .expect("This should be an iterable of one character.")
-                } else {
-                    c
-                }
-            })
-            .collect();
-
-        self.output += "{ ";
-        self.output += &lower;
-        self.output += " = ";
-        value.serialize(&mut *self)?;
-        self.output += "; }";
+        if !self.formatter().expand_variants() {
+            let mut inner = Serializer {
+                strings: self.strings,
+                ..self.nested()
+            };
+            value.serialize(&mut inner)?;
+            self.output += "{ ";
+            self.output += &variant_key(variant);
+            self.output += " = ";
+            self.output += &inner.output;
+            self.output += "; }";
+            return Ok(());
+        }
+        self.push_attr(variant_key(variant), value)?;
+        self.container = Container::Compact;
+        self.write_attrset();
         Ok(())
     }
 
     fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
-        self.output += "[";
-        self.indent_depth += 1;
         Ok(self)
     }
 
-    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
-        self.serialize_seq(Some(len))
+    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.container = Container::Compact;
+        Ok(self)
     }
 
     fn serialize_tuple_struct(
@@ -247,7 +1099,7 @@ impl ser::Serializer for &mut Serializer {
         _name: &'static str,
         len: usize,
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
-        self.serialize_seq(Some(len))
+        self.serialize_tuple(len)
     }
 
     fn serialize_tuple_variant(
@@ -257,24 +1109,25 @@ impl ser::Serializer for &mut Serializer {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        self.output += "{ ";
-        variant.serialize(&mut *self)?;
-        self.output += " = [";
-        Ok(self)
+        Ok(VariantSerializer::new(self, variant))
     }
 
     fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
-        self.output += "{";
-        self.indent_depth += 1;
+        if std::mem::take(&mut self.attr_path) {
+            // The attributes are written at the depth of the enclosing attrset
+            self.splice = true;
+            self.indent_depth -= 1;
+        }
         Ok(self)
     }
 
     fn serialize_struct(
         self,
         _name: &'static str,
-        len: usize,
+        _len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        self.serialize_map(Some(len))
+        self.container = Container::Struct;
+        self.serialize_map(None)
     }
 
     fn serialize_struct_variant(
@@ -284,9 +1137,6 @@ impl ser::Serializer for &mut Serializer {
         variant: &'static str,
         _len: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        self.output += "{ ";
-        variant.serialize(&mut *self)?;
-        self.output += " = {";
-        Ok(self)
+        Ok(VariantSerializer::new(self, variant))
     }
 }
@@ -0,0 +1,209 @@
+use crate::content::Content;
+use crate::format::Formatter;
+
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Finds values that are written several times, so they can be bound once
+/// in a `let` enclosing the output, as enabled by
+/// [`Config::share_repeated`](crate::Config::share_repeated).
+///
+/// The output is serialized twice. The first pass records every attribute
+/// value and list element, and the second replaces the outermost repeated
+/// ones with the name they are bound to. Values are identified by the order
+/// they are serialized in, which is the same in both passes, and are
+/// repeated when they are recorded as the same [`Content`], whatever text
+/// they are written as.
+///
+/// Values inside functions and `let` expressions are left alone, since they
+/// may refer to the arguments or bindings in scope.
+pub struct Sharing {
+    min_lines: usize,
+    replacing: bool,
+    /// Values recorded by the first pass, in the order they were started
+    values: Vec<Value>,
+    /// Values being serialized, innermost last
+    stack: Vec<usize>,
+    /// Number of scopes the current value is inside
+    scopes: usize,
+    next: usize,
+    replacements: BTreeMap<usize, String>,
+    /// `(name, value)` of every binding, with values rendered at depth zero
+    bindings: Vec<(String, String)>,
+}
+
+struct Value {
+    parent: Option<usize>,
+    /// The value and its rendering at depth zero, if it is large enough to
+    /// be shared
+    shared: Option<(Content, String)>,
+}
+
+const BINDING_PREFIX: &str = "shared";
+
+/// Whether `name` can be the name a shared value is bound to
+pub(crate) fn is_binding_name(name: &str) -> bool {
+    name.strip_prefix(BINDING_PREFIX)
+        .is_some_and(|n| !n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+}
+
+impl Sharing {
+    pub fn new(min_lines: usize) -> Self {
+        Sharing {
+            min_lines,
+            replacing: false,
+            values: Vec::new(),
+            stack: Vec::new(),
+            scopes: 0,
+            next: 0,
+            replacements: BTreeMap::new(),
+            bindings: Vec::new(),
+        }
+    }
+
+    /// Whether values are written at the top of the output, where the
+    /// bindings can be placed
+    pub fn is_root(&self) -> bool {
+        self.stack.is_empty() && self.scopes == 0
+    }
+
+    /// Enters a function or `let` expression, whose values are never shared
+    pub fn enter_scope(&mut self) {
+        self.scopes += 1;
+    }
+
+    pub fn exit_scope(&mut self) {
+        self.scopes -= 1;
+    }
+
+    /// Starts serializing a value, returning its id unless it is inside a
+    /// scope
+    pub fn begin(&mut self) -> Option<usize> {
+        if self.scopes > 0 {
+            return None;
+        }
+        let id = self.next;
+        self.next += 1;
+        if !self.replacing {
+            self.values.push(Value {
+                parent: self.stack.last().copied(),
+                shared: None,
+            });
+        }
+        self.stack.push(id);
+        Some(id)
+    }
+
+    /// Finishes serializing the value `id`, written to `output` on a line at
+    /// `depth`, with `record` recording it when it is large enough to be
+    /// shared
+    pub fn end(
+        &mut self,
+        id: Option<usize>,
+        output: &mut String,
+        depth: usize,
+        formatter: &dyn Formatter,
+        record: impl FnOnce() -> Option<Content>,
+    ) {
+        let Some(id) = id else {
+            return;
+        };
+        self.stack.pop();
+
+        if self.replacing {
+            if let Some(name) = self.replacements.get(&id) {
+                *output = name.clone();
+            }
+            return;
+        }
+        if output.lines().count() >= self.min_lines.max(2) {
+            self.values[id].shared =
+                unindent(output, depth, formatter).and_then(|rendered| Some((record()?, rendered)));
+        }
+    }
+
+    /// Decides which values to replace after the first pass, and prepares
+    /// for the second
+    pub fn plan(&mut self) {
+        let mut shared: BTreeSet<&Content> = {
+            let mut counts: BTreeMap<&Content, usize> = BTreeMap::new();
+            for (content, _) in self.values.iter().filter_map(|v| v.shared.as_ref()) {
+                *counts.entry(content).or_default() += 1;
+            }
+            counts
+                .into_iter()
+                .filter(|(_, count)| *count > 1)
+                .map(|(content, _)| content)
+                .collect()
+        };
+
+        // Values inside a replaced value disappear with it, which can leave
+        // other values with a single occurrence
+        let replaced = loop {
+            let mut covered = vec![false; self.values.len()];
+            let mut replaced = Vec::new();
+            for (id, value) in self.values.iter().enumerate() {
+                if value.parent.is_some_and(|p| covered[p]) {
+                    covered[id] = true;
+                } else if let Some((content, rendered)) = &value.shared
+                    && shared.contains(content)
+                {
+                    covered[id] = true;
+                    replaced.push((id, content, rendered));
+                }
+            }
+
+            let mut counts: BTreeMap<&Content, usize> = BTreeMap::new();
+            for (_, content, _) in &replaced {
+                *counts.entry(content).or_default() += 1;
+            }
+            let before = shared.len();
+            shared.retain(|content| counts.get(content).is_some_and(|c| *c > 1));
+            if shared.len() == before {
+                break replaced;
+            }
+        };
+
+        let mut names: BTreeMap<&Content, String> = BTreeMap::new();
+        for (id, content, rendered) in replaced {
+            let name = names.entry(content).or_insert_with(|| {
+                let name = format!("{BINDING_PREFIX}{}", self.bindings.len() + 1);
+                self.bindings.push((name.clone(), rendered.to_string()));
+                name
+            });
+            self.replacements.insert(id, name.clone());
+        }
+
+        self.replacing = true;
+        self.next = 0;
+    }
+
+    /// Takes the bindings to write, once the values referring to them are
+    /// about to be written
+    pub fn take_bindings(&mut self) -> Vec<(String, String)> {
+        match self.replacing {
+            true => std::mem::take(&mut self.bindings),
+            false => Vec::new(),
+        }
+    }
+}
+
+/// Removes the indentation of every line after the first of a value written
+/// on a line at `depth`, or returns `None` if a line is not indented, as in a
+/// multi-line [`NixLiteral`](crate::NixLiteral)
+fn unindent(output: &str, depth: usize, formatter: &dyn Formatter) -> Option<String> {
+    let mut indent = String::new();
+    formatter.write_indent(&mut indent, depth);
+
+    let mut unindented = String::with_capacity(output.len());
+    for (i, line) in output.split('\n').enumerate() {
+        if i > 0 {
+            unindented.push('\n');
+            if !line.is_empty() {
+                unindented += line.strip_prefix(&indent)?;
+                continue;
+            }
+        }
+        unindented += line;
+    }
+    Some(unindented)
+}
@@ -1,9 +1,21 @@
 use super::error::Error;
-use super::ser::Serializer;
+use super::map::is_nix_identifier;
+use super::ser::{Serializer, VariantSerializer};
 
 use serde::{Serialize, ser};
 
-impl ser::SerializeStruct for &mut Serializer {
+/// Renders a struct field name as an attribute name, quoting the names that
+/// are not identifiers, such as `8080/tcp` or `if`
+fn field_key(serializer: &Serializer, key: &'static str) -> String {
+    if is_nix_identifier(key) {
+        return key.to_string();
+    }
+    let mut base_key = String::new();
+    serializer.formatter().write_attr_name(&mut base_key, key);
+    base_key
+}
+
+impl ser::SerializeStruct for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -11,57 +23,17 @@ impl ser::SerializeStruct for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        if self.output.ends_with("{") {
-            self.output += "\n";
-        }
-
-        let mut key_serializer = Serializer {
-            output: String::new(),
-            pending_key: None,
-            indent_depth: self.indent_depth,
-        };
-
-        let mut val_serializer = Serializer {
-            output: String::new(),
-            pending_key: None,
-            indent_depth: self.indent_depth,
-        };
-
-        value.serialize(&mut val_serializer)?;
-        let val = val_serializer.output;
-
-        self.indent();
-        key.serialize(&mut key_serializer)?;
-        let mut base_key = key_serializer.output;
-
-        // TODO: Add cases where keys need to be escaped
-        if true {
-            let try_strip = base_key
-                .strip_prefix("\"")
-                .and_then(|s| s.strip_suffix("\""));
-
-            if let Some(str) = try_strip {
-                base_key = str.to_string()
-            }
-        }
-
-        self.output += &base_key;
-        self.output += " = ";
-        self.output += &val;
-        self.output += ";\n";
-
-        Ok(())
+        let key = field_key(self, key);
+        self.push_attr(key, value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.indent_depth -= 1;
-        self.indent();
-        self.output += "}";
+        self.write_attrset();
         Ok(())
     }
 }
 
-impl ser::SerializeStructVariant for &mut Serializer {
+impl ser::SerializeStructVariant for VariantSerializer<'_, '_> {
     type Ok = ();
     type Error = Error;
 
@@ -69,15 +41,19 @@ impl ser::SerializeStructVariant for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        self.output += " ";
-
-        key.serialize(&mut **self)?;
-        self.output += " = { ";
-        value.serialize(&mut **self)
+        if !self.expanded {
+            return self.push_inline(Some(key), value);
+        }
+        let key = field_key(&self.inner, key);
+        self.inner.push_attr(key, value)
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output += "}; }";
+    fn end(mut self) -> Result<Self::Ok, Self::Error> {
+        match self.expanded {
+            true => self.inner.write_attrset(),
+            false => self.inner.output = format!("{{{}}};", self.inner.output),
+        }
+        VariantSerializer::end(self);
         Ok(())
     }
 }
@@ -0,0 +1,1010 @@
+use crate::map::KEYWORDS;
+use std::fmt;
+use std::ops::Range;
+
+/// An error in the syntax of a Nix expression, at a position in its source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct SyntaxError {
+    pub message: String,
+    /// Byte offset of the error in the source
+    pub offset: usize,
+    /// Line of the error, starting at 1
+    pub line: usize,
+    /// Column of the error in characters, starting at 1
+    pub column: usize,
+}
+
+impl SyntaxError {
+    /// An error at the byte `offset` of `src`
+    pub fn new(src: &str, offset: usize, message: impl Into<String>) -> Self {
+        let before = &src[..offset];
+        let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+        SyntaxError {
+            message: message.into(),
+            offset,
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl fmt::Display for SyntaxError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            1 => write!(f, "{} at column {}", self.message, self.column),
+            line => write!(f, "{} at line {line}, column {}", self.message, self.column),
+        }
+    }
+}
+
+/// A parsed expression, with the range of its source
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Node {
+    pub kind: Kind,
+    pub span: Range<usize>,
+}
+
+/// The expressions that carry data, with everything else left as
+/// [`Kind::Other`]
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Kind {
+    Int(i64),
+    Float(f64),
+    /// A string, which is indented if it is written between `''`
+    String {
+        parts: Vec<StrPart>,
+        indented: bool,
+    },
+    /// A path without interpolations, such as `./a`, `~/a` or `<nixpkgs>`
+    Path,
+    Uri,
+    Ident(String),
+    List(Vec<Node>),
+    Attrs {
+        rec: bool,
+        bindings: Vec<Binding>,
+    },
+    Let {
+        bindings: Vec<Binding>,
+        body: Box<Node>,
+    },
+    Select {
+        expr: Box<Node>,
+        path: Vec<AttrName>,
+        default: Option<Box<Node>>,
+    },
+    Paren(Box<Node>),
+    /// An addition, such as `./. + "/a b"`
+    Add(Box<Node>, Box<Node>),
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StrPart {
+    /// Text, with escapes already replaced
+    Literal(String),
+    Interpolation(Node),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Binding {
+    /// `path = value;`
+    Attr { path: Vec<AttrName>, value: Node },
+    /// `inherit names;` or `inherit (from) names;`
+    Inherit {
+        from: Option<Node>,
+        names: Vec<AttrName>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum AttrName {
+    Static {
+        name: String,
+        span: Range<usize>,
+    },
+    /// A name computed by an expression, written as `${expr}` or as a string
+    /// with interpolations
+    Dynamic(Node),
+}
+
+impl AttrName {
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            AttrName::Static { span, .. } => span.clone(),
+            AttrName::Dynamic(node) => node.span.clone(),
+        }
+    }
+}
+
+/// Parses `src` as a single complete expression.
+pub(crate) fn parse(src: &str) -> Result<Node, SyntaxError> {
+    let mut parser = Parser {
+        src,
+        pos: 0,
+        depth: 0,
+    };
+    let node = parser.expr()?;
+    let (tok, span) = parser.peek()?;
+    if tok != Tok::Eof {
+        return Err(parser.unexpected(tok, span));
+    }
+    Ok(node)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tok<'s> {
+    Ident(&'s str),
+    Keyword(&'s str),
+    Int,
+    Float,
+    /// A path, or the start of a path followed by an interpolation
+    Path {
+        interpolated: bool,
+    },
+    Uri,
+    /// `"`
+    Str,
+    /// `''`
+    IndStr,
+    Op(&'static str),
+    Eof,
+    Invalid(char),
+}
+
+/// Operators, longest first
+const OPERATORS: [&str; 31] = [
+    "...", "${", "==", "!=", "<=", ">=", "&&", "||", "->", "//", "++", "+", "-", "*", "/", "<",
+    ">", "!", "?", ".", ";", ":", "=", "@", "(", ")", "[", "]", "{", "}", ",",
+];
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Assoc {
+    Left,
+    Right,
+    None,
+}
+
+/// Binding level and associativity of a binary operator, lower binding
+/// tighter, as listed in the Nix manual
+fn binary_op(op: &str) -> Option<(u8, Assoc)> {
+    Some(match op {
+        "?" => (4, Assoc::None),
+        "++" => (5, Assoc::Right),
+        "*" | "/" => (6, Assoc::Left),
+        "+" | "-" => (7, Assoc::Left),
+        "//" => (9, Assoc::Right),
+        "<" | "<=" | ">" | ">=" => (10, Assoc::None),
+        "==" | "!=" => (11, Assoc::None),
+        "&&" => (12, Assoc::Left),
+        "||" => (13, Assoc::Left),
+        "->" => (14, Assoc::Right),
+        _ => return None,
+    })
+}
+
+fn is_path_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '-' | '+')
+}
+
+fn is_uri_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || "%/?:@&=+$,-_.!~*'".contains(c)
+}
+
+/// Length of the longest prefix of `s` whose characters match `f`
+fn span_of(s: &str, f: impl Fn(char) -> bool) -> usize {
+    s.find(|c| !f(c)).unwrap_or(s.len())
+}
+
+fn ident_len(s: &str) -> usize {
+    match s.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+            1 + span_of(&s[1..], |c| {
+                c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-')
+            })
+        }
+        _ => 0,
+    }
+}
+
+fn float_len(s: &str) -> usize {
+    let digits = |s: &str| span_of(s, |c| c.is_ascii_digit());
+    let mut len = match s.chars().next() {
+        Some('1'..='9') => {
+            let int = digits(s);
+            if !s[int..].starts_with('.') {
+                return 0;
+            }
+            int + 1 + digits(&s[int + 1..])
+        }
+        Some('0' | '.') => {
+            let zero = s.starts_with('0') as usize;
+            if !s[zero..].starts_with('.') {
+                return 0;
+            }
+            let fraction = digits(&s[zero + 1..]);
+            if fraction == 0 {
+                return 0;
+            }
+            zero + 1 + fraction
+        }
+        _ => return 0,
+    };
+
+    if let Some(exp) = s[len..].strip_prefix(['e', 'E']) {
+        let sign = exp.starts_with(['+', '-']) as usize;
+        let exp_digits = digits(&exp[sign..]);
+        if exp_digits > 0 {
+            len += 1 + sign + exp_digits;
+        }
+    }
+    len
+}
+
+/// Length of a path, and whether it is followed by an interpolation
+fn path_len(s: &str) -> (usize, bool) {
+    let mut len = span_of(s, is_path_char);
+    let mut segments = 0;
+    loop {
+        let rest = &s[len..];
+        if rest.starts_with("${") && segments > 0 {
+            return (len, true);
+        }
+        let Some(after_slash) = rest.strip_prefix('/') else {
+            break;
+        };
+        if after_slash.starts_with("${") {
+            return (len + 1, true);
+        }
+        let segment = span_of(after_slash, is_path_char);
+        if segment == 0 {
+            break;
+        }
+        len += 1 + segment;
+        segments += 1;
+    }
+    match segments {
+        0 => (0, false),
+        _ => (len, false),
+    }
+}
+
+fn home_path_len(s: &str) -> (usize, bool) {
+    match s.strip_prefix('~') {
+        Some(rest) if rest.starts_with('/') => {
+            let (len, interpolated) = path_len(rest);
+            match len {
+                0 if rest.starts_with("/${") => (2, true),
+                0 => (0, false),
+                len => (1 + len, interpolated),
+            }
+        }
+        _ => (0, false),
+    }
+}
+
+fn search_path_len(s: &str) -> usize {
+    let Some(rest) = s.strip_prefix('<') else {
+        return 0;
+    };
+    let mut len = span_of(rest, is_path_char);
+    if len == 0 {
+        return 0;
+    }
+    while let Some(after_slash) = rest[len..].strip_prefix('/') {
+        let segment = span_of(after_slash, is_path_char);
+        if segment == 0 {
+            return 0;
+        }
+        len += 1 + segment;
+    }
+    match rest[len..].starts_with('>') {
+        true => len + 2,
+        false => 0,
+    }
+}
+
+fn uri_len(s: &str) -> usize {
+    match s.chars().next() {
+        Some(c) if c.is_ascii_alphabetic() => {}
+        _ => return 0,
+    }
+    let scheme = span_of(s, |c| {
+        c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.')
+    });
+    if !s[scheme..].starts_with(':') {
+        return 0;
+    }
+    match span_of(&s[scheme + 1..], is_uri_char) {
+        0 => 0,
+        rest => scheme + 1 + rest,
+    }
+}
+
+/// How deeply expressions can be nested before parsing fails, which keeps
+/// untrusted input from overflowing the stack. Each level takes over 20 KiB
+/// of stack in debug builds, so this is lower than the 128 of `serde_json`
+/// to stay within the 2 MiB of a spawned thread.
+const MAX_DEPTH: usize = 64;
+
+struct Parser<'s> {
+    src: &'s str,
+    pos: usize,
+    /// How many nested expressions are being parsed
+    depth: usize,
+}
+
+impl<'s> Parser<'s> {
+    fn error(&self, message: impl Into<String>, offset: usize) -> SyntaxError {
+        SyntaxError::new(self.src, offset, message)
+    }
+
+    fn unexpected(&self, tok: Tok, span: Range<usize>) -> SyntaxError {
+        match tok {
+            Tok::Eof => self.error("unexpected end of input", span.start),
+            Tok::Invalid(c) => self.error(format!("unexpected character `{c}`"), span.start),
+            _ => self.error(
+                format!("unexpected `{}`", &self.src[span.clone()]),
+                span.start,
+            ),
+        }
+    }
+
+    /// Skips whitespace and comments
+    fn skip_trivia(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            let rest = &self.src[self.pos..];
+            let trimmed = rest.trim_start();
+            self.pos += rest.len() - trimmed.len();
+            if trimmed.starts_with('#') {
+                self.pos += trimmed.find('\n').unwrap_or(trimmed.len());
+            } else if let Some(comment) = trimmed.strip_prefix("/*") {
+                let end = comment
+                    .find("*/")
+                    .ok_or_else(|| self.error("unterminated comment", self.pos))?;
+                self.pos += 2 + end + 2;
+            } else {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Returns the next token without consuming it
+    fn peek(&mut self) -> Result<(Tok<'s>, Range<usize>), SyntaxError> {
+        self.skip_trivia()?;
+        let start = self.pos;
+        let rest = &self.src[start..];
+        let Some(c) = rest.chars().next() else {
+            return Ok((Tok::Eof, start..start));
+        };
+
+        if rest.starts_with("''") {
+            return Ok((Tok::IndStr, start..start + 2));
+        }
+        if c == '"' {
+            return Ok((Tok::Str, start..start + 1));
+        }
+
+        // The longest token wins, and operators win ties
+        let op = OPERATORS.iter().find(|op| rest.starts_with(**op));
+        let op_len = op.map_or(0, |op| op.len());
+        let ident = ident_len(rest);
+        let int = span_of(rest, |c| c.is_ascii_digit());
+        let float = float_len(rest);
+        let (path, interpolated) = path_len(rest);
+        let (home, home_interpolated) = home_path_len(rest);
+        let search = search_path_len(rest);
+        let uri = uri_len(rest);
+
+        let longest = [op_len, ident, int, float, path, home, search, uri]
+            .into_iter()
+            .max()
+            .unwrap_or(0);
+        let span = start..start + longest;
+        let tok = match longest {
+            0 => Tok::Invalid(c),
+            len if len == op_len => Tok::Op(op.expect("operator")),
+            len if len == ident => match KEYWORDS.contains(&&rest[..len]) {
+                true => Tok::Keyword(&rest[..len]),
+                false => Tok::Ident(&rest[..len]),
+            },
+            len if len == int => Tok::Int,
+            len if len == float => Tok::Float,
+            len if len == path => Tok::Path { interpolated },
+            len if len == home => Tok::Path {
+                interpolated: home_interpolated,
+            },
+            len if len == search => Tok::Path {
+                interpolated: false,
+            },
+            _ => Tok::Uri,
+        };
+        if let Tok::Path {
+            interpolated: false,
+        } = tok
+            && self.src[span.end..].starts_with('/')
+        {
+            return Err(self.error("path has a trailing slash", span.end));
+        }
+        Ok((tok, span))
+    }
+
+    /// Consumes and returns the next token
+    fn bump(&mut self) -> Result<(Tok<'s>, Range<usize>), SyntaxError> {
+        let (tok, span) = self.peek()?;
+        self.pos = span.end;
+        Ok((tok, span))
+    }
+
+    /// Consumes the next token if it is `tok`
+    fn eat(&mut self, tok: Tok) -> Result<Option<Range<usize>>, SyntaxError> {
+        let (next, span) = self.peek()?;
+        if next != tok {
+            return Ok(None);
+        }
+        self.pos = span.end;
+        Ok(Some(span))
+    }
+
+    fn expect(&mut self, tok: Tok) -> Result<Range<usize>, SyntaxError> {
+        let (next, span) = self.peek()?;
+        if next != tok {
+            let expected = match tok {
+                Tok::Op(op) | Tok::Keyword(op) => op,
+                _ => unreachable!("only operators and keywords are expected"),
+            };
+            let found = match next {
+                Tok::Eof => "end of input".to_string(),
+                _ => format!("`{}`", &self.src[span.clone()]),
+            };
+            return Err(self.error(format!("expected `{expected}`, found {found}"), span.start));
+        }
+        self.pos = span.end;
+        Ok(span)
+    }
+
+    /// Runs `parse` one level of nesting deeper, failing at the next token
+    /// once the nesting passes [`MAX_DEPTH`]
+    fn nested<T>(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<T, SyntaxError>,
+    ) -> Result<T, SyntaxError> {
+        if self.depth == MAX_DEPTH {
+            let (_, span) = self.peek()?;
+            return Err(self.error("expression is nested too deeply", span.start));
+        }
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Parses a complete expression, including functions and the keywords
+    /// whose body extends as far as possible
+    fn expr(&mut self) -> Result<Node, SyntaxError> {
+        self.nested(Self::unnested_expr)
+    }
+
+    fn unnested_expr(&mut self) -> Result<Node, SyntaxError> {
+        let (tok, span) = self.peek()?;
+        let start = span.start;
+        let other = |end: usize| Node {
+            kind: Kind::Other,
+            span: start..end,
+        };
+
+        match tok {
+            Tok::Keyword("if") => {
+                self.bump()?;
+                self.expr()?;
+                self.expect(Tok::Keyword("then"))?;
+                self.expr()?;
+                self.expect(Tok::Keyword("else"))?;
+                let otherwise = self.expr()?;
+                Ok(other(otherwise.span.end))
+            }
+            Tok::Keyword("assert" | "with") => {
+                self.bump()?;
+                self.expr()?;
+                self.expect(Tok::Op(";"))?;
+                let body = self.expr()?;
+                Ok(other(body.span.end))
+            }
+            Tok::Keyword("let") if self.second()?.0 != Tok::Op("{") => {
+                self.bump()?;
+                let bindings = self.bindings(true)?;
+                self.expect(Tok::Keyword("in"))?;
+                let body = self.expr()?;
+                Ok(Node {
+                    span: start..body.span.end,
+                    kind: Kind::Let {
+                        bindings,
+                        body: Box::new(body),
+                    },
+                })
+            }
+            Tok::Ident(_) => match self.second()?.0 {
+                Tok::Op(":") => {
+                    self.bump()?;
+                    self.bump()?;
+                    let body = self.expr()?;
+                    Ok(other(body.span.end))
+                }
+                Tok::Op("@") => {
+                    self.bump()?;
+                    self.bump()?;
+                    if !self.formals()? {
+                        let (tok, span) = self.peek()?;
+                        return Err(self.unexpected(tok, span));
+                    }
+                    self.expect(Tok::Op(":"))?;
+                    let body = self.expr()?;
+                    Ok(other(body.span.end))
+                }
+                _ => self.binary(14),
+            },
+            Tok::Op("{") => {
+                let before = self.pos;
+                if self.formals().unwrap_or(false) {
+                    if self.eat(Tok::Op("@"))?.is_some() {
+                        match self.bump()? {
+                            (Tok::Ident(_), _) => {}
+                            (tok, span) => return Err(self.unexpected(tok, span)),
+                        }
+                    }
+                    if self.eat(Tok::Op(":"))?.is_some() {
+                        let body = self.expr()?;
+                        return Ok(other(body.span.end));
+                    }
+                }
+                self.pos = before;
+                self.binary(14)
+            }
+            _ => self.binary(14),
+        }
+    }
+
+    /// Returns the token after the next one
+    fn second(&mut self) -> Result<(Tok<'s>, Range<usize>), SyntaxError> {
+        let before = self.pos;
+        self.bump()?;
+        let second = self.peek();
+        self.pos = before;
+        second
+    }
+
+    /// Parses the arguments of a function, such as `{ a, b ? 1, ... }`,
+    /// returning whether they are valid
+    fn formals(&mut self) -> Result<bool, SyntaxError> {
+        if self.eat(Tok::Op("{"))?.is_none() {
+            return Ok(false);
+        }
+        loop {
+            match self.bump()?.0 {
+                Tok::Op("}") => return Ok(true),
+                Tok::Op("...") => return Ok(self.eat(Tok::Op("}"))?.is_some()),
+                Tok::Ident(_) => {
+                    if self.eat(Tok::Op("?"))?.is_some() {
+                        self.expr()?;
+                    }
+                    match self.bump()?.0 {
+                        Tok::Op(",") => {}
+                        Tok::Op("}") => return Ok(true),
+                        _ => return Ok(false),
+                    }
+                }
+                _ => return Ok(false),
+            }
+        }
+    }
+
+    /// Parses operators binding at most at `level`
+    fn binary(&mut self, level: u8) -> Result<Node, SyntaxError> {
+        let (tok, span) = self.peek()?;
+        let start = span.start;
+        let mut lhs = match tok {
+            Tok::Op("!") => {
+                self.bump()?;
+                let operand = self.nested(|p| p.binary(7))?;
+                Node {
+                    kind: Kind::Other,
+                    span: start..operand.span.end,
+                }
+            }
+            Tok::Op("-") => {
+                self.bump()?;
+                let operand = self.nested(|p| p.binary(2))?;
+                let kind = match operand.kind {
+                    Kind::Int(i) => Kind::Int(-i),
+                    Kind::Float(f) => Kind::Float(-f),
+                    _ => Kind::Other,
+                };
+                Node {
+                    kind,
+                    span: start..operand.span.end,
+                }
+            }
+            _ => self.application()?,
+        };
+
+        while let Tok::Op(op) = self.peek()?.0 {
+            let Some((op_level, assoc)) = binary_op(op) else {
+                break;
+            };
+            if op_level > level {
+                break;
+            }
+            self.bump()?;
+
+            lhs = match op {
+                "?" => Node {
+                    kind: Kind::Other,
+                    span: start..self.attr_path()?.last().expect("attribute path").span().end,
+                },
+                _ => {
+                    let rhs_level = match assoc {
+                        Assoc::Right => op_level,
+                        Assoc::Left | Assoc::None => op_level - 1,
+                    };
+                    let rhs = self.nested(|p| p.binary(rhs_level))?;
+                    let span = start..rhs.span.end;
+                    let kind = match op {
+                        "+" => Kind::Add(Box::new(lhs), Box::new(rhs)),
+                        _ => Kind::Other,
+                    };
+                    Node { kind, span }
+                }
+            };
+
+            if assoc == Assoc::None {
+                let (next, next_span) = self.peek()?;
+                if let Tok::Op(next_op) = next
+                    && binary_op(next_op).is_some_and(|(l, _)| l == op_level)
+                {
+                    return Err(self.error(
+                        format!("`{}` cannot follow `{}` without parentheses", next_op, op),
+                        next_span.start,
+                    ));
+                }
+            }
+        }
+        Ok(lhs)
+    }
+
+    /// Parses function applications
+    fn application(&mut self) -> Result<Node, SyntaxError> {
+        let mut function = self.select()?;
+        loop {
+            let (tok, _) = self.peek()?;
+            let starts_arg = matches!(
+                tok,
+                Tok::Ident(_)
+                    | Tok::Int
+                    | Tok::Float
+                    | Tok::Path { .. }
+                    | Tok::Uri
+                    | Tok::Str
+                    | Tok::IndStr
+                    | Tok::Op("(" | "[" | "{")
+                    | Tok::Keyword("rec")
+            );
+            if !starts_arg {
+                return Ok(function);
+            }
+            let arg = self.select()?;
+            function = Node {
+                kind: Kind::Other,
+                span: function.span.start..arg.span.end,
+            };
+        }
+    }
+
+    /// Parses an attribute selection, such as `a.b or c`
+    fn select(&mut self) -> Result<Node, SyntaxError> {
+        let expr = self.simple()?;
+        if self.eat(Tok::Op("."))?.is_none() {
+            return Ok(expr);
+        }
+        let path = self.attr_path()?;
+        let mut end = path.last().expect("attribute path").span().end;
+        let default = match self.eat(Tok::Keyword("or"))? {
+            Some(_) => {
+                let default = self.nested(Self::select)?;
+                end = default.span.end;
+                Some(Box::new(default))
+            }
+            None => None,
+        };
+        Ok(Node {
+            span: expr.span.start..end,
+            kind: Kind::Select {
+                expr: Box::new(expr),
+                path,
+                default,
+            },
+        })
+    }
+
+    fn simple(&mut self) -> Result<Node, SyntaxError> {
+        let (tok, span) = self.bump()?;
+        let start = span.start;
+        let text = &self.src[span.clone()];
+        let kind = match tok {
+            Tok::Ident(name) => Kind::Ident(name.to_string()),
+            Tok::Int => match text.parse() {
+                Ok(i) => Kind::Int(i),
+                Err(_) => {
+                    return Err(self.error(format!("integer `{text}` is out of range"), start));
+                }
+            },
+            Tok::Float => match text.parse() {
+                Ok(f) => Kind::Float(f),
+                Err(_) => return Err(self.error(format!("invalid float `{text}`"), start)),
+            },
+            Tok::Path {
+                interpolated: false,
+            } => Kind::Path,
+            Tok::Path { interpolated: true } => {
+                self.interpolated_path()?;
+                Kind::Other
+            }
+            Tok::Uri => Kind::Uri,
+            Tok::Str => self.string(start, false)?,
+            Tok::IndStr => self.string(start, true)?,
+            Tok::Op("(") => {
+                let inner = self.expr()?;
+                self.expect(Tok::Op(")"))?;
+                Kind::Paren(Box::new(inner))
+            }
+            Tok::Op("[") => {
+                let mut elements = Vec::new();
+                while self.eat(Tok::Op("]"))?.is_none() {
+                    if self.peek()?.0 == Tok::Eof {
+                        self.expect(Tok::Op("]"))?;
+                    }
+                    elements.push(self.nested(Self::select)?);
+                }
+                Kind::List(elements)
+            }
+            Tok::Op("{") => {
+                let bindings = self.bindings(false)?;
+                self.expect(Tok::Op("}"))?;
+                Kind::Attrs {
+                    rec: false,
+                    bindings,
+                }
+            }
+            Tok::Keyword("rec") => {
+                self.expect(Tok::Op("{"))?;
+                let bindings = self.bindings(false)?;
+                self.expect(Tok::Op("}"))?;
+                Kind::Attrs {
+                    rec: true,
+                    bindings,
+                }
+            }
+            // The deprecated `let { ... }`, whose value is its `body`
+            Tok::Keyword("let") => {
+                self.expect(Tok::Op("{"))?;
+                self.bindings(false)?;
+                self.expect(Tok::Op("}"))?;
+                Kind::Other
+            }
+            Tok::Eof => return Err(self.error("expected an expression, found end of input", start)),
+            _ => return Err(self.unexpected(tok, span)),
+        };
+        Ok(Node {
+            kind,
+            span: start..self.pos,
+        })
+    }
+
+    /// Parses the rest of a path after an interpolation starts
+    fn interpolated_path(&mut self) -> Result<(), SyntaxError> {
+        loop {
+            let rest = &self.src[self.pos..];
+            if rest.starts_with("${") {
+                self.pos += 2;
+                self.expr()?;
+                self.expect(Tok::Op("}"))?;
+                continue;
+            }
+            let len = span_of(rest, |c| is_path_char(c) || c == '/');
+            if len == 0 {
+                return Ok(());
+            }
+            self.pos += len;
+        }
+    }
+
+    /// Parses the rest of a string after its opening quote
+    fn string(&mut self, start: usize, indented: bool) -> Result<Kind, SyntaxError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        loop {
+            let rest = &self.src[self.pos..];
+            let mut chars = rest.chars();
+            let Some(c) = chars.next() else {
+                return Err(self.error("unterminated string", start));
+            };
+
+            if rest.starts_with("${") {
+                if !literal.is_empty() {
+                    parts.push(StrPart::Literal(std::mem::take(&mut literal)));
+                }
+                self.pos += 2;
+                let expr = self.expr()?;
+                self.expect(Tok::Op("}"))?;
+                parts.push(StrPart::Interpolation(expr));
+                continue;
+            }
+
+            let (text, len): (Cow, usize) = match (indented, c) {
+                (false, '"') => {
+                    self.pos += 1;
+                    break;
+                }
+                (false, '\\') => match chars.next() {
+                    Some(escaped) => (unescape(escaped), 1 + escaped.len_utf8()),
+                    None => return Err(self.error("unterminated string", start)),
+                },
+                (true, '\'') if rest.starts_with("'''") => ("''".into(), 3),
+                (true, '\'') if rest.starts_with("''$") => ("$".into(), 3),
+                (true, '\'') if rest.starts_with("''\\") => match rest[3..].chars().next() {
+                    Some(escaped) => (unescape(escaped), 3 + escaped.len_utf8()),
+                    None => return Err(self.error("unterminated string", start)),
+                },
+                (true, '\'') if rest.starts_with("''") => {
+                    self.pos += 2;
+                    break;
+                }
+                // `$$` is written as is, so a `{` after it is not an
+                // interpolation
+                (_, '$') if rest.starts_with("$$") => ("$$".into(), 2),
+                (_, c) => (c.to_string().into(), c.len_utf8()),
+            };
+            literal += &text;
+            self.pos += len;
+        }
+        if !literal.is_empty() {
+            parts.push(StrPart::Literal(literal));
+        }
+        Ok(Kind::String { parts, indented })
+    }
+
+    /// Parses attribute names separated by `.`
+    fn attr_path(&mut self) -> Result<Vec<AttrName>, SyntaxError> {
+        let mut path = vec![self.attr_name()?];
+        while self.eat(Tok::Op("."))?.is_some() {
+            path.push(self.attr_name()?);
+        }
+        Ok(path)
+    }
+
+    fn attr_name(&mut self) -> Result<AttrName, SyntaxError> {
+        let (tok, span) = self.bump()?;
+        match tok {
+            Tok::Ident(name) | Tok::Keyword(name @ "or") => Ok(AttrName::Static {
+                name: name.to_string(),
+                span,
+            }),
+            Tok::Str => {
+                let kind = self.string(span.start, false)?;
+                let span = span.start..self.pos;
+                match kind {
+                    Kind::String { parts, .. }
+                        if parts.iter().all(|p| matches!(p, StrPart::Literal(_))) =>
+                    {
+                        let name = parts
+                            .into_iter()
+                            .map(|part| match part {
+                                StrPart::Literal(text) => text,
+                                StrPart::Interpolation(_) => unreachable!(),
+                            })
+                            .collect();
+                        Ok(AttrName::Static { name, span })
+                    }
+                    kind => Ok(AttrName::Dynamic(Node { kind, span })),
+                }
+            }
+            Tok::Op("${") => {
+                let expr = self.expr()?;
+                let end = self.expect(Tok::Op("}"))?.end;
+                Ok(AttrName::Dynamic(Node {
+                    kind: expr.kind,
+                    span: span.start..end,
+                }))
+            }
+            Tok::Eof => {
+                Err(self.error("expected an attribute name, found end of input", span.start))
+            }
+            _ => Err(self.error(
+                format!(
+                    "expected an attribute name, found `{}`",
+                    &self.src[span.clone()]
+                ),
+                span.start,
+            )),
+        }
+    }
+
+    /// Parses the bindings of an attribute set or a `let`
+    fn bindings(&mut self, in_let: bool) -> Result<Vec<Binding>, SyntaxError> {
+        let mut bindings = Vec::new();
+        loop {
+            let (tok, _) = self.peek()?;
+            match tok {
+                Tok::Op("}") | Tok::Keyword("in") | Tok::Eof => return Ok(bindings),
+                Tok::Keyword("inherit") => {
+                    self.bump()?;
+                    let from = match self.eat(Tok::Op("("))? {
+                        Some(_) => {
+                            let from = self.expr()?;
+                            self.expect(Tok::Op(")"))?;
+                            Some(from)
+                        }
+                        None => None,
+                    };
+                    let mut names = Vec::new();
+                    while self.eat(Tok::Op(";"))?.is_none() {
+                        let name = self.attr_name()?;
+                        if let AttrName::Dynamic(node) = &name {
+                            return Err(self.error(
+                                "dynamic attributes are not allowed in `inherit`",
+                                node.span.start,
+                            ));
+                        }
+                        names.push(name);
+                    }
+                    bindings.push(Binding::Inherit { from, names });
+                }
+                _ => {
+                    let path = self.attr_path()?;
+                    if in_let && let Some(AttrName::Dynamic(node)) = path.first() {
+                        return Err(self.error(
+                            "dynamic attributes are not allowed in `let`",
+                            node.span.start,
+                        ));
+                    }
+                    self.expect(Tok::Op("="))?;
+                    let value = self.expr()?;
+                    self.expect(Tok::Op(";"))?;
+                    bindings.push(Binding::Attr { path, value });
+                }
+            }
+        }
+    }
+}
+
+type Cow = std::borrow::Cow<'static, str>;
+
+/// Replaces the escapes in the text of an indented string, which must be
+/// valid and without interpolations
+pub(crate) fn unescape_indented(text: &str) -> String {
+    let mut unescaped = String::new();
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        let (text, len): (Cow, usize) = if rest.starts_with("'''") {
+            ("''".into(), 3)
+        } else if rest.starts_with("''$") {
+            ("$".into(), 3)
+        } else if let Some(escaped) = rest.strip_prefix("''\\").and_then(|r| r.chars().next()) {
+            (unescape(escaped), 3 + escaped.len_utf8())
+        } else {
+            (c.to_string().into(), c.len_utf8())
+        };
+        unescaped += &text;
+        rest = &rest[len..];
+    }
+    unescaped
+}
+
+/// Replaces the character after a `\`
+fn unescape(c: char) -> Cow {
+    match c {
+        'n' => "\n".into(),
+        'r' => "\r".into(),
+        't' => "\t".into(),
+        c => c.to_string().into(),
+    }
+}
@@ -102,7 +102,7 @@ mod test {
         };
 
         let library: IndexMap<String, Book> =
-            vec![book_one, book_two, book_three, book_four, book_five]
+            [book_one, book_two, book_three, book_four, book_five]
                 .iter()
                 .map(|b| (b.name.clone(), b.clone()))
                 .collect();
@@ -416,17 +416,17 @@ mod test {
         // Relative path without prefix
         let path = NixPathBuf::new("path with spaces.nix");
         let result = to_string(&path).unwrap();
-        assert_eq!(result, r#"./. + "path with spaces.nix""#);
+        assert_eq!(result, r#"./. + "/path with spaces.nix""#);
 
         // Relative path with ./ prefix
         let path = NixPathBuf::new("./path with spaces.nix");
         let result = to_string(&path).unwrap();
-        assert_eq!(result, r#"./. + "path with spaces.nix""#);
+        assert_eq!(result, r#"./. + "/path with spaces.nix""#);
 
         // Relative path with ../ prefix
         let path = NixPathBuf::new("../path with spaces.nix");
         let result = to_string(&path).unwrap();
-        assert_eq!(result, r#"../. + "path with spaces.nix""#);
+        assert_eq!(result, r#"../. + "/path with spaces.nix""#);
 
         // Absolute path
         let path = NixPathBuf::new("/etc/nixos/path with spaces.nix");
@@ -441,27 +441,27 @@ mod test {
         // Path with double quotes - must be escaped
         let path = NixPathBuf::new(r#"path"with"quotes.nix"#);
         let result = to_string(&path).unwrap();
-        assert_eq!(result, r#"./. + "path\"with\"quotes.nix""#);
+        assert_eq!(result, r#"./. + "/path\"with\"quotes.nix""#);
 
         // Path with single quotes - not valid in Nix path literals
         let path = NixPathBuf::new("path'with'quotes.nix");
         let result = to_string(&path).unwrap();
-        assert_eq!(result, r#"./. + "path'with'quotes.nix""#);
+        assert_eq!(result, r#"./. + "/path'with'quotes.nix""#);
 
         // Path with dollar sign not followed by { - not valid in Nix path literals
         let path = NixPathBuf::new("path$var.nix");
         let result = to_string(&path).unwrap();
-        assert_eq!(result, r#"./. + "path$var.nix""#);
+        assert_eq!(result, r#"./. + "/path$var.nix""#);
 
         // Path with ${ - must be escaped
         let path = NixPathBuf::new("path${var}.nix");
         let result = to_string(&path).unwrap();
-        assert_eq!(result, r#"./. + "path\${var}.nix""#);
+        assert_eq!(result, r#"./. + "/path\${var}.nix""#);
 
         // Path with backslash - must be escaped
         let path = NixPathBuf::new(r"path\with\backslash.nix");
         let result = to_string(&path).unwrap();
-        assert_eq!(result, r#"./. + "path\\with\\backslash.nix""#);
+        assert_eq!(result, r#"./. + "/path\\with\\backslash.nix""#);
 
         // Path with percent-encoded characters (e.g. %2F)
         let path = NixPathBuf::new("/tmp/test%2Fdir/devenv.nix");
@@ -537,4 +537,3239 @@ mod test {
 
         assert_eq!(newtype_var_test, expected);
     }
+
+    /// The files of `tests/golden/nixfmt` are the expected output of
+    /// `Config::nixfmt`. CI checks that the real nixfmt leaves them
+    /// untouched; locally `golden_files_are_formatted` does the same with
+    /// `cargo test -- --ignored`, and fails if `nixfmt` is not installed.
+    mod nixfmt {
+        use crate::{Config, NixPathBuf, to_string_with_config};
+        use indexmap::IndexMap;
+        use serde::Serialize;
+        use std::process::Command;
+
+        #[derive(Serialize)]
+        struct Host {
+            name: String,
+            enable: bool,
+            port: u16,
+            address: (u8, u8, u8, u8),
+            tags: Vec<String>,
+            owners: Vec<String>,
+            aliases: Vec<String>,
+            extra: IndexMap<String, String>,
+            motd: String,
+            config: NixPathBuf,
+            parent: Option<String>,
+        }
+
+        #[derive(Serialize)]
+        enum Backend {
+            Local,
+            Remote(String),
+            Mirror(String, u16),
+            Bucket { name: String, region: String },
+        }
+
+        fn host() -> Host {
+            Host {
+                name: "alpha".into(),
+                enable: true,
+                port: 8080,
+                address: (10, 0, 0, 1),
+                tags: vec!["web".into(), "db".into()],
+                owners: vec!["ops".into()],
+                aliases: Vec::new(),
+                extra: IndexMap::new(),
+                motd: concat!(
+                    "Welcome to alpha, the primary web and database host of the cluster.\n",
+                    "Please report problems to the operations team."
+                )
+                .into(),
+                config: NixPathBuf::new("./hosts/alpha.nix"),
+                parent: None,
+            }
+        }
+
+        #[test]
+        fn attrs() {
+            let result = to_string_with_config(&host(), &Config::nixfmt()).unwrap();
+            assert_eq!(result, include_str!("../tests/golden/nixfmt/attrs.nix"));
+        }
+
+        #[test]
+        fn lists() {
+            let hosts = vec![host(), host()];
+            let result = to_string_with_config(&hosts, &Config::nixfmt()).unwrap();
+            assert_eq!(result, include_str!("../tests/golden/nixfmt/lists.nix"));
+        }
+
+        #[test]
+        fn variants() {
+            let backends: IndexMap<&str, Backend> = [
+                ("local", Backend::Local),
+                ("remote", Backend::Remote("ssh://backup".into())),
+                ("mirror", Backend::Mirror("mirror.example.org".into(), 873)),
+                (
+                    "bucket",
+                    Backend::Bucket {
+                        name: "archive".into(),
+                        region: "eu-west-1".into(),
+                    },
+                ),
+            ]
+            .into_iter()
+            .collect();
+
+            let result = to_string_with_config(&backends, &Config::nixfmt()).unwrap();
+            assert_eq!(result, include_str!("../tests/golden/nixfmt/variants.nix"));
+        }
+
+        #[test]
+        fn empty() {
+            let empty: IndexMap<String, Vec<u8>> = IndexMap::new();
+            let result = to_string_with_config(&empty, &Config::nixfmt()).unwrap();
+            assert_eq!(result, include_str!("../tests/golden/nixfmt/empty.nix"));
+        }
+
+        #[test]
+        #[ignore = "requires nixfmt, checked by the golden job in CI"]
+        fn golden_files_are_formatted() {
+            let dir = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/nixfmt");
+            let output = Command::new("nixfmt")
+                .arg("--check")
+                .arg(dir)
+                .output()
+                .expect("nixfmt is not installed");
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+
+    mod to_pretty {
+        use crate::{Config, to_string_with_config};
+        use indexmap::IndexMap;
+        use serde::Serialize;
+
+        #[test]
+        fn keys_are_escaped_on_one_line() {
+            let long = "a key long enough to be written as an indented string\nif it were a value";
+            let attrs: IndexMap<&str, u8> = [(long, 1), ("${x}", 2), ("tab\there", 3)]
+                .into_iter()
+                .collect();
+
+            let expected = concat!(
+                "{\n",
+                "  \"\\${x}\" = 2;\n",
+                "  \"a key long enough to be written as an indented string\\nif it were a value\" = 1;\n",
+                "  \"tab\\there\" = 3;\n",
+                "}"
+            );
+            assert_eq!(
+                to_string_with_config(&attrs, &Config::to_pretty()).unwrap(),
+                expected
+            );
+        }
+
+        #[test]
+        fn keys_are_escaped_like_escape_nix_identifier() {
+            let attrs: IndexMap<&str, u8> = [("q\"\\\r", 4), ("a$b", 3), ("\u{1}\u{8}\u{c}", 2)]
+                .into_iter()
+                .collect();
+
+            let expected = concat!(
+                "{\n",
+                "  \"\\u0001\\b\\f\" = 2;\n",
+                "  \"a\\$b\" = 3;\n",
+                "  \"q\\\"\\\\\\r\" = 4;\n",
+                "}"
+            );
+            assert_eq!(
+                to_string_with_config(&attrs, &Config::to_pretty()).unwrap(),
+                expected
+            );
+        }
+
+        #[test]
+        fn sorted_attrs() {
+            #[derive(Serialize)]
+            struct Service {
+                name: String,
+                enable: bool,
+                ports: Vec<u16>,
+                env: IndexMap<String, String>,
+                groups: Vec<String>,
+                user: Option<String>,
+            }
+
+            let service = Service {
+                name: "web".into(),
+                enable: true,
+                ports: vec![80, 443],
+                env: [("PORT", "80"), ("HOME", "/var/lib/web")]
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+                groups: Vec::new(),
+                user: None,
+            };
+
+            let result = to_string_with_config(&service, &Config::to_pretty()).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  enable = true;\n",
+                "  env = {\n",
+                "    HOME = \"/var/lib/web\";\n",
+                "    PORT = \"80\";\n",
+                "  };\n",
+                "  groups = [ ];\n",
+                "  name = \"web\";\n",
+                "  ports = [\n",
+                "    80\n",
+                "    443\n",
+                "  ];\n",
+                "  user = null;\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn strings() {
+            let strings: IndexMap<&str, &str> = [
+                ("short", "say \"${hi}\" \\ bye"),
+                ("script", "echo hi\n\necho ${HOME} ''\n"),
+                ("open", "first\nlast"),
+            ]
+            .into_iter()
+            .collect();
+
+            let result = to_string_with_config(&strings, &Config::to_pretty()).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  open = ''\n",
+                "    first\n",
+                "    last'';\n",
+                "  script = ''\n",
+                "    echo hi\n",
+                "    \n",
+                "    echo ''${HOME} '''\n",
+                "  '';\n",
+                "  short = \"say \\\"\\${hi}\\\" \\\\ bye\";\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn floats() {
+            let floats = [0.5, 1.0, -2.25, 1e-6, 1e20, 0.0001, 123456789012345.6];
+
+            let result = to_string_with_config(&floats, &Config::to_pretty()).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "[\n",
+                "  0.5\n",
+                "  1.0\n",
+                "  (-2.25)\n",
+                "  1e-06\n",
+                "  1e+20\n",
+                "  0.0001\n",
+                "  123456789012345.6\n",
+                "]",
+            );
+
+            assert_eq!(result, expected);
+        }
+    }
+
+    mod formatter {
+        use crate::{Config, Context, Formatter, to_string, to_string_with_config};
+        use indexmap::IndexMap;
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        enum Backend {
+            Mirror(String, u16),
+            Bucket { name: String, region: String },
+        }
+
+        #[test]
+        fn default_variants_are_unchanged() {
+            let backends = vec![
+                Backend::Mirror("mirror.example.org".into(), 873),
+                Backend::Bucket {
+                    name: "archive".into(),
+                    region: "eu-west-1".into(),
+                },
+            ];
+
+            let result = to_string(&backends).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "[\n",
+                "  { \"Mirror\" = [\"mirror.example.org\" 873 ] }\n",
+                "  { \"Bucket\" = { \"name\" = { \"archive\" \"region\" = { \"eu-west-1\"}; }\n",
+                "]",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn expanded_variants() {
+            struct Expanded;
+
+            impl Formatter for Expanded {
+                fn expand_variants(&self) -> bool {
+                    true
+                }
+            }
+
+            let backends = vec![
+                Backend::Mirror("mirror.example.org".into(), 873),
+                Backend::Bucket {
+                    name: "archive".into(),
+                    region: "eu-west-1".into(),
+                },
+            ];
+
+            let config = Config::new().formatter(Expanded);
+            let result = to_string_with_config(&backends, &config).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "[\n",
+                "  { mirror = [\"mirror.example.org\" 873 ]; }\n",
+                "  { bucket = { name = \"archive\"; region = \"eu-west-1\"; }; }\n",
+                "]",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn tuples_stay_on_one_line() {
+            let pairs = vec![(1, vec![2, 3])];
+
+            let result = to_string(&pairs).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "[\n",
+                "  [1 [\n",
+                "      2\n",
+                "      3\n",
+                "    ] ]\n",
+                "]",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn blank_lines_between_top_level_attrs() {
+            struct Spaced;
+
+            impl Formatter for Spaced {
+                fn between_attrs(&self, out: &mut String, ctx: &Context) {
+                    if ctx.depth == 0 {
+                        out.push('\n');
+                    }
+                }
+            }
+
+            let mut inner = IndexMap::new();
+            inner.insert("a", 1);
+            inner.insert("b", 2);
+
+            let mut outer = IndexMap::new();
+            outer.insert("first", inner.clone());
+            outer.insert("second", inner);
+
+            let config = Config::new().formatter(Spaced);
+            let result = to_string_with_config(&outer, &config).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  first = {\n",
+                "    a = 1;\n",
+                "    b = 2;\n",
+                "  };\n",
+                "\n",
+                "  second = {\n",
+                "    a = 1;\n",
+                "    b = 2;\n",
+                "  };\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+    }
+
+    mod none_policy {
+        use crate::{Config, NonePolicy, to_string_with_config};
+        use indexmap::IndexMap;
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        struct Marker;
+
+        #[derive(Serialize)]
+        struct Service {
+            enable: bool,
+            user: Option<String>,
+            groups: Vec<String>,
+            env: IndexMap<String, Option<String>>,
+            marker: Marker,
+            ports: Vec<Option<u16>>,
+        }
+
+        fn service() -> Service {
+            Service {
+                enable: true,
+                user: None,
+                groups: Vec::new(),
+                env: [("HOME".to_string(), None)].into_iter().collect(),
+                marker: Marker,
+                ports: vec![Some(80), None],
+            }
+        }
+
+        #[test]
+        fn omit_none() {
+            let config = Config::new().none_policy(NonePolicy::Omit);
+            let result = to_string_with_config(&service(), &config).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  enable = true;\n",
+                "  groups = [\n",
+                "  ];\n",
+                "  env = {\n",
+                "  };\n",
+                "  marker = null;\n",
+                "  ports = [\n",
+                "    80\n",
+                "    null\n",
+                "  ];\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn omit_none_and_empty() {
+            let config = Config::nixfmt()
+                .none_policy(NonePolicy::Omit)
+                .omit_empty_collections(true)
+                .unit_structs_as_attrsets(true);
+            let result = to_string_with_config(&service(), &config).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  enable = true;\n",
+                "  ports = [\n",
+                "    80\n",
+                "    null\n",
+                "  ];\n",
+                "}\n",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn unit_structs_as_attrsets() {
+            let config = Config::nixfmt().unit_structs_as_attrsets(true);
+            let result = to_string_with_config(&service(), &config).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  enable = true;\n",
+                "  user = null;\n",
+                "  groups = [ ];\n",
+                "  env = {\n",
+                "    HOME = null;\n",
+                "  };\n",
+                "  marker = { };\n",
+                "  ports = [\n",
+                "    80\n",
+                "    null\n",
+                "  ];\n",
+                "}\n",
+            );
+
+            assert_eq!(result, expected);
+        }
+    }
+
+    mod placeholders {
+        use crate::{Config, NonePolicy, to_string_with_config};
+        use serde::Serialize;
+        use std::collections::HashMap;
+        use std::path::PathBuf;
+
+        #[derive(Serialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Networking {
+            host_name: Option<String>,
+            #[serde(serialize_with = "crate::as_hinted_option")]
+            domain: Option<String>,
+            #[serde(serialize_with = "crate::as_hinted_option")]
+            name_servers: Option<Vec<String>>,
+            #[serde(serialize_with = "crate::as_hinted_option")]
+            hosts: Option<HashMap<String, Vec<String>>>,
+            #[serde(serialize_with = "crate::as_hinted_option")]
+            key_file: Option<Box<PathBuf>>,
+            #[serde(serialize_with = "crate::as_hinted_option")]
+            mtu: Option<u16>,
+            enable_ipv6: bool,
+        }
+
+        fn networking() -> Networking {
+            Networking {
+                host_name: None,
+                domain: None,
+                name_servers: None,
+                hosts: None,
+                key_file: None,
+                mtu: Some(1500),
+                enable_ipv6: true,
+            }
+        }
+
+        #[test]
+        fn commented_out_fields() {
+            let config = Config::new().none_policy(NonePolicy::Placeholder);
+            let result = to_string_with_config(&networking(), &config).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  # hostName = null;\n",
+                "  # domain = null; # type: string\n",
+                "  # nameServers = null; # type: list of string\n",
+                "  # hosts = null; # type: attribute set of list of string\n",
+                "  # keyFile = null; # type: path\n",
+                "  mtu = 1500;\n",
+                "  enableIpv6 = true;\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn hints_without_placeholders() {
+            let result = crate::to_string(&networking()).unwrap();
+
+            assert!(result.contains("  domain = null;\n"));
+        }
+
+        #[test]
+        fn placeholders_expand_variants() {
+            #[derive(Serialize)]
+            enum Backend {
+                Docker { socket: Option<String> },
+            }
+
+            let config = Config::nixfmt().none_policy(NonePolicy::Placeholder);
+            let backend = Backend::Docker { socket: None };
+            let result = to_string_with_config(&backend, &config).unwrap();
+
+            assert_eq!(result, "{\n  docker = {\n    # socket = null;\n  };\n}\n");
+        }
+
+        #[test]
+        fn none_in_lists_stays_null() {
+            let config = Config::new().none_policy(NonePolicy::Placeholder);
+            let result = to_string_with_config(&vec![Some(1), None], &config).unwrap();
+
+            assert_eq!(result, "[\n  1\n  null\n]");
+        }
+    }
+
+    mod comments {
+        use crate::{Config, NixCommented, NonePolicy, to_string, to_string_with_config};
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        struct Service {
+            enable: NixCommented<bool>,
+            ports: NixCommented<Vec<u16>>,
+            user: NixCommented<Option<String>>,
+        }
+
+        fn service() -> Service {
+            Service {
+                enable: NixCommented::new("Started at boot\n\nSee the manual", true),
+                ports: NixCommented::block("open in the firewall", vec![80]),
+                user: NixCommented::new("defaults to root", None),
+            }
+        }
+
+        #[test]
+        fn line_and_block_comments() {
+            let result = to_string(&service()).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  # Started at boot\n",
+                "  #\n",
+                "  # See the manual\n",
+                "  enable = true;\n",
+                "  /* open in the firewall */\n",
+                "  ports = [\n",
+                "    80\n",
+                "  ];\n",
+                "  # defaults to root\n",
+                "  user = null;\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn comments_expand_variants() {
+            #[derive(Serialize)]
+            enum Mirror {
+                Rsync(NixCommented<u16>),
+            }
+
+            let mirror = Mirror::Rsync(NixCommented::new("default port", 873));
+            let result = to_string_with_config(&mirror, &Config::nixfmt()).unwrap();
+
+            assert_eq!(result, "{\n  # default port\n  rsync = 873;\n}\n");
+        }
+
+        #[test]
+        fn comments_on_placeholders() {
+            let config = Config::nixfmt().none_policy(NonePolicy::Placeholder);
+            let result = to_string_with_config(&service(), &config).unwrap();
+
+            assert!(result.ends_with("  # defaults to root\n  # user = null;\n}\n"));
+        }
+
+        #[test]
+        fn comments_outside_attrsets_are_rejected() {
+            let element = to_string(&vec![NixCommented::new("first", 1)]).unwrap_err();
+            let top_level = to_string(&NixCommented::new("top", 1)).unwrap_err();
+
+            for error in [element, top_level] {
+                assert_eq!(
+                    error.to_string(),
+                    "comments are only supported on attributes"
+                );
+            }
+        }
+
+        #[test]
+        fn block_comment_cannot_end_early() {
+            let value = NixCommented::block("a */ b", 1);
+
+            assert!(
+                to_string(
+                    &[("key", value)]
+                        .into_iter()
+                        .collect::<std::collections::BTreeMap<_, _>>()
+                )
+                .is_err()
+            );
+        }
+
+        #[test]
+        fn header_above_nixfmt_output() {
+            let config = Config::nixfmt().header("Generated by nixgen.\nDo not edit.");
+            let result = to_string_with_config(&Vec::<u8>::new(), &config).unwrap();
+
+            assert_eq!(result, "# Generated by nixgen.\n# Do not edit.\n[ ]\n");
+        }
+    }
+
+    mod derive {
+        use crate::{Config, to_string, to_string_with_config};
+        use ser_nix_derive::NixSerialize;
+        use std::collections::BTreeMap;
+        use std::path::PathBuf;
+
+        #[derive(NixSerialize)]
+        #[nix(rename_all = "camelCase")]
+        struct Machine {
+            #[nix(path)]
+            imports: Vec<PathBuf>,
+            #[nix(path)]
+            key_file: Option<PathBuf>,
+            #[nix(literal)]
+            packages: BTreeMap<String, Vec<String>>,
+            #[nix(indented)]
+            script: String,
+            #[nix(attr_path)]
+            boot: Boot,
+            #[nix(rename = "stateVersion", comment = "Do not change")]
+            version: &'static str,
+            #[nix(skip)]
+            #[allow(dead_code)]
+            internal: u8,
+            #[nix(skip_serializing_if = "Option::is_none")]
+            user: Option<String>,
+        }
+
+        #[derive(NixSerialize)]
+        #[nix(rename_all = "camelCase")]
+        struct Boot {
+            #[nix(attr_path)]
+            loader: Loader,
+            kernel_modules: Vec<&'static str>,
+        }
+
+        #[derive(NixSerialize)]
+        struct Loader {
+            timeout: u8,
+        }
+
+        fn machine() -> Machine {
+            Machine {
+                imports: vec![PathBuf::from("hardware.nix")],
+                key_file: Some(PathBuf::from("/etc/my key")),
+                packages: [("alice".to_string(), vec!["pkgs.git".to_string()])]
+                    .into_iter()
+                    .collect(),
+                script: "echo ''hi''\n".to_string(),
+                boot: Boot {
+                    loader: Loader { timeout: 5 },
+                    kernel_modules: vec!["kvm"],
+                },
+                version: "24.11",
+                internal: 0,
+                user: None,
+            }
+        }
+
+        #[test]
+        fn field_attributes() {
+            let result = to_string(&machine()).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  imports = [\n",
+                "    ./hardware.nix\n",
+                "  ];\n",
+                "  keyFile = /. + \"/etc/my key\";\n",
+                "  packages = {\n",
+                "    alice = [\n",
+                "      pkgs.git\n",
+                "    ];\n",
+                "  };\n",
+                "  script = ''\n",
+                "    echo '''hi'''\n",
+                "  '';\n",
+                "  boot.loader.timeout = 5;\n",
+                "  boot.kernelModules = [\n",
+                "    \"kvm\"\n",
+                "  ];\n",
+                "  # Do not change\n",
+                "  stateVersion = \"24.11\";\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn attr_path_sorted_with_siblings() {
+            let result = to_string_with_config(&machine(), &Config::to_pretty()).unwrap();
+
+            let modules = result.find("boot.kernelModules = [").unwrap();
+            let timeout = result.find("boot.loader.timeout = 5;").unwrap();
+            let imports = result.find("imports = [").unwrap();
+            assert!(modules < timeout && timeout < imports);
+        }
+
+        #[test]
+        fn attr_path_outside_attrset() {
+            #[derive(NixSerialize)]
+            struct Wrapper<T> {
+                #[nix(attr_path)]
+                inner: T,
+            }
+
+            let list = vec![Wrapper {
+                inner: Loader { timeout: 1 },
+            }];
+            let result = to_string(&list).unwrap();
+
+            assert_eq!(result, "[\n  {\n    inner.timeout = 1;\n  }\n]");
+
+            let nested = Wrapper { inner: vec![1] };
+            assert_eq!(
+                to_string(&nested).unwrap(),
+                "{\n  inner = [\n    1\n  ];\n}"
+            );
+        }
+    }
+
+    mod options {
+        use crate::{Config, NixOption, NixOptions, NixType, to_string, to_string_with_config};
+        use serde::Serialize;
+        use std::collections::HashMap;
+
+        #[derive(Default, Serialize, ser_nix_derive::NixOptions)]
+        #[nix(default, rename_all = "camelCase")]
+        struct Server {
+            /// Address to bind to.
+            ///
+            /// Use `0.0.0.0` to listen on every interface.
+            listen_address: String,
+            #[nix(type = "lib.types.port")]
+            port: u16,
+            upstreams: Vec<Upstream>,
+            headers: HashMap<String, Vec<String>>,
+            tls: Option<Tls>,
+            #[nix(skip)]
+            #[allow(dead_code)]
+            internal: bool,
+        }
+
+        #[derive(Default, Serialize, ser_nix_derive::NixOptions)]
+        struct Upstream {
+            #[nix(default = "\"localhost\"", description = "Host name")]
+            host: String,
+            weight: Option<u8>,
+        }
+
+        #[derive(Serialize, ser_nix_derive::NixOptions)]
+        struct Tls {
+            #[nix(default = "vec![\"a.pem\", \"b.pem\"]")]
+            certificates: Vec<String>,
+            mode: TlsMode,
+        }
+
+        #[derive(Serialize, ser_nix_derive::NixOptions)]
+        #[nix(rename_all = "kebab-case")]
+        #[allow(dead_code)]
+        enum TlsMode {
+            Strict,
+            #[nix(rename = "off")]
+            Disabled,
+            AllowInsecure,
+        }
+
+        #[test]
+        fn derived_options() {
+            let result = to_string(&Server::nix_options()).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  listenAddress = lib.mkOption {\n",
+                "    type = lib.types.str;\n",
+                "    default = \"\";\n",
+                "    description = \"Address to bind to.\\n\\nUse `0.0.0.0` to listen on every interface.\";\n",
+                "  };\n",
+                "  port = lib.mkOption {\n",
+                "    type = lib.types.port;\n",
+                "    default = 0;\n",
+                "  };\n",
+                "  upstreams = lib.mkOption {\n",
+                "    type = lib.types.listOf (lib.types.submodule {\n",
+                "      options = {\n",
+                "        host = lib.mkOption {\n",
+                "          type = lib.types.str;\n",
+                "          default = \"localhost\";\n",
+                "          description = \"Host name\";\n",
+                "        };\n",
+                "        weight = lib.mkOption {\n",
+                "          type = lib.types.nullOr lib.types.ints.u8;\n",
+                "        };\n",
+                "      };\n",
+                "    });\n",
+                "    default = [\n",
+                "    ];\n",
+                "  };\n",
+                "  headers = lib.mkOption {\n",
+                "    type = lib.types.attrsOf (lib.types.listOf lib.types.str);\n",
+                "    default = {\n",
+                "    };\n",
+                "  };\n",
+                "  tls = lib.mkOption {\n",
+                "    type = lib.types.nullOr (lib.types.submodule {\n",
+                "      options = {\n",
+                "        certificates = lib.mkOption {\n",
+                "          type = lib.types.listOf lib.types.str;\n",
+                "          default = [\n",
+                "            \"a.pem\"\n",
+                "            \"b.pem\"\n",
+                "          ];\n",
+                "        };\n",
+                "        mode = lib.mkOption {\n",
+                "          type = lib.types.enum [\n",
+                "            \"strict\"\n",
+                "            \"off\"\n",
+                "            \"allow-insecure\"\n",
+                "          ];\n",
+                "        };\n",
+                "      };\n",
+                "    });\n",
+                "    default = null;\n",
+                "  };\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn options_follow_formatter() {
+            let result =
+                to_string_with_config(&Upstream::nix_options(), &Config::nixfmt()).unwrap();
+
+            assert!(result.contains("    type = lib.types.nullOr lib.types.ints.u8;\n"));
+            assert!(result.ends_with("  };\n}\n"));
+        }
+
+        #[test]
+        fn values_follow_formatter() {
+            let option = NixOption::new(HashMap::<String, u8>::nix_type())
+                .default(&HashMap::<String, u8>::new())
+                .example(&vec![HashMap::from([("a b", 1)])]);
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "lib.mkOption {\n",
+                "  type = lib.types.attrsOf lib.types.ints.u8;\n",
+                "  default = { };\n",
+                "  example = lib.literalExpression \"[\\n  {\\n    \\\"a b\\\" = 1;\\n  }\\n]\";\n",
+                "}\n",
+            );
+
+            assert_eq!(
+                to_string_with_config(&option, &Config::nixfmt()).unwrap(),
+                expected
+            );
+        }
+
+        #[test]
+        fn type_of_containers() {
+            let ty = to_string(&Option::<Vec<HashMap<String, bool>>>::nix_type()).unwrap();
+
+            assert_eq!(
+                ty,
+                "lib.types.nullOr (lib.types.listOf (lib.types.attrsOf lib.types.bool))"
+            );
+        }
+    }
+
+    #[cfg(feature = "json-schema")]
+    mod json_schema {
+        use crate::{Config, options_from_json_schema, to_string, to_string_with_config};
+        use serde_json::json;
+
+        #[test]
+        fn schema_to_options() {
+            let schema = json!({
+                "$schema": "http://json-schema.org/draft-07/schema#",
+                "title": "Settings",
+                "type": "object",
+                "properties": {
+                    "name": {
+                        "type": "string",
+                        "default": "demo",
+                        "examples": ["my-service"]
+                    },
+                    "retries": {
+                        "type": ["integer", "null"],
+                        "format": "uint8",
+                        "title": "Number of retries"
+                    },
+                    "level": { "$ref": "#/definitions/Level" },
+                    "limits": {
+                        "type": "object",
+                        "additionalProperties": { "type": "number", "format": "double" }
+                    },
+                    "target": {
+                        "oneOf": [
+                            { "type": "string" },
+                            { "$ref": "#/definitions/Target" }
+                        ]
+                    }
+                },
+                "definitions": {
+                    "Level": {
+                        "oneOf": [
+                            { "type": "string", "enum": ["debug", "info"] },
+                            { "type": "string", "const": "warn" },
+                            { "type": "null" }
+                        ],
+                        "description": "Log level"
+                    },
+                    "Target": {
+                        "type": "object",
+                        "properties": {
+                            "host": { "type": "string", "nullable": true }
+                        }
+                    }
+                }
+            });
+
+            let result = to_string(&options_from_json_schema(&schema).unwrap()).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  level = lib.mkOption {\n",
+                "    type = lib.types.nullOr (lib.types.enum [\n",
+                "      \"debug\"\n",
+                "      \"info\"\n",
+                "      \"warn\"\n",
+                "    ]);\n",
+                "    description = \"Log level\";\n",
+                "  };\n",
+                "  limits = lib.mkOption {\n",
+                "    type = lib.types.attrsOf lib.types.float;\n",
+                "  };\n",
+                "  name = lib.mkOption {\n",
+                "    type = lib.types.str;\n",
+                "    default = \"demo\";\n",
+                "    example = lib.literalExpression \"\\\"my-service\\\"\";\n",
+                "  };\n",
+                "  retries = lib.mkOption {\n",
+                "    type = lib.types.nullOr lib.types.ints.u8;\n",
+                "    description = \"Number of retries\";\n",
+                "  };\n",
+                "  target = lib.mkOption {\n",
+                "    type = lib.types.oneOf [\n",
+                "      lib.types.str\n",
+                "      (lib.types.submodule {\n",
+                "        options = {\n",
+                "          host = lib.mkOption {\n",
+                "            type = lib.types.nullOr lib.types.str;\n",
+                "          };\n",
+                "        };\n",
+                "      })\n",
+                "    ];\n",
+                "  };\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn non_string_enum() {
+            let schema = json!({
+                "type": "object",
+                "properties": {
+                    "port": { "enum": [80, -1, { "a b": [true] }, null] }
+                }
+            });
+            let config = Config::new().lib("pkgs.lib");
+
+            let options = options_from_json_schema(&schema).unwrap();
+            let result = to_string_with_config(&options, &config).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  port = pkgs.lib.mkOption {\n",
+                "    type = pkgs.lib.types.nullOr (pkgs.lib.types.enum [\n",
+                "      80\n",
+                "      (-1)\n",
+                "      {\n",
+                "        \"a b\" = [\n",
+                "          true\n",
+                "        ];\n",
+                "      }\n",
+                "    ]);\n",
+                "  };\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn recursive_ref() {
+            let schema = json!({
+                "type": "object",
+                "properties": {
+                    "tree": { "$ref": "#/definitions/Tree" }
+                },
+                "definitions": {
+                    "Tree": {
+                        "type": "object",
+                        "properties": {
+                            "children": {
+                                "type": "array",
+                                "items": { "$ref": "#/definitions/Tree" }
+                            }
+                        }
+                    }
+                }
+            });
+
+            let error = options_from_json_schema(&schema).unwrap_err();
+
+            assert!(error.to_string().contains("#/definitions/Tree"));
+        }
+
+        #[test]
+        fn unresolved_ref() {
+            let schema = json!({
+                "type": "object",
+                "properties": { "a": { "$ref": "#/definitions/Missing" } }
+            });
+
+            assert!(options_from_json_schema(&schema).is_err());
+        }
+    }
+
+    mod priorities {
+        use crate::{
+            Config, MkAfter, MkBefore, MkDefault, MkForce, MkOrder, MkOverride, NixLiteral,
+            NixOptions, to_string, to_string_with_config,
+        };
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        struct Overlay {
+            host_name: MkDefault<&'static str>,
+            packages: MkBefore<Vec<NixLiteral<'static>>>,
+            extra: MkAfter<Vec<u8>>,
+            user: MkOverride<Option<&'static str>>,
+            offset: MkForce<i32>,
+            order: MkOrder<Vec<u8>>,
+        }
+
+        #[test]
+        fn priority_wrappers() {
+            let overlay = Overlay {
+                host_name: MkDefault("alpha"),
+                packages: MkBefore(vec![NixLiteral::new("pkgs.git")]),
+                extra: MkAfter(vec![]),
+                user: MkOverride::new(900, None),
+                offset: MkForce(-1),
+                order: MkOrder::new(10, vec![1, 2]),
+            };
+
+            let result = to_string(&overlay).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  host_name = lib.mkDefault \"alpha\";\n",
+                "  packages = lib.mkBefore [\n",
+                "    pkgs.git\n",
+                "  ];\n",
+                "  extra = lib.mkAfter [\n",
+                "  ];\n",
+                "  user = lib.mkOverride 900 null;\n",
+                "  offset = lib.mkForce (-1);\n",
+                "  order = lib.mkOrder 10 [\n",
+                "    1\n",
+                "    2\n",
+                "  ];\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn parenthesized_values() {
+            let nested = MkForce(MkDefault(NixLiteral::new("pkgs.hello")));
+            let literal = MkForce(NixLiteral::new("a + b"));
+
+            assert_eq!(
+                to_string(&nested).unwrap(),
+                "lib.mkForce (lib.mkDefault pkgs.hello)"
+            );
+            assert_eq!(to_string(&literal).unwrap(), "lib.mkForce (a + b)");
+        }
+
+        #[derive(Serialize, ser_nix_derive::NixOptions)]
+        struct Service {
+            #[nix(default = "8080")]
+            port: u16,
+            user: MkDefault<String>,
+        }
+
+        #[test]
+        fn custom_lib_identifier() {
+            let config = Config::new().lib("l");
+            let result = to_string_with_config(&Service::nix_options(), &config).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  port = l.mkOption {\n",
+                "    type = l.types.ints.u16;\n",
+                "    default = 8080;\n",
+                "  };\n",
+                "  user = l.mkOption {\n",
+                "    type = l.types.str;\n",
+                "  };\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+    }
+
+    mod conditionals {
+        use crate::{Config, MkForce, MkIf, MkMerge, NixLiteral, to_string, to_string_with_config};
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        struct Service {
+            enable: bool,
+            port: u16,
+        }
+
+        #[derive(Serialize)]
+        struct Services {
+            web: MkMerge<MkIf<Service>>,
+            cache: MkIf<MkForce<bool>>,
+        }
+
+        fn services() -> Services {
+            Services {
+                web: MkMerge(vec![MkIf::new(
+                    "cfg.web.enable",
+                    Service {
+                        enable: true,
+                        port: 80,
+                    },
+                )]),
+                cache: MkIf::new(NixLiteral::from("!cfg.minimal"), MkForce(false)),
+            }
+        }
+
+        #[test]
+        fn nested_conditions() {
+            let result = to_string(&services()).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  web = lib.mkMerge [\n",
+                "    (lib.mkIf cfg.web.enable {\n",
+                "      enable = true;\n",
+                "      port = 80;\n",
+                "    })\n",
+                "  ];\n",
+                "  cache = lib.mkIf (!cfg.minimal) (lib.mkForce false);\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn conditions_follow_formatter() {
+            let result = to_string_with_config(&services(), &Config::nixfmt()).unwrap();
+
+            assert!(result.contains("    (lib.mkIf cfg.web.enable {\n      enable = true;\n"));
+            assert!(result.contains("    })\n  ];\n"));
+        }
+    }
+
+    mod modules {
+        use crate::{
+            Config, MkDefault, NixLiteral, NixModule, NixPattern, to_string, to_string_with_config,
+        };
+        use ser_nix_derive::NixSerialize;
+        use std::collections::BTreeMap;
+
+        #[derive(NixSerialize)]
+        struct Users {
+            #[nix(attr_path)]
+            users: UserSettings,
+            groups: Vec<&'static str>,
+        }
+
+        #[derive(NixSerialize)]
+        #[nix(rename_all = "camelCase")]
+        struct UserSettings {
+            mutable_users: MkDefault<bool>,
+        }
+
+        fn module() -> NixModule<Users> {
+            NixModule::new(Users {
+                users: UserSettings {
+                    mutable_users: MkDefault(false),
+                },
+                groups: vec!["wheel"],
+            })
+            .import("./users.nix")
+            .import("/etc/nixos/extra.nix")
+        }
+
+        #[test]
+        fn module_with_imports() {
+            let result = to_string_with_config(&module(), &Config::nixfmt()).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  config,\n",
+                "  lib,\n",
+                "  pkgs,\n",
+                "  ...\n",
+                "}:\n",
+                "{\n",
+                "  imports = [\n",
+                "    ./users.nix\n",
+                "    /etc/nixos/extra.nix\n",
+                "  ];\n",
+                "  users.mutableUsers = lib.mkDefault false;\n",
+                "  groups = [ \"wheel\" ];\n",
+                "}\n",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn module_arguments() {
+            let mut body = BTreeMap::new();
+            body.insert("packages", vec![NixLiteral::new("pkgs.git")]);
+
+            let args = NixPattern::new()
+                .arg_with_default("pkgs", &NixLiteral::new("import <nixpkgs> { }"))
+                .arg_with_default("extra", &vec!["a", "b"]);
+            let module = NixModule::new(body).args(args);
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{ pkgs ? import <nixpkgs> { }, extra ? [\n",
+                "    \"a\"\n",
+                "    \"b\"\n",
+                "  ] }:\n",
+                "{\n",
+                "  packages = [\n",
+                "    pkgs.git\n",
+                "  ];\n",
+                "}",
+            );
+
+            assert_eq!(to_string(&module).unwrap(), expected);
+        }
+
+        #[test]
+        fn empty_module() {
+            let module = NixModule::new(BTreeMap::<String, u8>::new()).args(NixPattern::new());
+
+            assert_eq!(to_string(&module).unwrap(), "{ }:\n{\n}");
+        }
+
+        #[test]
+        fn body_must_be_attrset() {
+            let list = NixModule::new(vec![1, 2]);
+            let literal = NixModule::new(NixLiteral::new("{ }"));
+
+            assert!(to_string(&list).is_err());
+            assert!(to_string(&literal).is_err());
+        }
+
+        #[test]
+        fn imports_are_written_once() {
+            let body = BTreeMap::from([("imports", vec![NixLiteral::new("./a.nix")])]);
+            let module = NixModule::new(body.clone()).args(NixPattern::new());
+
+            assert_eq!(
+                to_string(&module).unwrap(),
+                "{ }:\n{\n  imports = [\n    ./a.nix\n  ];\n}"
+            );
+            assert!(to_string(&NixModule::new(body).import("./b.nix")).is_err());
+        }
+    }
+
+    mod functions {
+        use crate::{
+            Config, NixApply, NixLambda, NixLiteral, NixPathBuf, NixPattern, to_string,
+            to_string_with_config,
+        };
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        #[derive(Serialize)]
+        struct Overlay {
+            overlays: Vec<NixLambda<NixLambda<BTreeMap<&'static str, NixLiteral<'static>>>>>,
+            hello: NixApply<NixApply<NixLiteral<'static>, NixPathBuf>, BTreeMap<&'static str, u8>>,
+        }
+
+        #[test]
+        fn lambdas_and_applications() {
+            let mut attrs = BTreeMap::new();
+            attrs.insert("hello", NixLiteral::new("prev.hello"));
+
+            let overlay = Overlay {
+                overlays: vec![NixLambda::new("final", NixLambda::new("prev", attrs))],
+                hello: NixApply::new(
+                    NixLiteral::new("pkgs.callPackage"),
+                    NixPathBuf::new("./pkg.nix"),
+                )
+                .arg(BTreeMap::from([("jobs", 4)])),
+            };
+
+            let result = to_string(&overlay).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  overlays = [\n",
+                "    (final: prev: {\n",
+                "      hello = prev.hello;\n",
+                "    })\n",
+                "  ];\n",
+                "  hello = pkgs.callPackage ./pkg.nix {\n",
+                "    jobs = 4;\n",
+                "  };\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn pattern_lambda() {
+            let pattern = NixPattern::new()
+                .arg("stdenv")
+                .arg("fetchurl")
+                .ellipsis(true);
+            let lambda =
+                NixLambda::with_pattern(pattern, NixLiteral::new("stdenv.mkDerivation { }"));
+
+            assert_eq!(
+                to_string(&lambda).unwrap(),
+                "{ stdenv, fetchurl, ... }:\nstdenv.mkDerivation { }"
+            );
+            assert_eq!(
+                to_string_with_config(&lambda, &Config::nixfmt()).unwrap(),
+                "{\n  stdenv,\n  fetchurl,\n  ...\n}:\nstdenv.mkDerivation { }\n"
+            );
+        }
+
+        #[test]
+        fn defaults_follow_formatter() {
+            let pattern = NixPattern::new()
+                .arg_with_default("ports", &Vec::<u16>::new())
+                .arg_with_default("name", &"${x}");
+            let lambda = NixLambda::with_pattern(pattern, NixLiteral::new("ports"));
+
+            assert_eq!(
+                to_string(&lambda).unwrap(),
+                "{ ports ? [\n  ], name ? \"''${x}\" }:\nports"
+            );
+            assert_eq!(
+                to_string_with_config(&lambda, &Config::nixfmt()).unwrap(),
+                "{\n  ports ? [ ],\n  name ? \"\\${x}\",\n}:\nports\n"
+            );
+        }
+
+        #[test]
+        fn parenthesized_functions_and_arguments() {
+            let identity = NixApply::new(NixLambda::new("x", NixLiteral::new("x")), -1);
+            let nested = NixApply::new(
+                NixLiteral::new("map"),
+                NixLambda::new("x", NixLiteral::new("x + 1")),
+            )
+            .arg(vec![1, 2]);
+
+            assert_eq!(to_string(&identity).unwrap(), "(x: x) (-1)");
+            assert_eq!(to_string(&nested).unwrap(), "map (x: x + 1) [\n  1\n  2\n]");
+        }
+
+        #[test]
+        fn invalid_arguments() {
+            let lambda = NixLambda::new("not valid", 1);
+            let pattern = NixLambda::with_pattern(NixPattern::new().arg("a.b"), 1);
+
+            assert!(to_string(&lambda).is_err());
+            assert!(to_string(&pattern).is_err());
+        }
+    }
+
+    mod sharing {
+        use crate::{
+            Config, NixLambda, NixLet, NixLiteral, NixModule, NixPattern, to_string,
+            to_string_with_config,
+        };
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        #[derive(Serialize, Clone)]
+        struct Tls {
+            enable: bool,
+            certificates: Vec<&'static str>,
+        }
+
+        #[derive(Serialize)]
+        struct Host {
+            name: &'static str,
+            tls: Tls,
+        }
+
+        fn tls() -> Tls {
+            Tls {
+                enable: true,
+                certificates: vec!["a.pem"],
+            }
+        }
+
+        fn hosts() -> Vec<Host> {
+            vec![
+                Host {
+                    name: "a",
+                    tls: tls(),
+                },
+                Host {
+                    name: "b",
+                    tls: tls(),
+                },
+            ]
+        }
+
+        #[test]
+        fn shared_in_module() {
+            let module = NixModule::new(BTreeMap::from([("hosts", hosts())]));
+            let config = Config::new().share_repeated(3);
+
+            let result = to_string_with_config(&module, &config).unwrap();
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{ config, lib, pkgs, ... }:\n",
+                "let\n",
+                "  shared1 = {\n",
+                "    enable = true;\n",
+                "    certificates = [\n",
+                "      \"a.pem\"\n",
+                "    ];\n",
+                "  };\n",
+                "in\n",
+                "{\n",
+                "  hosts = [\n",
+                "    {\n",
+                "      name = \"a\";\n",
+                "      tls = shared1;\n",
+                "    }\n",
+                "    {\n",
+                "      name = \"b\";\n",
+                "      tls = shared1;\n",
+                "    }\n",
+                "  ];\n",
+                "}",
+            );
+
+            assert_eq!(result, expected);
+        }
+
+        #[test]
+        fn outermost_values_are_shared() {
+            // Every certificate list is inside a shared block but one, so it
+            // is not shared itself
+            let mut values = BTreeMap::new();
+            values.insert("a", vec![tls()]);
+            values.insert("b", vec![tls()]);
+            let mut certificates = BTreeMap::new();
+            certificates.insert("c", tls().certificates);
+            let root = (values, certificates);
+
+            let config = Config::new().share_repeated(2);
+            let result = to_string_with_config(&root, &config).unwrap();
+
+            assert!(result.starts_with("let\n  shared1 = [\n    {\n"));
+            assert!(!result.contains("shared2"));
+            assert!(result.contains("a = shared1;"));
+            assert!(result.contains("b = shared1;"));
+            assert!(result.contains("c = [\n"));
+        }
+
+        #[test]
+        fn small_and_scoped_values_are_not_shared() {
+            let lambda = NixLambda::new("x", BTreeMap::from([("a", tls()), ("b", tls())]));
+            let small = BTreeMap::from([("a", vec![1]), ("b", vec![1])]);
+            let config = Config::new().share_repeated(5);
+
+            assert_eq!(
+                to_string_with_config(&lambda, &config).unwrap(),
+                to_string(&lambda).unwrap()
+            );
+            assert_eq!(
+                to_string_with_config(&small, &config).unwrap(),
+                to_string(&small).unwrap()
+            );
+        }
+
+        #[test]
+        fn values_written_alike_are_not_shared() {
+            // The literal is written as the string would be, but it is not
+            // the same value
+            let root = (vec!["a.pem"], vec![NixLiteral::new("\"a.pem\"")]);
+            let config = Config::new().share_repeated(2);
+
+            assert_eq!(
+                to_string_with_config(&root, &config).unwrap(),
+                to_string(&root).unwrap()
+            );
+        }
+
+        #[test]
+        fn shared_names_are_deterministic() {
+            let other = Tls {
+                enable: false,
+                certificates: vec![],
+            };
+            let root = vec![tls(), other.clone(), tls(), other];
+            let config = Config::new().share_repeated(3);
+
+            let first = to_string_with_config(&root, &config).unwrap();
+            let second = to_string_with_config(&root, &config).unwrap();
+
+            assert_eq!(first, second);
+            assert!(first.ends_with("in\n[\n  shared1\n  shared2\n  shared1\n  shared2\n]"));
+        }
+
+        #[test]
+        fn let_expressions() {
+            let bindings = BTreeMap::from([("port", 8080)]);
+            let expr = NixLet::new(bindings, NixLiteral::new("port"));
+            let list = vec![NixLet::new(BTreeMap::<&str, u8>::new(), 1)];
+            let lambda = NixLambda::with_pattern(NixPattern::new().arg("lib"), expr.clone());
+
+            assert_eq!(to_string(&expr).unwrap(), "let\n  port = 8080;\nin\nport");
+            assert_eq!(to_string(&list).unwrap(), "[\n  (let\n  in\n  1)\n]");
+            assert_eq!(
+                to_string_with_config(&lambda, &Config::nixfmt()).unwrap(),
+                "{ lib }:\nlet\n  port = 8080;\nin\nport\n"
+            );
+            assert!(to_string(&NixLet::new(1, 2)).is_err());
+        }
+    }
+
+    mod inherit {
+        use crate::{MkForce, NixCommented, NixInherit, NixLet, NixLiteral, to_string};
+        use ser_nix_derive::NixSerialize;
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        #[derive(Serialize)]
+        struct Args {
+            stdenv: NixInherit,
+            fetchurl: NixInherit,
+            python3: NixInherit,
+            version: &'static str,
+            hello: NixCommented<NixInherit>,
+            cowsay: NixInherit,
+        }
+
+        #[test]
+        fn consecutive_inherits_are_grouped() {
+            let args = Args {
+                stdenv: NixInherit::new(),
+                fetchurl: NixInherit::new(),
+                python3: NixInherit::from_set("pkgs"),
+                version: "1.0",
+                hello: NixCommented::new("Unused", NixInherit::new()),
+                cowsay: NixInherit::new(),
+            };
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  inherit stdenv fetchurl;\n",
+                "  inherit (pkgs) python3;\n",
+                "  version = \"1.0\";\n",
+                "  # Unused\n",
+                "  inherit hello;\n",
+                "  inherit cowsay;\n",
+                "}",
+            );
+
+            assert_eq!(to_string(&args).unwrap(), expected);
+        }
+
+        #[test]
+        fn inherit_in_let_bindings() {
+            let bindings = BTreeMap::from([("hello", NixInherit::from_set("pkgs"))]);
+            let expr = NixLet::new(bindings, NixLiteral::new("hello"));
+
+            assert_eq!(
+                to_string(&expr).unwrap(),
+                "let\n  inherit (pkgs) hello;\nin\nhello"
+            );
+        }
+
+        #[test]
+        fn attribute_paths_select_the_name() {
+            #[derive(NixSerialize)]
+            struct Config {
+                #[nix(attr_path)]
+                services: Services,
+                #[nix(attr_path)]
+                networking: Networking,
+            }
+
+            #[derive(NixSerialize)]
+            struct Services {
+                nginx: NixInherit,
+            }
+
+            #[derive(NixSerialize)]
+            #[nix(rename_all = "camelCase")]
+            struct Networking {
+                host_name: NixInherit,
+            }
+
+            let config = Config {
+                services: Services {
+                    nginx: NixInherit::from_set("cfg.services"),
+                },
+                networking: Networking {
+                    host_name: NixInherit::new(),
+                },
+            };
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  services.nginx = cfg.services.nginx;\n",
+                "  networking.hostName = hostName;\n",
+                "}",
+            );
+
+            assert_eq!(to_string(&config).unwrap(), expected);
+
+            let quoted = BTreeMap::from([("a b", NixInherit::from_set("import ./x.nix"))]);
+            assert_eq!(
+                to_string(&quoted).unwrap(),
+                "{\n  inherit (import ./x.nix) \"a b\";\n}"
+            );
+        }
+
+        #[test]
+        fn inherit_outside_attributes() {
+            let quoted = BTreeMap::from([("a b", NixInherit::new())]);
+            let forced = BTreeMap::from([("a", MkForce(NixInherit::new()))]);
+
+            assert!(to_string(&NixInherit::new()).is_err());
+            assert!(to_string(&vec![NixInherit::new()]).is_err());
+            assert!(to_string(&forced).is_err());
+            assert!(to_string(&quoted).is_err());
+        }
+    }
+
+    mod rec {
+        use crate::{
+            Config, NixApply, NixAttrRef, NixLiteral, NixRec, to_string, to_string_with_config,
+        };
+        use ser_nix_derive::NixSerialize;
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        #[derive(Serialize)]
+        struct Src {
+            url: NixLiteral<'static>,
+            tag: NixAttrRef,
+        }
+
+        #[derive(NixSerialize)]
+        struct Package {
+            version: &'static str,
+            src: NixApply<NixLiteral<'static>, Src>,
+            #[nix(attr_path)]
+            meta: Meta,
+        }
+
+        #[derive(NixSerialize)]
+        struct Meta {
+            tags: Vec<NixAttrRef>,
+        }
+
+        fn package(tag: &str) -> NixRec<Package> {
+            NixRec(Package {
+                version: "1.2",
+                src: NixApply::new(
+                    NixLiteral::new("fetchurl"),
+                    Src {
+                        url: NixLiteral::new("\"https://example.org/${version}.tar.gz\""),
+                        tag: NixAttrRef::new("version"),
+                    },
+                ),
+                meta: Meta {
+                    tags: vec![NixAttrRef::new(tag)],
+                },
+            })
+        }
+
+        #[test]
+        fn references_to_siblings() {
+            #[rustfmt::skip]
+            let expected = concat!(
+                "rec {\n",
+                "  version = \"1.2\";\n",
+                "  src = fetchurl {\n",
+                "    url = \"https://example.org/${version}.tar.gz\";\n",
+                "    tag = version;\n",
+                "  };\n",
+                "  meta.tags = [\n",
+                "    meta\n",
+                "  ];\n",
+                "}",
+            );
+
+            assert_eq!(to_string(&package("meta")).unwrap(), expected);
+        }
+
+        #[test]
+        fn missing_references() {
+            assert!(to_string(&package("pname")).is_err());
+            assert!(to_string(&NixAttrRef::new("version")).is_err());
+            assert!(to_string(&NixRec(vec![1])).is_err());
+
+            let invalid = NixRec(BTreeMap::from([("a b", NixAttrRef::new("a b"))]));
+            assert!(to_string(&invalid).is_err());
+        }
+
+        #[test]
+        fn nested_rec_refers_to_enclosing_attributes() {
+            let mut inner = BTreeMap::new();
+            inner.insert("b", NixAttrRef::new("a"));
+            let mut outer = BTreeMap::new();
+            outer.insert("a", NixRec(BTreeMap::new()));
+            outer.insert("c", NixRec(inner));
+
+            assert_eq!(
+                to_string(&NixRec(outer)).unwrap(),
+                "rec {\n  a = rec {\n  };\n  c = rec {\n    b = a;\n  };\n}"
+            );
+        }
+
+        #[test]
+        fn values_are_not_shared() {
+            let set = NixRec(BTreeMap::from([("a", vec![1, 2]), ("b", vec![1, 2])]));
+            let config = Config::new().share_repeated(2);
+
+            assert_eq!(
+                to_string_with_config(&set, &config).unwrap(),
+                to_string(&set).unwrap()
+            );
+        }
+    }
+
+    mod with {
+        use crate::{
+            Config, NixLambda, NixLet, NixLiteral, NixModule, NixPattern, NixRec, NixWith,
+            as_literal_seq, to_string, to_string_with_config,
+        };
+        use ser_nix_derive::NixSerialize;
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        #[derive(NixSerialize)]
+        struct System {
+            #[nix(attr_path)]
+            environment: Environment,
+        }
+
+        #[derive(Serialize)]
+        struct Environment {
+            #[serde(rename = "systemPackages")]
+            #[serde(serialize_with = "as_literal_seq")]
+            packages: Vec<String>,
+        }
+
+        fn literals(exprs: &[&'static str]) -> Vec<NixLiteral<'static>> {
+            exprs.iter().map(|expr| NixLiteral::new(expr)).collect()
+        }
+
+        #[test]
+        fn with_expressions() {
+            let with = NixWith::new("pkgs", literals(&["git"]));
+
+            assert_eq!(
+                to_string(&vec![&with]).unwrap(),
+                "[\n  (with pkgs; [\n    git\n  ])\n]"
+            );
+            assert!(to_string(&NixWith::new(" ", 1)).is_err());
+        }
+
+        #[test]
+        fn common_prefix() {
+            let system = System {
+                environment: Environment {
+                    packages: vec!["pkgs.git".into(), "pkgs.curl".into(), "pkgs.jq".into()],
+                },
+            };
+            let config = Config::new().with_common_prefix(true);
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  environment.systemPackages = with pkgs; [\n",
+                "    git\n",
+                "    curl\n",
+                "    jq\n",
+                "  ];\n",
+                "}",
+            );
+
+            assert_eq!(to_string_with_config(&system, &config).unwrap(), expected);
+        }
+
+        #[test]
+        fn longest_common_prefix() {
+            let packages = literals(&[
+                "pkgs.python3Packages.numpy",
+                "pkgs.python3Packages.requests",
+            ]);
+            let config = Config::new().with_common_prefix(true);
+
+            assert_eq!(
+                to_string_with_config(&packages, &config).unwrap(),
+                "with pkgs.python3Packages; [\n  numpy\n  requests\n]"
+            );
+        }
+
+        #[test]
+        fn lists_without_common_prefix() {
+            fn unchanged<T: Serialize>(packages: &T) {
+                let config = Config::new().with_common_prefix(true);
+                assert_eq!(
+                    to_string_with_config(packages, &config).unwrap(),
+                    to_string(packages).unwrap()
+                );
+            }
+
+            unchanged(&literals(&["pkgs.git", "unstable.jq"]));
+            unchanged(&literals(&["pkgs.git", "pkgs"]));
+            unchanged(&literals(&["pkgs.git", "(pkgs.jq.override { })"]));
+            unchanged(&literals(&["pkgs.git"]));
+            unchanged(&vec!["pkgs.git", "pkgs.jq"]);
+        }
+
+        #[test]
+        fn derived_literal_lists() {
+            #[derive(NixSerialize)]
+            struct Environment {
+                #[nix(literal)]
+                packages: Vec<String>,
+            }
+
+            let environment = Environment {
+                packages: vec!["pkgs.git".into(), "pkgs.curl".into()],
+            };
+            let config = Config::new().with_common_prefix(true);
+
+            assert_eq!(
+                to_string_with_config(&environment, &config).unwrap(),
+                "{\n  packages = with pkgs; [\n    git\n    curl\n  ];\n}"
+            );
+        }
+
+        fn unchanged<T: Serialize>(value: &T, config: Config) {
+            assert_eq!(
+                to_string_with_config(value, &config.clone().with_common_prefix(true)).unwrap(),
+                to_string_with_config(value, &config).unwrap()
+            );
+        }
+
+        #[test]
+        fn lists_inside_bindings() {
+            let packages = literals(&["pkgs.git", "pkgs.hello"]);
+            let mut bindings = BTreeMap::new();
+            bindings.insert("hello", NixLiteral::new("pkgs.hello"));
+
+            let pattern = NixPattern::new().arg("pkgs").arg("git");
+            unchanged(&NixLambda::with_pattern(pattern, &packages), Config::new());
+            unchanged(&NixLet::new(&bindings, &packages), Config::new());
+            unchanged(&NixRec(BTreeMap::from([("git", &packages)])), Config::new());
+            let module = NixModule::new(BTreeMap::from([("packages", &packages)]));
+            unchanged(&module, Config::new());
+        }
+
+        #[test]
+        fn names_that_with_does_not_shadow() {
+            unchanged(&literals(&["pkgs.git", "pkgs.let"]), Config::new());
+            unchanged(&literals(&["pkgs.git", "pkgs.in"]), Config::new());
+            unchanged(&literals(&["pkgs.git", "pkgs.map"]), Config::new());
+            unchanged(&literals(&["pkgs.true", "pkgs.jq"]), Config::new());
+
+            let packages = literals(&["pkgs.git", "pkgs.shared1"]);
+            unchanged(&packages, Config::new().share_repeated(1));
+        }
+    }
+
+    mod key {
+        use crate::{NixInherit, NixKey, NixLet, to_string};
+        use std::collections::BTreeMap;
+
+        #[test]
+        fn key_kinds() {
+            let mut map = BTreeMap::new();
+            map.insert(NixKey::new("enable"), 1);
+            map.insert(NixKey::new("8080/tcp"), 2);
+            map.insert(NixKey::quoted("name"), 3);
+            map.insert(NixKey::dynamic("user"), 4);
+            map.insert(NixKey::interpolated("${user}-home"), 5);
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  \"8080/tcp\" = 2;\n",
+                "  enable = 1;\n",
+                "  \"name\" = 3;\n",
+                "  ${user} = 4;\n",
+                "  \"${user}-home\" = 5;\n",
+                "}",
+            );
+
+            assert_eq!(to_string(&map).unwrap(), expected);
+        }
+
+        #[test]
+        fn dynamic_keys_where_nix_forbids_them() {
+            let bindings = BTreeMap::from([(NixKey::dynamic("name"), 1)]);
+            let inherited = BTreeMap::from([(NixKey::dynamic("name"), NixInherit::new())]);
+            let empty = BTreeMap::from([(NixKey::dynamic(""), 1)]);
+
+            assert!(to_string(&NixLet::new(bindings, 1)).is_err());
+            assert!(to_string(&inherited).is_err());
+            assert!(to_string(&empty).is_err());
+            assert!(to_string(&NixKey::new("name")).is_err());
+            assert!(to_string(&vec![NixKey::dynamic("name")]).is_err());
+
+            let static_bindings = BTreeMap::from([(NixKey::new("name"), 1)]);
+            assert_eq!(
+                to_string(&NixLet::new(static_bindings, 1)).unwrap(),
+                "let\n  name = 1;\nin\n1"
+            );
+        }
+    }
+
+    mod expr {
+        use crate::expr::{
+            self, BinOp, StringPart, attrs, builtins, ident, interpolate, lambda, let_in, list,
+            path, string, value,
+        };
+        use crate::{Config, NixPattern, to_string, to_string_with_config};
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        #[test]
+        fn operators_are_parenthesized_by_precedence() {
+            let (a, b, c) = (ident("a"), ident("b"), ident("c"));
+
+            let cases = [
+                (a.clone() + b.clone() * c.clone(), "a + b * c"),
+                ((a.clone() + b.clone()) * c.clone(), "(a + b) * c"),
+                (a.clone() - (b.clone() - c.clone()), "a - (b - c)"),
+                ((a.clone() - b.clone()) - c.clone(), "a - b - c"),
+                (a.clone().update(b.clone().update(c.clone())), "a // b // c"),
+                (
+                    a.clone().update(b.clone()).update(c.clone()),
+                    "(a // b) // c",
+                ),
+                (
+                    a.clone()
+                        .binary(BinOp::Eq, b.clone())
+                        .binary(BinOp::Eq, c.clone()),
+                    "(a == b) == c",
+                ),
+                (!(a.clone().binary(BinOp::And, b.clone())), "!(a && b)"),
+                (!a.clone().apply(b.clone()), "!a b"),
+                (-(-a.clone()), "-(-a)"),
+                (a.clone().apply(b.clone()).apply(c.clone()), "a b c"),
+                (a.clone().apply(b.clone().apply(c.clone())), "a (b c)"),
+                (a.clone().apply(b.clone() + c.clone()), "a (b + c)"),
+                (
+                    a.clone().has_attr("x").binary(BinOp::Or, c.clone()),
+                    "a ? x || c",
+                ),
+                (lambda("x", ident("x")).apply(a.clone()), "(x: x) a"),
+                (
+                    a.clone() + expr::if_then_else(b.clone(), c.clone(), a.clone()),
+                    "a + (if b then c else a)",
+                ),
+            ];
+
+            for (expr, expected) in cases {
+                assert_eq!(to_string(&expr).unwrap(), expected);
+            }
+        }
+
+        #[test]
+        fn selections() {
+            let pkgs = ident("pkgs");
+
+            assert_eq!(
+                to_string(&pkgs.clone().select("hello").select("meta")).unwrap(),
+                "pkgs.hello.meta"
+            );
+            assert_eq!(
+                to_string(&pkgs.clone().select("a b").select("if").or(ident("null"))).unwrap(),
+                "pkgs.\"a b\".\"if\" or null"
+            );
+            assert_eq!(
+                to_string(&pkgs.clone().select("a").or(ident("b")).select("c")).unwrap(),
+                "(pkgs.a or b).c"
+            );
+            assert_eq!(
+                to_string(
+                    &builtins("toJSON")
+                        .apply(attrs([("a", value(&1))]))
+                        .select("x")
+                )
+                .unwrap(),
+                "(builtins.toJSON {\n  a = 1;\n}).x"
+            );
+            assert!(to_string(&pkgs.or(ident("b"))).is_err());
+        }
+
+        #[test]
+        fn strings_and_paths() {
+            let expr = interpolate([
+                StringPart::from("\"${x}\" "),
+                ident("pkgs").select("hello").into(),
+                "\n".into(),
+            ]);
+
+            assert_eq!(
+                to_string(&expr).unwrap(),
+                "\"\\\"\\${x}\\\" ${pkgs.hello}\\n\""
+            );
+            assert_eq!(to_string(&string("a\\b")).unwrap(), "\"a\\\\b\"");
+            assert_eq!(
+                to_string(&ident("import").apply(path("./x.nix"))).unwrap(),
+                "import ./x.nix"
+            );
+            assert!(to_string(&ident("if")).is_err());
+            assert!(to_string(&ident("a-b.c")).is_err());
+        }
+
+        #[test]
+        fn scopes_and_containers() {
+            let body = list([
+                ident("git"),
+                ident("lib").select("getBin").apply(ident("curl")),
+                -value(&1),
+                value(&-1),
+                value(&"x"),
+            ]);
+            let expr = lambda_args(let_in(
+                [("inherit", ident("x"))],
+                expr::with(ident("pkgs"), body),
+            ));
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{ pkgs, ... }:\n",
+                "let\n",
+                "  \"inherit\" = x;\n",
+                "in\n",
+                "with pkgs; [\n",
+                "  git\n",
+                "  (lib.getBin curl)\n",
+                "  (-1)\n",
+                "  (-1)\n",
+                "  \"x\"\n",
+                "]",
+            );
+
+            assert_eq!(to_string(&expr).unwrap(), expected);
+        }
+
+        fn lambda_args(body: crate::expr::Expr) -> crate::expr::Expr {
+            expr::lambda_pattern(NixPattern::new().arg("pkgs").ellipsis(true), body)
+        }
+
+        #[test]
+        fn embedded_in_serialized_values() {
+            #[derive(Serialize)]
+            struct Service {
+                enable: crate::expr::Expr,
+                ports: Vec<crate::expr::Expr>,
+            }
+
+            let service = Service {
+                enable: expr::assert(
+                    ident("cfg").select("enable"),
+                    !ident("cfg").select("minimal"),
+                ),
+                ports: vec![
+                    ident("cfg").select("port") + value(&1),
+                    value(&BTreeMap::from([("a", 1)])),
+                ],
+            };
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  enable = assert cfg.enable; !cfg.minimal;\n",
+                "  ports = [\n",
+                "    (cfg.port + 1)\n",
+                "    {\n",
+                "      a = 1;\n",
+                "    }\n",
+                "  ];\n",
+                "}\n",
+            );
+
+            assert_eq!(
+                to_string_with_config(&service, &Config::nixfmt()).unwrap(),
+                expected
+            );
+        }
+    }
+
+    mod attr_select {
+        use crate::{Config, NixAttrSelect, to_string, to_string_with_config};
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        #[test]
+        fn names_are_quoted_when_necessary() {
+            let select = NixAttrSelect::new("pkgs")
+                .attr("python3Packages")
+                .attr("foo.bar")
+                .attr("if")
+                .attr("2to3")
+                .attr("a\"b");
+
+            assert_eq!(
+                to_string(&select).unwrap(),
+                "pkgs.python3Packages.\"foo.bar\".\"if\".\"2to3\".\"a\\\"b\""
+            );
+            assert!(to_string(&NixAttrSelect::new("with")).is_err());
+            assert!(to_string(&NixAttrSelect::new("pkgs").interpolated(" ")).is_err());
+        }
+
+        #[test]
+        fn keywords_are_quoted() {
+            let keywords = [
+                "assert", "else", "if", "in", "inherit", "let", "or", "rec", "then", "with",
+            ];
+            for keyword in keywords {
+                let select = NixAttrSelect::new("cfg").attr(keyword).attr("x");
+                let expected = format!("cfg.\"{keyword}\".x");
+                assert_eq!(to_string(&select).unwrap(), expected);
+                assert_eq!(expected.parse::<NixAttrSelect>().unwrap(), select);
+                assert!(format!("cfg.{keyword}.x").parse::<NixAttrSelect>().is_err());
+
+                let attrs = BTreeMap::from([(keyword, 1)]);
+                let expected = format!("{{\n  \"{keyword}\" = 1;\n}}");
+                assert_eq!(to_string(&attrs).unwrap(), expected);
+            }
+        }
+
+        #[test]
+        fn field_names_are_quoted_when_necessary() {
+            #[derive(Serialize)]
+            struct Ports {
+                #[serde(rename = "a.b")]
+                dotted: u8,
+                #[serde(rename = "8080/tcp")]
+                tcp: u8,
+                #[serde(rename = "if")]
+                keyword: u8,
+                #[serde(rename = "ok-name")]
+                plain: u8,
+            }
+
+            let ports = Ports {
+                dotted: 1,
+                tcp: 2,
+                keyword: 3,
+                plain: 4,
+            };
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  \"a.b\" = 1;\n",
+                "  \"8080/tcp\" = 2;\n",
+                "  \"if\" = 3;\n",
+                "  ok-name = 4;\n",
+                "}",
+            );
+
+            assert_eq!(to_string(&ports).unwrap(), expected);
+        }
+
+        #[test]
+        fn parse_dotted_paths() {
+            let parsed: NixAttrSelect = "inputs.\"foo.bar\".${pkgs.system}.hello".parse().unwrap();
+            let built = NixAttrSelect::new("inputs")
+                .attr("foo.bar")
+                .interpolated("pkgs.system")
+                .attr("hello");
+            assert_eq!(parsed, built);
+            assert_eq!(parsed.root(), "inputs");
+
+            for invalid in [
+                "",
+                "pkgs.",
+                "pkgs..a",
+                "pkgs.\"a",
+                "pkgs.${a",
+                "pkgs.\"${a}\"",
+                "1.a",
+                "a b.c",
+            ] {
+                assert!(invalid.parse::<NixAttrSelect>().is_err(), "{invalid}");
+            }
+        }
+
+        #[test]
+        fn or_default() {
+            let mut default = BTreeMap::new();
+            default.insert("enable", false);
+            let select: NixAttrSelect = "config.services.app".parse().unwrap();
+
+            let mut attrs = BTreeMap::new();
+            attrs.insert("app", select.clone().or(&default));
+            attrs.insert("port", select.clone().attr("port").or(&-1));
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  app = config.services.app or {\n",
+                "    enable = false;\n",
+                "  };\n",
+                "  port = config.services.app.port or (-1);\n",
+                "}",
+            );
+
+            assert_eq!(to_string(&attrs).unwrap(), expected);
+            assert!(to_string(&NixAttrSelect::new("pkgs").or(&1)).is_err());
+        }
+
+        #[test]
+        fn defaults_follow_formatter() {
+            let select = NixAttrSelect::new("cfg").attr("hosts");
+            let attrs = BTreeMap::from([("hosts", select.or(&BTreeMap::from([("a b", "${x}")])))]);
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  hosts = cfg.hosts or {\n",
+                "    \"a b\" = \"\\${x}\";\n",
+                "  };\n",
+                "}\n",
+            );
+
+            assert_eq!(
+                to_string_with_config(&attrs, &Config::nixfmt()).unwrap(),
+                expected
+            );
+        }
+    }
+
+    mod strict_literals {
+        use crate::syntax::{Kind, parse};
+        use crate::{Config, NixLiteral, to_string, to_string_with_config};
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        #[test]
+        fn valid_expressions() {
+            for valid in [
+                "pkgs.hello",
+                "lib.mkForce true",
+                "builtins.currentSystem",
+                "x: x + 1",
+                "{ pkgs, lib ? pkgs.lib, ... }@args: args",
+                "args@{ a, b, }: a",
+                "{ }: null",
+                "let a = 1; inherit (pkgs) git; in a",
+                "rec { a = 1; b = a; inherit a; }",
+                "{ a.b.\"c d\" = 1; ${name} = 2; \"x-${y}\" = 3; }",
+                "with pkgs; [ git curl ]",
+                "if a then b else c",
+                "assert a != null; a",
+                "a.b or c.d",
+                "a ? b.c && !d || e -> f",
+                "-1 - -2 * 3 / 4",
+                "[ 1 2.5 .5 1e3 ./a ~/b <nixpkgs> /c/d https://example.org/a?b=c ]",
+                "\"a ${b} \\\" $${c} $\"",
+                "''\n  a ${b}\n  ''${c} ''' $$ '\n''",
+                "./a/${b}/c",
+                "import ./a.nix { } // { } ++ [ ]",
+                "f (x: x) [ ] { }",
+                "# comment\n1 /* block */",
+                "(1)",
+                "a: b: a b",
+                "x:x",
+            ] {
+                if let Err(e) = parse(valid) {
+                    panic!("`{valid}`: {e}");
+                }
+            }
+        }
+
+        #[test]
+        fn invalid_expressions() {
+            for (invalid, error) in [
+                ("", "expected an expression, found end of input at column 1"),
+                ("pkgs.hello;", "unexpected `;` at column 11"),
+                ("{ a = 1 }", "expected `;`, found `}` at column 9"),
+                ("[ 1 2", "expected `]`, found end of input at column 6"),
+                ("[ -1 ]", "unexpected `-` at column 3"),
+                ("\"a", "unterminated string at column 1"),
+                (
+                    "a == b == c",
+                    "`==` cannot follow `==` without parentheses at column 8",
+                ),
+                (
+                    "let ${a} = 1; in a",
+                    "dynamic attributes are not allowed in `let` at column 5",
+                ),
+                ("./a/", "path has a trailing slash at column 4"),
+                ("1 +\n  ) ", "unexpected `)` at line 2, column 3"),
+                ("/* a", "unterminated comment at column 1"),
+                ("a ^ b", "unexpected character `^` at column 3"),
+                (
+                    "99999999999999999999",
+                    "integer `99999999999999999999` is out of range at column 1",
+                ),
+            ] {
+                assert_eq!(parse(invalid).unwrap_err().to_string(), error, "{invalid}");
+            }
+        }
+
+        #[test]
+        fn data_is_parsed() {
+            let node = parse("{ a = [ (-1) 2.5 \"s${x}\" ]; b.c = ./p; }").unwrap();
+            let Kind::Attrs {
+                rec: false,
+                bindings,
+            } = node.kind
+            else {
+                panic!("{node:?}");
+            };
+            assert_eq!(bindings.len(), 2);
+            assert_eq!(parse("-3").unwrap().kind, Kind::Int(-3));
+            assert_eq!(parse("<nixpkgs>").unwrap().kind, Kind::Path);
+            assert_eq!(parse("1/2").unwrap().kind, Kind::Path);
+            assert_eq!(parse("x:y").unwrap().kind, Kind::Uri);
+            assert_eq!(parse("a b").unwrap().kind, Kind::Other);
+        }
+
+        #[test]
+        fn literals_are_checked() {
+            assert!(NixLiteral::try_new("pkgs.hello").is_ok());
+            assert!(NixLiteral::parse(String::from("{ a = 1; }")).is_ok());
+            assert_eq!(
+                NixLiteral::parse("if a then b").unwrap_err().to_string(),
+                "invalid Nix literal `if a then b`: expected `else`, found end of input at column 12"
+            );
+        }
+
+        #[test]
+        fn deep_nesting_is_rejected() {
+            let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+            assert!(parse(&nested(60)).is_ok());
+
+            let error = "expression is nested too deeply at column 65";
+            let deep = nested(1000);
+            assert_eq!(parse(&deep).unwrap_err().to_string(), error);
+            assert!(NixLiteral::try_new(&deep).is_err());
+            assert!(crate::parse(&deep).unwrap_err().to_string().contains(error));
+            assert!(crate::from_str::<i64>(&deep).is_err());
+
+            for deep in [
+                "!".repeat(1000) + "a",
+                "-".repeat(1000) + "1",
+                "[ ".repeat(1000),
+                "{ a = ".repeat(1000),
+                "a: ".repeat(1000),
+                "a ++ ".repeat(1000) + "a",
+                "a.b or ".repeat(1000) + "a",
+                "\"${".repeat(1000),
+            ] {
+                let message = parse(&deep).unwrap_err().to_string();
+                assert!(
+                    message.starts_with("expression is nested too deeply"),
+                    "{message}"
+                );
+            }
+        }
+
+        #[test]
+        fn errors_name_the_field() {
+            #[derive(Serialize)]
+            struct Service {
+                packages: Vec<NixLiteral<'static>>,
+            }
+
+            let mut services = BTreeMap::new();
+            services.insert(
+                "web",
+                Service {
+                    packages: vec![NixLiteral::new("pkgs.git"), NixLiteral::new("pkgs.")],
+                },
+            );
+
+            let config = Config::new().strict_literals(true);
+            assert_eq!(
+                to_string_with_config(&services, &config)
+                    .unwrap_err()
+                    .to_string(),
+                "invalid Nix literal `pkgs.` in `web.packages`: \
+                 expected an attribute name, found end of input at column 6"
+            );
+            assert!(to_string(&services).is_ok());
+        }
+
+        #[test]
+        fn literal_fields_are_checked() {
+            use ser_nix_derive::NixSerialize;
+
+            #[derive(NixSerialize)]
+            struct Service {
+                #[nix(literal)]
+                block: String,
+            }
+
+            let service = Service {
+                block: "pkgs.hello;".to_string(),
+            };
+            let config = Config::new().strict_literals(true);
+            assert_eq!(
+                to_string_with_config(&service, &config)
+                    .unwrap_err()
+                    .to_string(),
+                "invalid Nix literal `pkgs.hello;` in `block`: unexpected `;` at column 11"
+            );
+            assert_eq!(to_string(&service).unwrap(), "{\n  block = pkgs.hello;;\n}");
+        }
+
+        #[test]
+        fn generated_expressions_are_not_checked() {
+            use crate::expr::ident;
+
+            let sum = ident("a") + ident("b") * ident("c");
+            let config = Config::new().strict_literals(true);
+            assert_eq!(to_string_with_config(&sum, &config).unwrap(), "a + b * c");
+        }
+    }
+
+    mod literal_indentation {
+        use crate::literal::lines_in_string;
+        use crate::{NixLiteral, to_string};
+        use serde::Serialize;
+
+        #[derive(Serialize)]
+        struct Service {
+            config: NixLiteral<'static>,
+        }
+
+        #[derive(Serialize)]
+        struct Services {
+            web: Service,
+        }
+
+        #[test]
+        fn lines_are_reindented() {
+            let services = Services {
+                web: Service {
+                    config: NixLiteral::new(
+                        "lib.mkIf cfg.enable {\n\
+                         \x20       port = 80;\n\
+                         \n\
+                         \x20       hosts = [ ];\n\
+                         \x20     }",
+                    ),
+                },
+            };
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  web = {\n",
+                "    config = lib.mkIf cfg.enable {\n",
+                "      port = 80;\n",
+                "\n",
+                "      hosts = [ ];\n",
+                "    };\n",
+                "  };\n",
+                "}",
+            );
+
+            assert_eq!(to_string(&services).unwrap(), expected);
+        }
+
+        #[test]
+        fn literal_fields_are_reindented() {
+            use ser_nix_derive::NixSerialize;
+
+            #[derive(NixSerialize)]
+            struct Service {
+                #[nix(literal)]
+                config: String,
+            }
+
+            #[derive(NixSerialize)]
+            struct Services {
+                web: Service,
+            }
+
+            let services = Services {
+                web: Service {
+                    config: "lib.mkIf true {\n  a = 1;\n}".to_string(),
+                },
+            };
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  web = {\n",
+                "    config = lib.mkIf true {\n",
+                "      a = 1;\n",
+                "    };\n",
+                "  };\n",
+                "}",
+            );
+
+            assert_eq!(to_string(&services).unwrap(), expected);
+        }
+
+        #[test]
+        fn strings_are_left_alone() {
+            let services = Services {
+                web: Service {
+                    config: NixLiteral::new(
+                        "pkgs.writeText \"a\" ''\n  \
+                         b ${\"\n\"}\n    \
+                         c\n\
+                         ''",
+                    ),
+                },
+            };
+
+            #[rustfmt::skip]
+            let expected = concat!(
+                "{\n",
+                "  web = {\n",
+                "    config = pkgs.writeText \"a\" ''\n",
+                "  b ${\"\n",
+                "\"}\n",
+                "    c\n",
+                "''",
+                ";\n",
+                "  };\n",
+                "}",
+            );
+
+            assert_eq!(to_string(&services).unwrap(), expected);
+        }
+
+        #[test]
+        fn string_lines_are_found() {
+            assert_eq!(lines_in_string("a"), [false]);
+            assert_eq!(
+                lines_in_string("''\n${ {\n}.a }\n'''\n''\n\"\\\n\"\n# ''\n/* ''\n */ x"),
+                [
+                    false, true, false, true, true, false, true, false, false, false
+                ]
+            );
+        }
+    }
+
+    mod parse {
+        use crate::{
+            Config, NixLiteral, NixPathBuf, Value, parse, to_string, to_string_with_config,
+        };
+        use serde::Serialize;
+        use std::collections::BTreeMap;
+
+        fn attrs<const N: usize>(attrs: [(&str, Value); N]) -> Value {
+            Value::Attrs(attrs.into_iter().map(|(k, v)| (k.to_string(), v)).collect())
+        }
+
+        fn literal(expr: &str) -> Value {
+            Value::Literal(NixLiteral::from(expr.to_string()))
+        }
+
+        #[test]
+        fn data() {
+            let value = parse(
+                "{\n\
+                 \x20 a = [ 1 (-2) 2.5 true null \"s\\n\" ./a/b https://example.org ];\n\
+                 \x20 b.c = { d = 1; };\n\
+                 \x20 b.c.e = 2;\n\
+                 \x20 \"f g\" = ''\n\
+                 \x20   x\n\
+                 \x20     y\n\
+                 \x20 '';\n\
+                 }",
+            )
+            .unwrap();
+
+            let expected = attrs([
+                (
+                    "a",
+                    Value::List(vec![
+                        Value::Int(1),
+                        Value::Int(-2),
+                        Value::Float(2.5),
+                        Value::Bool(true),
+                        Value::Null,
+                        Value::String("s\n".into()),
+                        Value::Path(NixPathBuf::new("./a/b")),
+                        Value::String("https://example.org".into()),
+                    ]),
+                ),
+                (
+                    "b",
+                    attrs([("c", attrs([("d", Value::Int(1)), ("e", Value::Int(2))]))]),
+                ),
+                ("f g", Value::String("x\n  y\n".into())),
+            ]);
+
+            assert_eq!(value, expected);
+        }
+
+        #[test]
+        fn expressions_are_kept_as_literals() {
+            let value = parse(
+                "{ a = pkgs.hello; b = \"${x}\"; c = <nixpkgs>; d = x: x; e = { ${n} = 1; }; }",
+            )
+            .unwrap();
+
+            let expected = attrs([
+                ("a", literal("pkgs.hello")),
+                ("b", literal("\"${x}\"")),
+                ("c", literal("<nixpkgs>")),
+                ("d", literal("x: x")),
+                ("e", literal("{ ${n} = 1; }")),
+            ]);
+
+            assert_eq!(value, expected);
+        }
+
+        #[test]
+        fn variables_are_resolved() {
+            let value = parse(
+                "let\n\
+                 \x20 base = { port = 80; };\n\
+                 \x20 host = base.port;\n\
+                 in rec {\n\
+                 \x20 inherit host;\n\
+                 \x20 inherit (base) port;\n\
+                 \x20 url = address;\n\
+                 \x20 address = \"localhost\";\n\
+                 \x20 missing = base.other or null;\n\
+                 \x20 free = pkgs;\n\
+                 }",
+            )
+            .unwrap();
+
+            let expected = attrs([
+                ("host", Value::Int(80)),
+                ("port", Value::Int(80)),
+                ("url", Value::String("localhost".into())),
+                ("address", Value::String("localhost".into())),
+                ("missing", Value::Null),
+                ("free", literal("pkgs")),
+            ]);
+
+            assert_eq!(value, expected);
+        }
+
+        #[test]
+        fn indentation_is_found_before_escapes() {
+            assert_eq!(
+                parse("''\n  a ''\\n b\n''").unwrap(),
+                Value::String("a \n b\n".into())
+            );
+            assert_eq!(
+                parse("''\n  ''\\ a\n   b''").unwrap(),
+                Value::String(" a\n b".into())
+            );
+        }
+
+        #[test]
+        fn strings_are_written_back_unchanged() {
+            let mut written = String::from(
+                "{\n\
+                 \x20 a = ''\n\
+                 \x20   a line long enough for the string to be written as an indented string again\n\
+                 \x20     b ''${c} '''\n\
+                 \n\
+                 \x20   d\n\
+                 \x20 '';\n\
+                 }\n",
+            );
+            let value = parse(&written).unwrap();
+            for _ in 0..3 {
+                let next = to_string_with_config(&parse(&written).unwrap(), &Config::nixfmt());
+                let next = next.unwrap();
+                assert_eq!(parse(&next).unwrap(), value);
+                assert_eq!(next, written);
+                written = next;
+            }
+        }
+
+        #[test]
+        fn errors() {
+            for (invalid, error) in [
+                (
+                    "{ a = 1; a.b = 2; }",
+                    "attribute `a` is already defined at column 10",
+                ),
+                (
+                    "let a = b; b = a; in a",
+                    "infinite recursion in the value of `a` at column 16",
+                ),
+                (
+                    "let a = { }; in a.b",
+                    "attribute `b` is missing at column 19",
+                ),
+                (
+                    "{\n  a = 1\n}",
+                    "expected `;`, found `}` at line 3, column 1",
+                ),
+            ] {
+                assert_eq!(parse(invalid).unwrap_err().to_string(), error, "{invalid}");
+            }
+        }
+
+        #[test]
+        fn serialized_values_are_read_back() {
+            #[derive(Serialize)]
+            struct Service {
+                name: String,
+                ports: Vec<u16>,
+                root: NixPathBuf,
+                package: NixLiteral<'static>,
+            }
+
+            let service = Service {
+                name: "web".into(),
+                ports: vec![80, 443],
+                root: NixPathBuf::new("./www"),
+                package: NixLiteral::new("pkgs.nginx"),
+            };
+            let mut services = BTreeMap::new();
+            services.insert("a", &service);
+            services.insert("b", &service);
+
+            let config = Config::new().share_repeated(1);
+            let written = to_string_with_config(&services, &config).unwrap();
+            let value = parse(&written).unwrap();
+
+            let expected = attrs([
+                ("name", Value::String("web".into())),
+                ("package", literal("pkgs.nginx")),
+                ("ports", Value::List(vec![Value::Int(80), Value::Int(443)])),
+                ("root", Value::Path(NixPathBuf::new("./www"))),
+            ]);
+            assert_eq!(value.get("a"), Some(&expected));
+            assert_eq!(value.get("b"), Some(&expected));
+            assert_eq!(parse(&to_string(&value).unwrap()).unwrap(), value);
+        }
+    }
+
+    mod from_str {
+        use crate::{Config, NixLiteral, NixPathBuf, from_str, to_string_with_config};
+        use serde::{Deserialize, Serialize};
+        use std::collections::{BTreeMap, HashMap};
+        use std::path::PathBuf;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Backend {
+            Local,
+            Remote(String),
+            Mirror { url: String, weight: u8 },
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Settings {
+            name: String,
+            port: u16,
+            ratio: f64,
+            enabled: bool,
+            tag: char,
+            limit: Option<u32>,
+            root: PathBuf,
+            config: NixLiteral<'static>,
+            files: Vec<NixPathBuf>,
+            pair: (i32, String),
+            backends: Vec<Backend>,
+            weights: BTreeMap<String, u8>,
+        }
+
+        /// Writes `value` with `Config::nixfmt`, whose strings, keys and
+        /// variants Nix reads back as they were, and checks that it is read
+        /// back unchanged
+        fn round_trip<T>(value: &T)
+        where
+            T: Serialize + serde::de::DeserializeOwned + PartialEq + std::fmt::Debug,
+        {
+            let written = to_string_with_config(value, &Config::nixfmt()).unwrap();
+            match from_str::<T>(&written) {
+                Ok(read) => assert_eq!(&read, value, "{written}"),
+                Err(e) => panic!("{written}\n{e}"),
+            }
+        }
+
+        /// Strings made of pieces that are escaped or could be taken for
+        /// indentation, long enough for some to be written as `''` strings
+        fn strings() -> impl Iterator<Item = String> {
+            const PIECES: &[&str] = &[
+                "a",
+                " ",
+                "  ",
+                "\n",
+                "\n\n",
+                "'",
+                "''",
+                "$",
+                "$$",
+                "{",
+                "${",
+                "}",
+                "\\",
+                "\"",
+                "\t",
+                "\r",
+                "é",
+                "#",
+                "lorem ipsum dolor sit amet consectetur",
+            ];
+
+            // A xorshift generator, so that failures can be reproduced
+            let mut state = 0x2545_f491_4f6c_dd1d_u64;
+            let mut next = move |bound: usize| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as usize % bound
+            };
+            (0..2000).map(move |_| {
+                let len = next(16);
+                (0..len).map(|_| PIECES[next(PIECES.len())]).collect()
+            })
+        }
+
+        #[test]
+        fn strings_are_read_back() {
+            #[derive(Debug, PartialEq, Serialize, Deserialize)]
+            struct Nested {
+                inner: Vec<String>,
+            }
+
+            for string in strings() {
+                round_trip(&string);
+                round_trip(&Nested {
+                    inner: vec![string],
+                });
+            }
+        }
+
+        #[test]
+        fn maps_with_number_keys_are_read_back() {
+            let mut state = 1u64;
+            for len in 0..20 {
+                let unsigned: BTreeMap<u32, bool> = (0..len)
+                    .map(|i| {
+                        state = state.wrapping_mul(6364136223846793005).wrapping_add(1);
+                        ((state >> 33) as u32, i % 2 == 0)
+                    })
+                    .collect();
+                round_trip(&unsigned);
+
+                let signed: BTreeMap<i64, Vec<u8>> = unsigned
+                    .keys()
+                    .map(|&k| (-i64::from(k), vec![len]))
+                    .collect();
+                round_trip(&signed);
+            }
+
+            let flags: HashMap<bool, String> = [(true, "yes".into()), (false, "no".into())].into();
+            round_trip(&flags);
+        }
+
+        #[test]
+        fn nested_structs_are_read_back() {
+            #[derive(Debug, PartialEq, Serialize, Deserialize)]
+            struct Host {
+                name: String,
+                ports: BTreeMap<u16, String>,
+                motd: String,
+                children: Vec<Host>,
+            }
+
+            let mut strings = strings();
+            let mut host = |children| Host {
+                name: strings.next().unwrap(),
+                ports: [(22, "ssh".into()), (80, strings.next().unwrap())].into(),
+                motd: strings.next().unwrap(),
+                children,
+            };
+            let mut tree = Vec::new();
+            for _ in 0..5 {
+                let leaves = vec![host(Vec::new()), host(Vec::new())];
+                tree = vec![host(leaves), host(tree)];
+                round_trip(&tree);
+            }
+        }
+
+        #[test]
+        fn serialized_structs_are_read_back() {
+            let mut weights = BTreeMap::new();
+            weights.insert("a b".to_string(), 1);
+            let settings = Settings {
+                name: "web \"main\"\n".into(),
+                port: 8080,
+                ratio: 0.5,
+                enabled: true,
+                tag: 'x',
+                limit: None,
+                root: PathBuf::from("/srv/www"),
+                config: NixLiteral::new("lib.mkForce {\n  a = 1;\n}"),
+                files: vec![NixPathBuf::new("./a.nix")],
+                pair: (1, "b".into()),
+                backends: vec![
+                    Backend::Local,
+                    Backend::Remote("https://example.org".into()),
+                    Backend::Mirror {
+                        url: "b".into(),
+                        weight: 2,
+                    },
+                ],
+                weights,
+            };
+
+            round_trip(&settings);
+        }
+
+        #[test]
+        fn numbers_are_read_back() {
+            round_trip(&vec![-1i64, 2, -3]);
+            round_trip(&(-1i64, 2i64));
+            round_trip(&vec![1.0f64, -2.5, 1e20, -1e300, 1e-7, 0.0]);
+            round_trip(&(1e20f64, -0.5f64));
+
+            let written = crate::to_string(&vec![-1i64, 2]).unwrap();
+            assert_eq!(from_str::<Vec<i64>>(&written).unwrap(), vec![-1, 2]);
+            let written = crate::to_string(&1e20f64).unwrap();
+            assert_eq!(from_str::<f64>(&written).unwrap(), 1e20);
+        }
+
+        #[test]
+        fn non_finite_floats_are_rejected() {
+            for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+                let err = crate::to_string(&vec![value]).unwrap_err();
+                assert!(
+                    err.to_string().contains("cannot be written in Nix"),
+                    "{err}"
+                );
+            }
+        }
+
+        #[test]
+        fn paths_with_spaces_are_read_back() {
+            for path in [
+                "./a b.nix",
+                "../a b.nix",
+                "../../a b/c.nix",
+                "/etc/my key",
+                "./a\"b$c.nix",
+            ] {
+                round_trip(&NixPathBuf::new(path));
+                round_trip(&PathBuf::from(path));
+            }
+            let read: NixPathBuf = from_str(r#"./. + "/a b.nix""#).unwrap();
+            assert_eq!(read, NixPathBuf::new("./a b.nix"));
+        }
+
+        #[test]
+        fn let_and_rec_are_resolved() {
+            #[derive(Debug, PartialEq, Deserialize)]
+            struct Host {
+                address: String,
+                url: String,
+            }
+
+            let hosts: HashMap<u32, Host> = from_str(
+                "let domain = \"example.org\"; in {\n\
+                 \x20 \"1\" = rec { address = domain; url = address; };\n\
+                 }",
+            )
+            .unwrap_or_else(|e| panic!("{e}"));
+            assert_eq!(hosts[&1].address, "example.org");
+            assert_eq!(hosts[&1].url, "example.org");
+        }
+
+        #[test]
+        fn literals_accept_any_value() {
+            let literals: Vec<NixLiteral> = from_str("[ 1 \"a\" { b = 2; } (x: x) ]").unwrap();
+            let literals: Vec<&str> = literals.iter().map(NixLiteral::as_str).collect();
+            assert_eq!(literals, ["1", "\"a\"", "{ b = 2; }", "x: x"]);
+        }
+
+        #[test]
+        fn errors_have_positions() {
+            #[derive(Debug, Deserialize)]
+            #[serde(deny_unknown_fields)]
+            #[allow(dead_code)]
+            struct Service {
+                port: u16,
+                name: String,
+            }
+
+            for (invalid, error) in [
+                (
+                    "{\n  port = -1;\n  name = \"a\";\n}",
+                    "invalid value: integer `-1`, expected u16 at line 2, column 10",
+                ),
+                (
+                    "{\n  port = 1;\n  name = pkgs.hello;\n}",
+                    "expected data, found the expression `pkgs.hello` at line 3, column 10",
+                ),
+                (
+                    "{ port = 1; name = \"a\"; other = 2; }",
+                    "unknown field `other`, expected `port` or `name` at column 25",
+                ),
+                ("{ port = 1; }", "missing field `name` at column 1"),
+                (
+                    "1",
+                    "invalid type: integer `1`, expected struct Service at column 1",
+                ),
+                ("{ port = 1 }", "expected `;`, found `}` at column 12"),
+            ] {
+                let e = from_str::<Service>(invalid).unwrap_err();
+                assert_eq!(e.to_string(), error, "{invalid}");
+            }
+
+            let e = from_str::<Vec<Backend>>("[ \"Other\" ]").unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                "unknown variant `Other`, expected one of `Local`, `Remote`, `Mirror` at column 3"
+            );
+            let e = from_str::<NixPathBuf>("1").unwrap_err();
+            assert_eq!(
+                e.to_string(),
+                "invalid type: integer `1`, expected a path at column 1"
+            );
+        }
+    }
+
+    mod from_value {
+        use crate::{NixLiteral, NixPathBuf, Value, from_value, parse};
+        use serde::Deserialize;
+        use std::collections::HashMap;
+        use std::path::PathBuf;
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        enum Backend {
+            Local,
+            Mirror { url: String },
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        #[serde(untagged)]
+        enum Source {
+            Url { url: String, hash: String },
+            Path(NixPathBuf),
+        }
+
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Service {
+            root: PathBuf,
+            source: Source,
+            package: NixLiteral<'static>,
+            backends: Vec<Backend>,
+            ports: HashMap<u16, String>,
+        }
+
+        #[test]
+        fn values_are_read_into_types() {
+            let value = parse(
+                r#"{
+                  root = /srv;
+                  source = ./site;
+                  package = pkgs.nginx;
+                  backends = [ "Local" { mirror.url = "https://a"; } ];
+                  ports."80" = "http";
+                }"#,
+            )
+            .unwrap();
+            let expected = Service {
+                root: PathBuf::from("/srv"),
+                source: Source::Path(NixPathBuf::new("./site")),
+                package: NixLiteral::new("pkgs.nginx"),
+                backends: vec![
+                    Backend::Local,
+                    Backend::Mirror {
+                        url: "https://a".to_string(),
+                    },
+                ],
+                ports: HashMap::from([(80, "http".to_string())]),
+            };
+            assert_eq!(Service::deserialize(&value).unwrap(), expected);
+            assert_eq!(from_value::<Service>(value).unwrap(), expected);
+        }
+
+        #[test]
+        fn untagged_enums_are_read() {
+            let value = parse("{ url = \"https://a\"; hash = \"sha256-x\"; }").unwrap();
+            assert_eq!(
+                Source::deserialize(&value).unwrap(),
+                Source::Url {
+                    url: "https://a".to_string(),
+                    hash: "sha256-x".to_string(),
+                }
+            );
+            assert_eq!(
+                from_value::<Source>(Value::Path(NixPathBuf::new("./a"))).unwrap(),
+                Source::Path(NixPathBuf::new("./a"))
+            );
+        }
+
+        #[test]
+        fn literals_are_only_read_as_expressions() {
+            let value = parse("{ a = f 1; b = [ 1 2 ]; }").unwrap();
+            assert_eq!(
+                from_value::<String>(value.get("a").unwrap().clone())
+                    .unwrap_err()
+                    .to_string(),
+                "expected data, found the expression `f 1`"
+            );
+            assert_eq!(
+                NixLiteral::deserialize(value.get("b").unwrap()).unwrap(),
+                NixLiteral::new("[\n  1\n  2\n]")
+            );
+            assert_eq!(
+                from_value::<NixPathBuf>(Value::Int(1))
+                    .unwrap_err()
+                    .to_string(),
+                "invalid type: integer `1`, expected a path"
+            );
+        }
+    }
 }
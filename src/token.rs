@@ -0,0 +1,135 @@
+use crate::comment::CommentStyle;
+use crate::mode::{self, StringMode};
+use crate::ser::Form;
+use crate::unpack::{ArgKind, KeyKind, Payload};
+
+/// What a newtype struct passed to the serializer by a wrapper of this crate
+/// stands for, recognized by its name
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Token {
+    /// A path, written as is
+    Path,
+    /// A [`NixLiteral`](crate::NixLiteral), written as is once validated
+    Literal,
+    /// Text written as is, which is not a literal of its own
+    Raw,
+    /// An expression already rendered at depth zero
+    Rendered,
+    /// A value written as a string of its Nix source
+    Source,
+    /// A name written after the `lib` of the configuration
+    Lib,
+    /// A value that must be parenthesized unless it is a single term
+    Term,
+    /// An inherited attribute, with the set it is inherited from
+    Inherit,
+    /// A [`NixRec`](crate::NixRec)
+    Rec,
+    /// A [`NixAttrRef`](crate::NixAttrRef)
+    AttrRef,
+    /// A unit struct naming the type of a `None` value
+    Placeholder,
+    /// A struct or map written as attribute paths of the enclosing attrset
+    AttrPath,
+    /// A value whose strings are written in another way
+    Strings(StringMode),
+    /// A wrapper whose contents are unpacked by an
+    /// [`Unpacker`](crate::unpack::Unpacker)
+    Unpack(Payload),
+}
+
+const NAMES: &[(Token, &str)] = &[
+    (Token::Path, "$ser_nix::private::Path"),
+    (Token::Literal, "$ser_nix::private::Literal"),
+    (Token::Raw, "$ser_nix::private::Raw"),
+    (Token::Rendered, "$ser_nix::private::Rendered"),
+    (Token::Source, "$ser_nix::private::Source"),
+    (Token::Lib, "$ser_nix::private::Lib"),
+    (Token::Term, "$ser_nix::private::Term"),
+    (Token::Inherit, "$ser_nix::private::Inherit"),
+    (Token::Rec, "$ser_nix::private::Rec"),
+    (Token::AttrRef, "$ser_nix::private::AttrRef"),
+    (Token::Placeholder, "$ser_nix::private::Placeholder"),
+    (Token::AttrPath, mode::ATTR_PATH),
+    (Token::Strings(StringMode::Path), mode::PATH),
+    (Token::Strings(StringMode::Literal), mode::LITERAL),
+    (Token::Strings(StringMode::Indented), mode::INDENTED),
+    (
+        Token::Unpack(Payload::Comment(CommentStyle::Line)),
+        "$ser_nix::private::Commented::Line",
+    ),
+    (
+        Token::Unpack(Payload::Comment(CommentStyle::Block)),
+        "$ser_nix::private::Commented::Block",
+    ),
+    (Token::Unpack(Payload::Apply), "$ser_nix::private::Apply"),
+    (
+        Token::Unpack(Payload::Pattern { ellipsis: false }),
+        "$ser_nix::private::Pattern",
+    ),
+    (
+        Token::Unpack(Payload::Pattern { ellipsis: true }),
+        "$ser_nix::private::Pattern::Ellipsis",
+    ),
+    (
+        Token::Unpack(Payload::Lambda(ArgKind::Name)),
+        "$ser_nix::private::Lambda::Name",
+    ),
+    (
+        Token::Unpack(Payload::Lambda(ArgKind::Pattern)),
+        "$ser_nix::private::Lambda::Pattern",
+    ),
+    (Token::Unpack(Payload::Let), "$ser_nix::private::Let"),
+    (Token::Unpack(Payload::With), "$ser_nix::private::With"),
+    (
+        Token::Unpack(Payload::Concat(Form::Term)),
+        "$ser_nix::private::Concat::Term",
+    ),
+    (
+        Token::Unpack(Payload::Concat(Form::Apply)),
+        "$ser_nix::private::Concat::Apply",
+    ),
+    (
+        Token::Unpack(Payload::Concat(Form::Open)),
+        "$ser_nix::private::Concat::Open",
+    ),
+    (
+        Token::Unpack(Payload::Key(KeyKind::Static)),
+        "$ser_nix::private::Key::Static",
+    ),
+    (
+        Token::Unpack(Payload::Key(KeyKind::Quoted)),
+        "$ser_nix::private::Key::Quoted",
+    ),
+    (
+        Token::Unpack(Payload::Key(KeyKind::Dynamic)),
+        "$ser_nix::private::Key::Dynamic",
+    ),
+    (
+        Token::Unpack(Payload::Key(KeyKind::Interpolated)),
+        "$ser_nix::private::Key::Interpolated",
+    ),
+    (Token::Unpack(Payload::Module), "$ser_nix::private::Module"),
+];
+
+impl Token {
+    /// Returns the name of the newtype struct standing for the token
+    pub fn name(self) -> &'static str {
+        NAMES
+            .iter()
+            .find(|(token, _)| *token == self)
+            .map(|(_, name)| *name)
+            .expect("every token has a name")
+    }
+
+    /// Returns the token a newtype struct stands for, if it is one
+    pub fn from_name(name: &str) -> Option<Token> {
+        if !name.starts_with("$ser_nix::private::") {
+            return None;
+        }
+        NAMES
+            .iter()
+            .find(|(_, other)| *other == name)
+            .map(|(token, _)| *token)
+    }
+}
@@ -1,9 +1,9 @@
 use super::error::Error;
-use super::ser::Serializer;
+use super::ser::{Serializer, VariantSerializer};
 
 use serde::{Serialize, ser};
 
-impl ser::SerializeTuple for &mut Serializer {
+impl ser::SerializeTuple for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -11,19 +11,16 @@ impl ser::SerializeTuple for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        if !self.output.ends_with('[') {
-            self.output += " ";
-        }
-        value.serialize(&mut **self)
+        self.push_element(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output += " ]";
+        self.write_list();
         Ok(())
     }
 }
 
-impl ser::SerializeTupleStruct for &mut Serializer {
+impl ser::SerializeTupleStruct for &mut Serializer<'_> {
     type Ok = ();
     type Error = Error;
 
@@ -31,19 +28,16 @@ impl ser::SerializeTupleStruct for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        if !self.output.ends_with('[') {
-            self.output += " ";
-        }
-        value.serialize(&mut **self)
+        self.push_element(value)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output += " ]";
+        self.write_list();
         Ok(())
     }
 }
 
-impl ser::SerializeTupleVariant for &mut Serializer {
+impl ser::SerializeTupleVariant for VariantSerializer<'_, '_> {
     type Ok = ();
     type Error = Error;
 
@@ -51,14 +45,18 @@ impl ser::SerializeTupleVariant for &mut Serializer {
     where
         T: ?Sized + Serialize,
     {
-        if !self.output.ends_with('[') {
-            self.output += " ";
+        match self.expanded {
+            true => self.inner.push_element(value),
+            false => self.push_inline(None, value),
         }
-        value.serialize(&mut **self)
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
-        self.output += " ] }";
+    fn end(mut self) -> Result<Self::Ok, Self::Error> {
+        match self.expanded {
+            true => self.inner.write_list(),
+            false => self.inner.output = format!("[{} ]", self.inner.output),
+        }
+        VariantSerializer::end(self);
         Ok(())
     }
 }
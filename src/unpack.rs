@@ -0,0 +1,597 @@
+use crate::apply::needs_parens;
+use crate::comment::{Comment, CommentStyle};
+use crate::error::Error;
+use crate::map::is_nix_identifier;
+use crate::path::RawEmitter;
+use crate::ser::{Form, Outcome, Role, Serializer as NixSerializer};
+use serde::{Serialize, ser};
+
+/// The private wrappers whose contents are written as a tuple of strings
+/// followed by values. What varies between wrappers of the same kind is part
+/// of the [`Token`](crate::token::Token) they are recognized by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Payload {
+    /// `(text, value)` of a [`NixCommented`](crate::NixCommented)
+    Comment(CommentStyle),
+    /// `(function, argument)` of a function application, where the function
+    /// is serialized like any other value
+    Apply,
+    /// `(name, default, ...)` of a [`NixPattern`](crate::NixPattern), with an
+    /// empty [`Raw`](crate::literal::Raw) for a missing default
+    Pattern { ellipsis: bool },
+    /// `(argument, body)` of a [`NixLambda`](crate::NixLambda)
+    Lambda(ArgKind),
+    /// `(bindings, body)` of a [`NixLet`](crate::NixLet)
+    Let,
+    /// `(scope, body)` of a [`NixWith`](crate::NixWith)
+    With,
+    /// `(part, ...)` of an [`Expr`](crate::expr::Expr), whose parts are
+    /// written one after the other
+    Concat(Form),
+    /// `(text,)` of a [`NixKey`](crate::NixKey)
+    Key(KeyKind),
+    /// `(args, imports, body)` of a [`NixModule`](crate::NixModule), without
+    /// `imports` when there are none
+    Module,
+}
+
+/// The argument of a [`NixLambda`](crate::NixLambda)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ArgKind {
+    Name,
+    /// A [`NixPattern`](crate::NixPattern), after which the body starts on
+    /// the next line
+    Pattern,
+}
+
+/// How a [`NixKey`](crate::NixKey) is written
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum KeyKind {
+    Static,
+    Quoted,
+    Dynamic,
+    Interpolated,
+}
+
+impl Payload {
+    /// Number of strings preceding the values
+    fn parts(self) -> usize {
+        match self {
+            Payload::Comment(_) | Payload::Key(_) | Payload::Apply => 1,
+            Payload::Pattern { .. } | Payload::Lambda(_) | Payload::Concat(_) => 0,
+            Payload::Let | Payload::With | Payload::Module => 0,
+        }
+    }
+}
+
+/// Internal serializer that unpacks the tuple inside a private wrapper,
+/// writing its values to `parent`.
+pub(crate) struct Unpacker<'s, 'a> {
+    parent: &'s mut NixSerializer<'a>,
+    payload: Payload,
+    parts: Vec<String>,
+    /// Length of the tuple
+    len: usize,
+    /// Number of values written after the parts
+    values: usize,
+    /// Whether the values are inside a scope, where they are not shared
+    scoped: bool,
+    /// Whether `parent` was inside bindings written by the serializer before
+    /// the wrapper bound names of its own
+    outer_bound: Option<bool>,
+}
+
+impl<'s, 'a> Unpacker<'s, 'a> {
+    pub fn new(parent: &'s mut NixSerializer<'a>, payload: Payload) -> Self {
+        Unpacker {
+            parent,
+            payload,
+            parts: Vec::new(),
+            len: 0,
+            values: 0,
+            scoped: false,
+            outer_bound: None,
+        }
+    }
+
+    /// Marks the values written from now on as inside the names bound by the
+    /// wrapper, which `with` does not shadow
+    fn bind(&mut self) {
+        self.outer_bound.get_or_insert(self.parent.env.scoped);
+        self.parent.env.scoped = true;
+    }
+
+    /// Stops sharing the values written until the end of the wrapper
+    fn enter_scope(&mut self) {
+        if let Some(sharing) = self.parent.env.sharing {
+            sharing.borrow_mut().enter_scope();
+            self.scoped = true;
+        }
+    }
+
+    /// Writes the bindings or the body of a `let`
+    fn let_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.values > 0 {
+            let parent = &mut *self.parent;
+            value.serialize(&mut *parent)?;
+            parent.outcome = Outcome::Written;
+            parent.form = Form::Open;
+            return Ok(());
+        }
+
+        self.enter_scope();
+        self.bind();
+        let mut bindings = self.parent.child();
+        bindings.attr_path = true;
+        value.serialize(&mut bindings)?;
+        let bindings = bindings.spliced.ok_or_else(|| {
+            ser::Error::custom("the bindings of a let expression must be an attribute set")
+        })?;
+        if let Some(attr) = bindings.iter().find(|attr| attr.dynamic) {
+            return Err(ser::Error::custom(format!(
+                "the binding `{}` of a let expression cannot be a dynamic attribute",
+                attr.key
+            )));
+        }
+        self.parent.write_let(&bindings);
+        Ok(())
+    }
+
+    /// Writes the scope or the body of a `with`
+    fn with_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.values > 0 {
+            let parent = &mut *self.parent;
+            value.serialize(&mut *parent)?;
+            parent.outcome = Outcome::Written;
+            parent.form = Form::Open;
+            return Ok(());
+        }
+
+        let mut scope = self.parent.nested();
+        value.serialize(&mut scope)?;
+        if scope.output.trim().is_empty() {
+            return Err(ser::Error::custom(
+                "the scope of a with expression is empty",
+            ));
+        }
+        self.enter_scope();
+        let parent = &mut *self.parent;
+        parent.output += "with ";
+        parent.output += &scope.output;
+        parent.output += "; ";
+        Ok(())
+    }
+
+    /// Writes the argument or the body of a lambda
+    fn lambda_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let parent = &mut *self.parent;
+        if self.values > 0 {
+            value.serialize(&mut *parent)?;
+            // The function is written even if its body would be left out
+            parent.outcome = Outcome::Written;
+            parent.form = Form::Open;
+            return Ok(());
+        }
+
+        self.enter_scope();
+        let parent = &mut *self.parent;
+        let mut arg = parent.nested();
+        value.serialize(&mut arg)?;
+        let pattern = self.payload == Payload::Lambda(ArgKind::Pattern);
+        if !pattern && !is_nix_identifier(&arg.output) {
+            return Err(ser::Error::custom(format!(
+                "invalid function argument `{}`",
+                arg.output
+            )));
+        }
+        parent.output += &arg.output;
+        parent.output.push(':');
+        match pattern {
+            // The body of a function taking a pattern starts on the next line
+            true => {
+                parent.output.push('\n');
+                let depth = parent.indent_depth;
+                parent.formatter().write_indent(&mut parent.output, depth);
+            }
+            false => parent.output.push(' '),
+        }
+        self.bind();
+        Ok(())
+    }
+
+    /// Records the next name or default of a pattern
+    fn pattern_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.values.is_multiple_of(2) {
+            let mut name = String::new();
+            value.serialize(RawEmitter { output: &mut name })?;
+            self.parts.push(name);
+            return Ok(());
+        }
+
+        // Defaults are written one level deeper than the pattern when it
+        // spans several lines, and cannot refer to the bindings of the body
+        if !self.scoped {
+            self.enter_scope();
+        }
+        let mut default = self.parent.child();
+        value.serialize(&mut default)?;
+        self.parts.push(default.output);
+        Ok(())
+    }
+
+    /// Writes the next value of a module
+    fn module_value<T>(&mut self, value: &T) -> Result<(), Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        let parent = &mut *self.parent;
+        match self.values {
+            0 => {
+                let root = parent.env.sharing.is_some_and(|s| s.borrow().is_root());
+                value.serialize(&mut *parent)?;
+                parent.output.push(':');
+                parent.output.push('\n');
+                let depth = parent.indent_depth;
+                parent.formatter().write_indent(&mut parent.output, depth);
+                match root {
+                    // Repeated values are bound inside the module, where
+                    // its arguments are in scope
+                    true => parent.write_shared_bindings(),
+                    false => self.enter_scope(),
+                }
+                self.bind();
+                Ok(())
+            }
+            1 if self.len == 3 => parent.push_attr("imports".to_string(), value),
+            _ => {
+                let mut body = parent.child();
+                body.attr_path = true;
+                value.serialize(&mut body)?;
+                let entries = body.spliced.ok_or_else(|| {
+                    ser::Error::custom("the body of a module must be an attribute set")
+                })?;
+                if self.len == 3 && entries.iter().any(|attr| attr.key == "imports") {
+                    return Err(ser::Error::custom(
+                        "the body of a module with imports cannot have its own `imports`",
+                    ));
+                }
+                parent.entries.extend(entries);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn expected() -> Error {
+    ser::Error::custom("expected wrapped value")
+}
+
+impl ser::SerializeTuple for Unpacker<'_, '_> {
+    type Ok = ();
+    type Error = Error;
+
+    fn serialize_element<T>(&mut self, value: &T) -> Result<(), Self::Error>
+    where
+        T: ?Sized + Serialize,
+    {
+        if self.payload == Payload::Apply && self.parts.is_empty() {
+            let mut function = self.parent.nested();
+            value.serialize(&mut function)?;
+            // Applications are left-associative, anything else that is not a
+            // single term must be parenthesized
+            let function = match function.form == Form::Apply || !needs_parens(&function.output) {
+                true => function.output,
+                false => format!("({})", function.output),
+            };
+            self.parts.push(function);
+            return Ok(());
+        }
+        if self.parts.len() < self.payload.parts() {
+            let mut raw = String::new();
+            value.serialize(RawEmitter { output: &mut raw })?;
+            self.parts.push(raw);
+            return Ok(());
+        }
+
+        let result = match self.payload {
+            Payload::Comment(_) => value.serialize(&mut *self.parent),
+            Payload::Apply => {
+                let mut arg = self.parent.nested();
+                arg.strings = self.parent.strings;
+                value.serialize(&mut arg)?;
+
+                self.parent.output += &self.parts[0];
+                self.parent.output.push(' ');
+                self.parent.write_term(&arg.output);
+                self.parent.form = Form::Apply;
+                Ok(())
+            }
+            Payload::Lambda(_) => self.lambda_value(value),
+            Payload::Let => self.let_value(value),
+            Payload::With => self.with_value(value),
+            Payload::Concat(_) => {
+                let mut part = self.parent.nested();
+                part.strings = self.parent.strings;
+                value.serialize(&mut part)?;
+                self.parent.output += &part.output;
+                Ok(())
+            }
+            Payload::Module => self.module_value(value),
+            Payload::Pattern { .. } => self.pattern_value(value),
+            Payload::Key(_) => unreachable!("only contains strings"),
+        };
+        self.values += 1;
+        result
+    }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        if let Some(scoped) = self.outer_bound {
+            self.parent.env.scoped = scoped;
+        }
+        if self.scoped
+            && let Some(sharing) = self.parent.env.sharing
+        {
+            sharing.borrow_mut().exit_scope();
+        }
+        match self.payload {
+            Payload::Comment(style) => {
+                let Some(text) = self.parts.into_iter().next() else {
+                    return Err(expected());
+                };
+                if style == CommentStyle::Block && text.contains("*/") {
+                    return Err(ser::Error::custom("block comments cannot contain `*/`"));
+                }
+                // Comments are written above the attribute, anywhere else
+                // they would be lost
+                if self.parent.role != Role::AttrValue {
+                    return Err(ser::Error::custom(
+                        "comments are only supported on attributes",
+                    ));
+                }
+                self.parent.comment = Some(Comment { text, style });
+            }
+            Payload::Apply if self.values == 0 => return Err(expected()),
+            Payload::Apply => {}
+            Payload::Pattern { ellipsis } => {
+                let args = &self.parts;
+                if !args.len().is_multiple_of(2) {
+                    return Err(expected());
+                }
+                if let Some(name) = args.iter().step_by(2).find(|n| !is_nix_identifier(n)) {
+                    return Err(ser::Error::custom(format!(
+                        "invalid function argument `{name}`"
+                    )));
+                }
+                let args: Vec<(&str, Option<&str>)> = args
+                    .chunks(2)
+                    .map(|pair| {
+                        let default = Some(pair[1].as_str()).filter(|d| !d.is_empty());
+                        (pair[0].as_str(), default)
+                    })
+                    .collect();
+                let depth = self.parent.indent_depth;
+                let formatter = self.parent.formatter();
+                formatter.write_pattern(&mut self.parent.output, &args, ellipsis, depth);
+            }
+            Payload::Key(kind) => {
+                let [text] = &self.parts[..] else {
+                    return Err(expected());
+                };
+                let parent = &mut *self.parent;
+                if parent.role != Role::MapKey {
+                    return Err(ser::Error::custom(format!(
+                        "`{text}` can only be used as the key of a map"
+                    )));
+                }
+                let formatter = parent.formatter();
+                match kind {
+                    KeyKind::Static if is_nix_identifier(text) => parent.output += text,
+                    KeyKind::Static | KeyKind::Quoted => {
+                        formatter.write_attr_name(&mut parent.output, text)
+                    }
+                    _ if text.trim().is_empty() => {
+                        return Err(ser::Error::custom(
+                            "dynamic attribute without an expression",
+                        ));
+                    }
+                    KeyKind::Dynamic => {
+                        parent.output += "${";
+                        parent.output += text;
+                        parent.output.push('}');
+                    }
+                    KeyKind::Interpolated => {
+                        parent.output.push('"');
+                        parent.output += text;
+                        parent.output.push('"');
+                    }
+                }
+                parent.key_kind = Some(kind);
+            }
+            Payload::Lambda(_) | Payload::Let | Payload::With | Payload::Module
+                if self.values < 2 =>
+            {
+                return Err(expected());
+            }
+            Payload::Lambda(_) | Payload::Let | Payload::With => {}
+            Payload::Concat(Form::Term) => {}
+            Payload::Concat(form) => self.parent.form = form,
+            Payload::Module => {
+                self.parent.write_attrset();
+                self.parent.outcome = Outcome::Written;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ser::Serializer for Unpacker<'_, '_> {
+    type Ok = ();
+    type Error = Error;
+    type SerializeSeq = ser::Impossible<(), Error>;
+    type SerializeTuple = Self;
+    type SerializeTupleStruct = ser::Impossible<(), Error>;
+    type SerializeTupleVariant = ser::Impossible<(), Error>;
+    type SerializeMap = ser::Impossible<(), Error>;
+    type SerializeStruct = ser::Impossible<(), Error>;
+    type SerializeStructVariant = ser::Impossible<(), Error>;
+
+    fn serialize_tuple(mut self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.len = len;
+        Ok(self)
+    }
+
+    fn serialize_bool(self, _v: bool) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_i8(self, _v: i8) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_i16(self, _v: i16) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_i32(self, _v: i32) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_i64(self, _v: i64) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_u8(self, _v: u8) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_u16(self, _v: u16) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_u32(self, _v: u32) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_u64(self, _v: u64) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_f32(self, _v: f32) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_f64(self, _v: f64) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_char(self, _v: char) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_str(self, _v: &str) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_bytes(self, _v: &[u8]) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_some<T: ?Sized + Serialize>(self, _value: &T) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+    ) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_newtype_struct<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_newtype_variant<T: ?Sized + Serialize>(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<Self::Ok, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_seq(self, _len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeTupleVariant, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_map(self, _len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_struct(
+        self,
+        _name: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStruct, Self::Error> {
+        Err(expected())
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        _variant_index: u32,
+        _variant: &'static str,
+        _len: usize,
+    ) -> Result<Self::SerializeStructVariant, Self::Error> {
+        Err(expected())
+    }
+}
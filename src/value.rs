@@ -0,0 +1,170 @@
+use crate::data::{Data, DataKind};
+use crate::error::Error;
+use crate::literal::NixLiteral;
+use crate::path::NixPathBuf;
+use serde::ser::Error as _;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeMap;
+
+/// A Nix value, as read by [`parse`].
+///
+/// Expressions that are not data, such as function applications or
+/// variables, are kept as [`NixLiteral`]s, so a value serializes back to the
+/// Nix it was read from. A value can be read into Rust types with
+/// [`from_value`](crate::from_value).
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{parse, to_string, Value};
+///
+/// let mut config = parse("{ port = 80; package = pkgs.nginx; }").unwrap();
+/// if let Value::Attrs(attrs) = &mut config {
+///     attrs.insert("port".to_string(), Value::Int(8080));
+/// }
+///
+/// assert_eq!(
+///     to_string(&config).unwrap(),
+///     "{\n  package = pkgs.nginx;\n  port = 8080;\n}"
+/// );
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Path(NixPathBuf),
+    List(Vec<Value>),
+    Attrs(BTreeMap<String, Value>),
+    /// An expression that is not data, written as it was read
+    Literal(NixLiteral<'static>),
+}
+
+impl Value {
+    /// Returns the attribute `name` if the value is an attrset that has it.
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Attrs(attrs) => attrs.get(name),
+            _ => None,
+        }
+    }
+
+    /// Returns whether the value is `null`.
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    /// Returns the value if it is a boolean.
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Value::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is an integer.
+    pub fn as_i64(&self) -> Option<i64> {
+        match self {
+            Value::Int(i) => Some(*i),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a number, converting integers.
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Int(i) => Some(*i as f64),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+
+    /// Returns the value if it is a string.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+impl From<Data> for Value {
+    fn from(data: Data) -> Self {
+        match data.kind {
+            DataKind::Null => Value::Null,
+            DataKind::Bool(b) => Value::Bool(b),
+            DataKind::Int(i) => Value::Int(i),
+            DataKind::Float(f) => Value::Float(f),
+            DataKind::String(s) => Value::String(s),
+            DataKind::Path(path) => Value::Path(NixPathBuf::new(path)),
+            DataKind::List(elements) => {
+                Value::List(elements.into_iter().map(Value::from).collect())
+            }
+            DataKind::Attrs(attrs) => Value::Attrs(
+                attrs
+                    .into_iter()
+                    .map(|attr| (attr.name, Value::from(attr.value)))
+                    .collect(),
+            ),
+            DataKind::Literal(expr) => Value::Literal(NixLiteral::from(expr)),
+        }
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            Value::Null => serializer.serialize_unit(),
+            Value::Bool(b) => serializer.serialize_bool(*b),
+            Value::Int(i) => serializer.serialize_i64(*i),
+            Value::Float(f) => serializer.serialize_f64(*f),
+            Value::String(s) => serializer.serialize_str(s),
+            Value::Path(path) => path.serialize(serializer),
+            Value::List(elements) => elements.serialize(serializer),
+            Value::Attrs(attrs) => attrs.serialize(serializer),
+            Value::Literal(expr) => expr.serialize(serializer),
+        }
+    }
+}
+
+/// Parses Nix text into a [`Value`].
+///
+/// Attrsets, lists, strings, numbers, booleans, `null` and paths are read as
+/// data. The variables bound by `let` and recursive attrsets are replaced
+/// with their values, and any other expression is kept as a
+/// [`Value::Literal`].
+///
+/// # Errors
+///
+/// Fails with the line and column of the mistake if `s` is not a single
+/// complete Nix expression, if an attribute is defined twice or if a value
+/// refers to itself.
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{parse, NixLiteral, Value};
+///
+/// let value = parse("let port = 80; in { inherit port; hosts = [ \"a\" ]; }").unwrap();
+/// assert_eq!(value.get("port"), Some(&Value::Int(80)));
+///
+/// let value = parse("{ package = pkgs.hello; }").unwrap();
+/// assert_eq!(
+///     value.get("package"),
+///     Some(&Value::Literal(NixLiteral::new("pkgs.hello")))
+/// );
+///
+/// let error = parse("{ a = 1; a = 2; }").unwrap_err();
+/// assert_eq!(error.to_string(), "attribute `a` is already defined at column 10");
+/// ```
+pub fn parse(s: &str) -> Result<Value, Error> {
+    let node = crate::syntax::parse(s).map_err(Error::custom)?;
+    let data = crate::data::read(s, &node).map_err(Error::custom)?;
+    Ok(Value::from(data))
+}
@@ -0,0 +1,124 @@
+use crate::literal::NixLiteral;
+use crate::map::is_nix_identifier;
+use crate::token::Token;
+use crate::unpack::Payload;
+use serde::{Serialize, Serializer};
+
+/// A `with` expression, bringing the attributes of `scope` into scope in its
+/// body: `with scope; body`.
+///
+/// The scope is written as is, such as `pkgs` or `pkgs.python3Packages`.
+/// Variables bound around the expression, such as the arguments of a
+/// function, take precedence over the attributes of the scope.
+///
+/// # Example
+///
+/// ```
+/// use ser_nix::{to_string, NixLiteral, NixWith};
+///
+/// let packages = NixWith::new("pkgs", vec![NixLiteral::new("git"), NixLiteral::new("curl")]);
+///
+/// let result = to_string(&packages).unwrap();
+/// assert_eq!(result, "with pkgs; [\n  git\n  curl\n]");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct NixWith<T> {
+    pub scope: String,
+    pub body: T,
+}
+
+impl<T> NixWith<T> {
+    /// Brings the attributes of `scope` into scope in `body`.
+    pub fn new(scope: impl Into<String>, body: T) -> Self {
+        NixWith {
+            scope: scope.into(),
+            body,
+        }
+    }
+}
+
+impl<T: Serialize> Serialize for NixWith<T> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let scope = NixLiteral::new(&self.scope);
+        serializer
+            .serialize_newtype_struct(Token::Unpack(Payload::With).name(), &(scope, &self.body))
+    }
+}
+
+/// Names in scope everywhere, which `with` does not shadow
+const GLOBALS: &[&str] = &[
+    "abort",
+    "baseNameOf",
+    "break",
+    "builtins",
+    "derivation",
+    "derivationStrict",
+    "dirOf",
+    "false",
+    "fetchGit",
+    "fetchMercurial",
+    "fetchTarball",
+    "fetchTree",
+    "fromTOML",
+    "import",
+    "isNull",
+    "map",
+    "null",
+    "placeholder",
+    "removeAttrs",
+    "scopedImport",
+    "throw",
+    "toString",
+    "true",
+];
+
+/// Finds the longest attribute path, such as `pkgs`, that every element
+/// selects an attribute from, and removes it from the elements.
+///
+/// Returns `None`, leaving the elements unchanged, unless there are at least
+/// two elements, all of them are attribute paths of identifiers, and none is
+/// left starting with a name that `with` would not bring into scope: a
+/// global such as `map`, or a name for which `is_bound` is true.
+pub(crate) fn strip_common_scope(
+    elements: &mut [String],
+    is_bound: impl Fn(&str) -> bool,
+) -> Option<String> {
+    if elements.len() < 2 {
+        return None;
+    }
+
+    let paths: Vec<Vec<&str>> = elements
+        .iter()
+        .map(|element| element.split('.').collect())
+        .collect();
+    if paths.iter().flatten().any(|name| !is_nix_identifier(name)) {
+        return None;
+    }
+
+    // Every element keeps at least its last name
+    let mut len = paths.iter().map(|path| path.len() - 1).min()?;
+    for path in &paths[1..] {
+        len = path
+            .iter()
+            .zip(&paths[0])
+            .take(len)
+            .take_while(|(a, b)| a == b)
+            .count();
+    }
+    if len == 0 {
+        return None;
+    }
+    let shadowed = |name: &&str| GLOBALS.contains(name) || is_bound(name);
+    if paths.iter().map(|path| &path[len]).any(shadowed) {
+        return None;
+    }
+
+    let scope = paths[0][..len].join(".");
+    for element in elements.iter_mut() {
+        element.replace_range(..scope.len() + 1, "");
+    }
+    Some(scope)
+}
@@ -0,0 +1,23 @@
+{
+  name = "alpha";
+  enable = true;
+  port = 8080;
+  address = [
+    10
+    0
+    0
+    1
+  ];
+  tags = [
+    "web"
+    "db"
+  ];
+  owners = [ "ops" ];
+  aliases = [ ];
+  extra = { };
+  motd = ''
+    Welcome to alpha, the primary web and database host of the cluster.
+    Please report problems to the operations team.'';
+  config = ./hosts/alpha.nix;
+  parent = null;
+}
@@ -0,0 +1,18 @@
+{
+  local = "Local";
+  remote = {
+    remote = "ssh://backup";
+  };
+  mirror = {
+    mirror = [
+      "mirror.example.org"
+      873
+    ];
+  };
+  bucket = {
+    bucket = {
+      name = "archive";
+      region = "eu-west-1";
+    };
+  };
+}